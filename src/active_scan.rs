@@ -0,0 +1,708 @@
+use crate::{
+    rudy_tui::Req,
+    scanner::{self, Issue, Severity},
+    settings::{ActiveScanSettings, Settings},
+    upstream::{self, UpstreamConnector},
+};
+use hudsucker::hyper::{Client, Uri, Version};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use time::OffsetDateTime;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    sync::{mpsc::Sender, Notify, Semaphore},
+    time::{interval, MissedTickBehavior},
+};
+use tracing::warn;
+
+/// Host the open redirect probes point to.
+const REDIRECT_HOST: &str = "rudy-redirect.example";
+
+/// Request headers which are not copied to probes. Bodies are sent uncompressed so they can be
+/// checked, and hyper sets the framing headers itself.
+const SKIPPED_HEADERS: [&str; 8] = [
+    "accept-encoding",
+    "connection",
+    "content-length",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// What a probe checks for in its response.
+#[derive(Clone)]
+enum Check {
+    SqlError,
+    SqlTiming,
+    /// Cross-site scripting with the name of the injected tag.
+    Xss(String),
+    /// Path traversal with text of the file the payload points to.
+    Traversal(&'static str),
+    Redirect,
+    /// Server-side request forgery, found by the callback listener.
+    Ssrf,
+    /// Injection of a response header with the given value.
+    HeaderInjection(String),
+    /// Reflection of the given X-Forwarded-Host header.
+    ForwardedHost(String),
+}
+
+/// The scanned exchange, which the responses to the probes are compared with.
+struct Original {
+    id: usize,
+    body: String,
+    duration: Option<Duration>,
+}
+
+/// A copy of a request with a payload in one of its parameters or headers.
+#[derive(Clone)]
+struct Probe {
+    check: Check,
+    /// Parameter or header the payload is in.
+    target: String,
+    payload: String,
+    req: Req,
+    original: Arc<Original>,
+}
+
+impl Probe {
+    /// Checks the response to the probe, returning the issue it shows.
+    fn evaluate(&self, res: &Req, sleep: Duration) -> Option<Issue> {
+        res.status?;
+        let body = scanner::response_body(res);
+        let body = String::from_utf8_lossy(&body);
+        let location = scanner::header(&res.res_headers, "location").unwrap_or_default();
+        let payload = self.payload.replace('\r', "\\r").replace('\n', "\\n");
+        let sent = format!("{}={}", self.target, payload);
+
+        match &self.check {
+            Check::SqlError => {
+                let error = scanner::sql_error(&body)?;
+                if scanner::sql_error(&self.original.body).is_some() {
+                    return None;
+                }
+                Some(Issue::new(
+                    Severity::High,
+                    "SQL injection (error based)",
+                    format!("{} caused \"{}\"", sent, error),
+                ))
+            }
+            Check::SqlTiming => {
+                let took = res.duration?;
+                let before = self.original.duration?;
+                if took < sleep || before >= sleep / 2 {
+                    return None;
+                }
+                Some(Issue::new(
+                    Severity::High,
+                    "SQL injection (time based)",
+                    format!(
+                        "{} took {:.1}s, the original request {:.1}s",
+                        sent,
+                        took.as_secs_f64(),
+                        before.as_secs_f64()
+                    ),
+                ))
+            }
+            Check::Xss(tag) => {
+                let html = res.mime_type().is_none_or(|m| m == "text/html");
+                if !html || !body.contains(&format!("<{}>", tag)) {
+                    return None;
+                }
+                Some(Issue::new(
+                    Severity::High,
+                    "Cross-site scripting (reflected)",
+                    format!("{} was reflected without encoding", sent),
+                ))
+            }
+            Check::Traversal(text) => {
+                if !body.contains(text) || self.original.body.contains(text) {
+                    return None;
+                }
+                Some(Issue::new(
+                    Severity::High,
+                    "Path traversal",
+                    format!("{} returned \"{}\"", sent, text),
+                ))
+            }
+            Check::Redirect => {
+                let external = [
+                    format!("https://{}", REDIRECT_HOST),
+                    format!("//{}", REDIRECT_HOST),
+                ];
+                let redirect = matches!(res.status, Some(300..=399))
+                    && external.iter().any(|e| location.starts_with(e.as_str()));
+                redirect.then(|| {
+                    Issue::new(
+                        Severity::Medium,
+                        "Open redirect",
+                        format!("{} redirected to {}", sent, location),
+                    )
+                })
+            }
+            Check::Ssrf => None,
+            Check::HeaderInjection(value) => {
+                let injected = scanner::header(&res.res_headers, "x-rudy-injected")?;
+                injected.contains(value.as_str()).then(|| {
+                    Issue::new(
+                        Severity::Medium,
+                        "HTTP header injection",
+                        format!("{} added the header X-Rudy-Injected", sent),
+                    )
+                })
+            }
+            Check::ForwardedHost(host) => {
+                let place = if location.contains(host.as_str()) {
+                    "the Location header"
+                } else if body.contains(host.as_str()) {
+                    "the body"
+                } else {
+                    return None;
+                };
+                Some(Issue::new(
+                    Severity::Medium,
+                    "Forwarded host reflected",
+                    format!("{} was reflected in {}", sent, place),
+                ))
+            }
+        }
+    }
+}
+
+/// Progress of the active scan, shown in the Issues tab.
+#[derive(Clone, Copy, Default)]
+pub struct Status {
+    pub queued: usize,
+    pub running: usize,
+    pub sent: usize,
+    /// Number of probes which found an issue.
+    pub found: usize,
+    pub paused: bool,
+}
+
+struct State {
+    queue: VecDeque<Probe>,
+    status: Status,
+    /// Server-side request forgery probes by the token in their callback URL, with the time
+    /// after which callbacks are no longer expected.
+    callbacks: HashMap<String, (Probe, Instant)>,
+    /// Number used to make the markers of the payloads unique.
+    next_marker: usize,
+}
+
+/// Handle of the active scanner. Probes are queued here and sent by a background task, which
+/// keeps to the rate limit and concurrency cap of the settings. Exchanges in which a probe found
+/// an issue are added to the History.
+#[derive(Clone)]
+pub struct ActiveScanner {
+    state: Arc<Mutex<State>>,
+    notify: Arc<Notify>,
+    settings: ActiveScanSettings,
+    tx: Sender<Req>,
+    /// Address of the callback listener, if it could be started. It is started by the first
+    /// scan.
+    callback: Arc<OnceLock<Option<SocketAddr>>>,
+}
+
+impl ActiveScanner {
+    pub fn start(settings: &Settings, client: Client<UpstreamConnector>, tx: Sender<Req>) -> Self {
+        let state = Arc::new(Mutex::new(State {
+            queue: VecDeque::new(),
+            status: Status::default(),
+            callbacks: HashMap::new(),
+            next_marker: 1,
+        }));
+        let notify = Arc::new(Notify::new());
+        let active = &settings.active_scan;
+
+        tokio::spawn(send_probes(
+            Arc::clone(&state),
+            Arc::clone(&notify),
+            client,
+            tx.clone(),
+            active.clone(),
+        ));
+
+        ActiveScanner {
+            state,
+            notify,
+            settings: active.clone(),
+            tx,
+            callback: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Returns the address of the callback listener, starting it if it is not running yet.
+    fn callback(&self) -> Option<SocketAddr> {
+        *self
+            .callback
+            .get_or_init(|| match bind_callback_listener(&self.settings) {
+                Ok(listener) => {
+                    let addr = listener.local_addr().ok();
+                    let state = Arc::clone(&self.state);
+                    tokio::spawn(serve_callbacks(listener, state, self.tx.clone()));
+                    addr
+                }
+                Err(e) => {
+                    warn!("Unable to start the active scan callback listener. {}", e);
+                    None
+                }
+            })
+    }
+
+    /// Queues the probes of an exchange. Returns the number of probes queued.
+    pub fn scan(&self, req: &Req) -> usize {
+        let callback = self.callback();
+        let body = scanner::response_body(req);
+        let original = Arc::new(Original {
+            id: req.id,
+            body: String::from_utf8_lossy(&body).to_string(),
+            duration: req.duration,
+        });
+
+        let mut state = self.state.lock().unwrap();
+        let mut marker = || {
+            state.next_marker += 1;
+            format!("rudy{}", state.next_marker - 1)
+        };
+        let mut probes = vec![];
+
+        let uri = req.url.parse::<Uri>().unwrap_or_default();
+        let query: Vec<(String, String)> =
+            form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes())
+                .into_owned()
+                .collect();
+        let is_form = scanner::header(&req.headers, "content-type")
+            .is_some_and(|t| t.starts_with("application/x-www-form-urlencoded"));
+        let form: Vec<(String, String)> = if is_form {
+            form_urlencoded::parse(&req.body).into_owned().collect()
+        } else {
+            vec![]
+        };
+
+        for (in_query, params) in [(true, &query), (false, &form)] {
+            for (i, (name, value)) in params.iter().enumerate() {
+                for (check, payload) in self.payloads(value, callback, &mut marker) {
+                    let mut edited = params.clone();
+                    edited[i].1 = payload.clone();
+                    let encoded = form_urlencoded::Serializer::new(String::new())
+                        .extend_pairs(&edited)
+                        .finish();
+                    let mut probe_req = probe_request(req);
+                    if in_query {
+                        probe_req.url = with_query(&uri, &encoded);
+                    } else {
+                        probe_req.body = encoded.into_bytes();
+                    }
+                    probes.push(Probe {
+                        check,
+                        target: name.clone(),
+                        payload,
+                        req: probe_req,
+                        original: Arc::clone(&original),
+                    });
+                }
+            }
+        }
+
+        let host = format!("{}.example", marker());
+        let mut probe_req = probe_request(req);
+        probe_req
+            .headers
+            .push(("X-Forwarded-Host".to_string(), host.clone()));
+        probes.push(Probe {
+            check: Check::ForwardedHost(host.clone()),
+            target: "X-Forwarded-Host".to_string(),
+            payload: host,
+            req: probe_req,
+            original,
+        });
+
+        let count = probes.len();
+        state.queue.extend(probes);
+        state.status.queued = state.queue.len();
+        drop(state);
+        self.notify.notify_one();
+        count
+    }
+
+    /// Returns the checks to run on a parameter and the payloads which replace its value.
+    fn payloads(
+        &self,
+        value: &str,
+        callback: Option<SocketAddr>,
+        marker: &mut impl FnMut() -> String,
+    ) -> Vec<(Check, String)> {
+        let sleep = self.settings.sleep_secs;
+        let mut payloads = vec![
+            (Check::SqlError, format!("{}'", value)),
+            (Check::SqlError, format!("{}\"", value)),
+            (
+                Check::SqlTiming,
+                format!(
+                    "{}'XOR(SELECT(0)FROM(SELECT(SLEEP({})))x)XOR'",
+                    value, sleep
+                ),
+            ),
+            (
+                Check::SqlTiming,
+                format!("{}';SELECT pg_sleep({})--", value, sleep),
+            ),
+            (
+                Check::SqlTiming,
+                format!("{}';WAITFOR DELAY '0:0:{}'--", value, sleep),
+            ),
+        ];
+
+        let tag = marker();
+        payloads.push((Check::Xss(tag.clone()), format!("{}\"'><{}>", value, tag)));
+        payloads.push((
+            Check::Traversal("root:x:0:0:"),
+            "../../../../../../../../etc/passwd".to_string(),
+        ));
+        payloads.push((
+            Check::Traversal("[fonts]"),
+            r"..\..\..\..\..\..\..\..\windows\win.ini".to_string(),
+        ));
+        payloads.push((Check::Redirect, format!("https://{}/", REDIRECT_HOST)));
+        if let Some(addr) = callback {
+            payloads.push((Check::Ssrf, format!("http://{}/{}", addr, marker())));
+        }
+        let injected = marker();
+        payloads.push((
+            Check::HeaderInjection(injected.clone()),
+            format!("{}\r\nX-Rudy-Injected: {}", value, injected),
+        ));
+        payloads
+    }
+
+    pub fn status(&self) -> Status {
+        self.state.lock().unwrap().status
+    }
+
+    /// Pauses or resumes sending the queued probes. Returns whether the scan is now paused.
+    pub fn toggle_pause(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.status.paused = !state.status.paused;
+        if !state.status.paused {
+            self.notify.notify_one();
+        }
+        state.status.paused
+    }
+}
+
+/// Copies the request of an exchange, leaving out the headers which are not sent with probes.
+fn probe_request(req: &Req) -> Req {
+    Req {
+        host: req.host.clone(),
+        method: req.method.clone(),
+        url: req.url.clone(),
+        headers: req
+            .headers
+            .iter()
+            .filter(|(name, _)| !SKIPPED_HEADERS.contains(&name.to_lowercase().as_str()))
+            .cloned()
+            .collect(),
+        body: req.body.clone(),
+        client_version: Version::HTTP_11,
+        ..Default::default()
+    }
+}
+
+/// Replaces the query of a URI.
+fn with_query(uri: &Uri, query: &str) -> String {
+    let mut url = String::new();
+    if let (Some(scheme), Some(authority)) = (uri.scheme_str(), uri.authority()) {
+        url = format!("{}://{}", scheme, authority);
+    }
+    format!("{}{}?{}", url, uri.path(), query)
+}
+
+fn bind_callback_listener(settings: &ActiveScanSettings) -> std::io::Result<TcpListener> {
+    let listener =
+        std::net::TcpListener::bind((settings.callback_host.as_str(), settings.callback_port))?;
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
+/// Sends the queued probes, waiting while the scan is paused or the queue is empty.
+async fn send_probes(
+    state: Arc<Mutex<State>>,
+    notify: Arc<Notify>,
+    client: Client<UpstreamConnector>,
+    tx: Sender<Req>,
+    settings: ActiveScanSettings,
+) {
+    let semaphore = Arc::new(Semaphore::new(settings.concurrency.max(1)));
+    let mut rate = interval(Duration::from_secs(1) / settings.requests_per_second.max(1));
+    rate.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let time_limit = Duration::from_secs(settings.timeout_secs);
+    let sleep = Duration::from_secs(settings.sleep_secs);
+
+    loop {
+        let permit = Arc::clone(&semaphore)
+            .acquire_owned()
+            .await
+            .expect("Failed to acquire probe permit");
+        rate.tick().await;
+
+        let probe = loop {
+            {
+                let mut state = state.lock().unwrap();
+                if !state.status.paused {
+                    if let Some(probe) = state.queue.pop_front() {
+                        state.status.queued = state.queue.len();
+                        state.status.running += 1;
+                        if let (Check::Ssrf, Some(token)) =
+                            (&probe.check, probe.payload.rsplit('/').next())
+                        {
+                            let now = Instant::now();
+                            state.callbacks.retain(|_, (_, expires)| *expires > now);
+                            let expires = now + time_limit;
+                            state
+                                .callbacks
+                                .insert(token.to_string(), (probe.clone(), expires));
+                        }
+                        break probe;
+                    }
+                }
+            }
+            notify.notified().await;
+        };
+
+        let state = Arc::clone(&state);
+        let client = client.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let res = upstream::send(&client, &probe.req, time_limit).await;
+            let issue = probe.evaluate(&res, sleep);
+            {
+                let mut state = state.lock().unwrap();
+                state.status.running -= 1;
+                state.status.sent += 1;
+                if issue.is_some() {
+                    state.status.found += 1;
+                }
+            }
+            if let Some(issue) = issue {
+                add_to_history(&tx, res, &probe, issue).await;
+            }
+            drop(permit);
+        });
+    }
+}
+
+async fn add_to_history(tx: &Sender<Req>, mut entry: Req, probe: &Probe, issue: Issue) {
+    entry.comment = format!("Active scan of request {}", probe.original.id);
+    entry.issues = vec![issue];
+    tx.send(entry).await.ok();
+}
+
+/// Answers the requests made to the callback URLs of the server-side request forgery probes,
+/// and reports the probes they belong to.
+async fn serve_callbacks(listener: TcpListener, state: Arc<Mutex<State>>, tx: Sender<Req>) {
+    loop {
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Failed to accept active scan callback. {}", e);
+                continue;
+            }
+        };
+        let state = Arc::clone(&state);
+        let tx = tx.clone();
+
+        tokio::spawn(async move {
+            let mut buf = [0; 4096];
+            let n = stream.read(&mut buf).await.unwrap_or_default();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await
+                .ok();
+
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+            let token = path.trim_matches('/');
+            let probe = {
+                let mut state = state.lock().unwrap();
+                let probe = state
+                    .callbacks
+                    .remove(token)
+                    .filter(|(_, expires)| *expires > Instant::now())
+                    .map(|(probe, _)| probe);
+                if probe.is_some() {
+                    state.status.found += 1;
+                }
+                probe
+            };
+
+            if let Some(probe) = probe {
+                let issue = Issue::new(
+                    Severity::High,
+                    "Server-side request forgery",
+                    format!(
+                        "{}={} made {} connect to the callback listener",
+                        probe.target,
+                        probe.payload,
+                        peer.ip()
+                    ),
+                );
+                let mut entry = probe.req.clone();
+                entry.timestamp = Some(OffsetDateTime::now_utc());
+                add_to_history(&tx, entry, &probe, issue).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{net::TcpStream, sync::mpsc};
+
+    fn original(body: &str, duration: u64) -> Arc<Original> {
+        Arc::new(Original {
+            id: 1,
+            body: body.to_string(),
+            duration: Some(Duration::from_millis(duration)),
+        })
+    }
+
+    fn probe(check: Check, original: Arc<Original>) -> Probe {
+        Probe {
+            check,
+            target: "q".to_string(),
+            payload: "x'".to_string(),
+            req: Req::default(),
+            original,
+        }
+    }
+
+    fn response(body: &str, duration: u64) -> Req {
+        Req {
+            status: Some(200),
+            res_body: body.as_bytes().to_vec(),
+            duration: Some(Duration::from_millis(duration)),
+            ..Req::default()
+        }
+    }
+
+    #[test]
+    fn sql_errors_only_count_when_new() {
+        let sleep = Duration::from_secs(5);
+        let error = response("You have an error in your SQL syntax", 10);
+        let new = probe(Check::SqlError, original("ok", 10));
+        let issue = new.evaluate(&error, sleep).unwrap();
+        assert_eq!(issue.title, "SQL injection (error based)");
+        let old = probe(Check::SqlError, original("ORA-00933", 10));
+        assert!(old.evaluate(&error, sleep).is_none());
+    }
+
+    #[test]
+    fn timing_needs_a_fast_original() {
+        let sleep = Duration::from_secs(5);
+        let slow = response("", 5_200);
+        let fast_original = probe(Check::SqlTiming, original("", 100));
+        assert!(fast_original.evaluate(&slow, sleep).is_some());
+        let slow_original = probe(Check::SqlTiming, original("", 3_000));
+        assert!(slow_original.evaluate(&slow, sleep).is_none());
+        assert!(fast_original.evaluate(&response("", 100), sleep).is_none());
+    }
+
+    #[test]
+    fn redirects_to_the_probe_host_are_open() {
+        let mut res = response("", 10);
+        res.status = Some(302);
+        res.res_headers = vec![(
+            "Location".to_string(),
+            format!("https://{}/", REDIRECT_HOST),
+        )];
+        let redirect = probe(Check::Redirect, original("", 10));
+        assert!(redirect.evaluate(&res, Duration::from_secs(5)).is_some());
+        res.res_headers[0].1 = "/home".to_string();
+        assert!(redirect.evaluate(&res, Duration::from_secs(5)).is_none());
+    }
+
+    #[test]
+    fn with_query_keeps_scheme_and_path() {
+        let uri: Uri = "https://a.test:8443/p/q?a=1".parse().unwrap();
+        assert_eq!(with_query(&uri, "a=2"), "https://a.test:8443/p/q?a=2");
+    }
+
+    #[test]
+    fn probe_requests_leave_out_framing_headers() {
+        let req = Req {
+            headers: vec![
+                ("Content-Length".to_string(), "3".to_string()),
+                ("Accept-Encoding".to_string(), "gzip".to_string()),
+                ("Cookie".to_string(), "a=1".to_string()),
+            ],
+            ..Req::default()
+        };
+        assert_eq!(
+            probe_request(&req).headers,
+            [("Cookie".to_string(), "a=1".to_string())]
+        );
+    }
+
+    /// Makes a request to a callback URL and returns the entry the callback added, if any.
+    async fn call_back(addr: SocketAddr, token: &str, rx: &mut mpsc::Receiver<Req>) -> Option<Req> {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET /{} HTTP/1.1\r\n\r\n", token);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.unwrap();
+        tokio::time::timeout(Duration::from_millis(200), rx.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    #[tokio::test]
+    async fn callback_listener_starts_lazily_and_expires_probes() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let settings = Settings::default();
+        let scanner = ActiveScanner::start(&settings, upstream::client(&settings).unwrap(), tx);
+        assert!(scanner.callback.get().is_none());
+
+        let req = Req {
+            id: 1,
+            url: "http://127.0.0.1:9/?q=1".to_string(),
+            host: "127.0.0.1".to_string(),
+            status: Some(200),
+            ..Req::default()
+        };
+        scanner.toggle_pause();
+        assert!(scanner.scan(&req) > 0);
+        let addr = scanner.callback.get().copied().flatten().unwrap();
+
+        {
+            let mut state = scanner.state.lock().unwrap();
+            let ssrf = state
+                .queue
+                .iter()
+                .find(|p| matches!(p.check, Check::Ssrf))
+                .cloned()
+                .unwrap();
+            let now = Instant::now();
+            let expired = now - Duration::from_secs(1);
+            let expires = now + Duration::from_secs(60);
+            state
+                .callbacks
+                .insert("old".to_string(), (ssrf.clone(), expired));
+            state.callbacks.insert("new".to_string(), (ssrf, expires));
+        }
+
+        assert!(call_back(addr, "old", &mut rx).await.is_none());
+        let entry = call_back(addr, "new", &mut rx).await.unwrap();
+        assert_eq!(entry.issues[0].title, "Server-side request forgery");
+        assert_eq!(entry.comment, "Active scan of request 1");
+    }
+}
@@ -0,0 +1,387 @@
+use crate::settings::{KeyType, LeafCertSettings};
+use hudsucker::{
+    async_trait::async_trait,
+    certificate_authority::CertificateAuthority,
+    hyper::http::uri::Authority,
+    rustls::{self, ServerConfig},
+};
+use moka::future::Cache;
+use openssl::{pkey::PKey, rsa::Rsa};
+use rand::{thread_rng, Rng};
+use rcgen::{KeyPair, SanType, PKCS_ECDSA_P256_SHA256};
+use rustls_pemfile as pemfile;
+use std::{collections::HashSet, net::IpAddr, path::PathBuf, sync::Arc};
+use time::{Duration, OffsetDateTime};
+use tracing::warn;
+use x509_parser::{
+    certificate::X509Certificate,
+    extensions::GeneralName,
+    oid_registry::{OID_KEY_TYPE_EC_PUBLIC_KEY, OID_PKCS1_RSAENCRYPTION},
+    parse_x509_certificate,
+};
+
+/// Size in bits of generated RSA keys.
+const RSA_KEY_BITS: u32 = 2048;
+
+/// Offset subtracted from the current time for the start of a leaf certificate's validity, to
+/// tolerate clients with slightly wrong clocks.
+const NOT_BEFORE_OFFSET: i64 = 60;
+
+/// Issues the leaf certificates presented to clients for intercepted hosts.
+///
+/// Certificates are cached in memory and, if a cache directory is configured, persisted to disk
+/// so they survive restarts. Clones share the cache.
+#[derive(Clone)]
+pub struct LeafAuthority {
+    private_key: rustls::PrivateKey,
+    ca_cert: rustls::Certificate,
+    settings: LeafCertSettings,
+    /// Hosts for which clients may only negotiate HTTP/1.1.
+    http1_hosts: HashSet<String>,
+    cache: Cache<String, CachedConfig>,
+}
+
+/// Server config cached for a host, with the expiry of its certificate.
+#[derive(Clone)]
+struct CachedConfig {
+    server_cfg: Arc<ServerConfig>,
+    not_after: OffsetDateTime,
+}
+
+/// Certificate issued for a host, with its private key.
+struct Leaf {
+    cert: rustls::Certificate,
+    key: rustls::PrivateKey,
+    not_after: OffsetDateTime,
+}
+
+impl LeafAuthority {
+    pub fn new(
+        private_key: rustls::PrivateKey,
+        ca_cert: rustls::Certificate,
+        settings: LeafCertSettings,
+        http1_hosts: HashSet<String>,
+    ) -> Result<Self, rcgen::RcgenError> {
+        // Refresh cached configs well before newly issued certificates expire. Certificates loaded
+        // from disk may expire sooner, so entries are also checked against their expiry.
+        let ttl = std::time::Duration::from_secs(settings.validity_days.get() * 24 * 60 * 60 / 2);
+
+        let ca = LeafAuthority {
+            private_key,
+            ca_cert,
+            cache: Cache::builder()
+                .max_capacity(settings.cache_size)
+                .time_to_live(ttl)
+                .build(),
+            settings,
+            http1_hosts,
+        };
+
+        ca.signer()?;
+        Ok(ca)
+    }
+
+    /// Returns the CA as an rcgen certificate for signing leaf certificates.
+    fn signer(&self) -> Result<rcgen::Certificate, rcgen::RcgenError> {
+        let key_pair = KeyPair::from_der(&self.private_key.0)?;
+        let params = rcgen::CertificateParams::from_ca_cert_der(&self.ca_cert.0, key_pair)?;
+        rcgen::Certificate::from_params(params)
+    }
+
+    /// Names a certificate for the host must be issued for.
+    fn sans<'a>(&'a self, host: &'a str) -> impl Iterator<Item = &'a str> {
+        std::iter::once(host).chain(self.settings.extra_sans.iter().map(|s| s.as_str()))
+    }
+
+    /// Generates a certificate for a host, along with its private key.
+    fn gen_cert(&self, host: &str) -> Leaf {
+        let mut params = rcgen::CertificateParams::default();
+        params.serial_number = Some(thread_rng().gen::<u64>());
+
+        let not_before = OffsetDateTime::now_utc() - Duration::seconds(NOT_BEFORE_OFFSET);
+        params.not_before = not_before;
+        let not_after = not_before + Duration::days(self.settings.validity_days.get() as i64);
+        params.not_after = not_after;
+
+        for name in self.sans(host) {
+            let san = match name.parse::<IpAddr>() {
+                Ok(ip) => SanType::IpAddress(ip),
+                Err(_) => SanType::DnsName(name.to_string()),
+            };
+            params.subject_alt_names.push(san);
+        }
+
+        let key_pair = match self.settings.key_type {
+            KeyType::Ca => {
+                KeyPair::from_der(&self.private_key.0).expect("Failed to parse private key")
+            }
+            KeyType::Ecdsa => {
+                KeyPair::generate(&PKCS_ECDSA_P256_SHA256).expect("Failed to generate private key")
+            }
+            // rcgen can't generate RSA keys, so they are generated with OpenSSL.
+            KeyType::Rsa => {
+                let pem = Rsa::generate(RSA_KEY_BITS)
+                    .and_then(PKey::from_rsa)
+                    .and_then(|key| key.private_key_to_pem_pkcs8())
+                    .expect("Failed to generate private key");
+                KeyPair::from_pem(&String::from_utf8_lossy(&pem))
+                    .expect("Failed to parse private key")
+            }
+        };
+        params.alg = key_pair
+            .compatible_algs()
+            .next()
+            .expect("Failed to find compatible algorithm");
+        params.key_pair = Some(key_pair);
+
+        let cert = rcgen::Certificate::from_params(params).expect("Failed to generate certificate");
+        let signer = self.signer().expect("Failed to parse CA certificate");
+
+        let cert_der = cert
+            .serialize_der_with_signer(&signer)
+            .expect("Failed to serialize certificate");
+        let key_der = match self.settings.key_type {
+            KeyType::Ca => self.private_key.0.clone(),
+            KeyType::Ecdsa | KeyType::Rsa => cert.serialize_private_key_der(),
+        };
+
+        Leaf {
+            cert: rustls::Certificate(cert_der),
+            key: rustls::PrivateKey(key_der),
+            not_after,
+        }
+    }
+
+    fn cert_path(&self, host: &str) -> Option<PathBuf> {
+        let dir = self.settings.cache_dir.as_ref()?;
+        let file_name: String = host
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        Some(PathBuf::from(dir).join(format!("{}.pem", file_name)))
+    }
+
+    /// Loads a persisted certificate for a host if it is still valid, was issued by the current CA
+    /// and has the configured names.
+    fn load_cert(&self, host: &str) -> Option<Leaf> {
+        let bytes = std::fs::read(self.cert_path(host)?).ok()?;
+
+        let mut cert = None;
+        let mut key = None;
+        for item in pemfile::read_all(&mut bytes.as_slice()).ok()? {
+            match item {
+                pemfile::Item::X509Certificate(c) => cert = Some(rustls::Certificate(c)),
+                pemfile::Item::PKCS8Key(k) => key = Some(rustls::PrivateKey(k)),
+                _ => {}
+            }
+        }
+        let cert = cert?;
+
+        let (_, leaf) = parse_x509_certificate(&cert.0).ok()?;
+        let (_, ca) = parse_x509_certificate(&self.ca_cert.0).ok()?;
+        if !leaf.validity().is_valid()
+            || leaf.issuer().as_raw() != ca.subject().as_raw()
+            || !self.has_key_type(&leaf, &ca)
+        {
+            return None;
+        }
+
+        let mut sans: Vec<String> = leaf
+            .subject_alternative_name()
+            .ok()??
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(dns) => Some(dns.to_string()),
+                GeneralName::IPAddress(&[a, b, c, d]) => {
+                    Some(IpAddr::from([a, b, c, d]).to_string())
+                }
+                GeneralName::IPAddress(ip) => <[u8; 16]>::try_from(*ip)
+                    .ok()
+                    .map(|ip| IpAddr::from(ip).to_string()),
+                _ => None,
+            })
+            .collect();
+        let mut expected: Vec<String> = self
+            .sans(host)
+            .map(|name| match name.parse::<IpAddr>() {
+                Ok(ip) => ip.to_string(),
+                Err(_) => name.to_string(),
+            })
+            .collect();
+        sans.sort();
+        expected.sort();
+        if sans != expected {
+            return None;
+        }
+
+        let not_after =
+            OffsetDateTime::from_unix_timestamp(leaf.validity().not_after.timestamp()).ok()?;
+        let key = match (key, &self.settings.key_type) {
+            (Some(key), KeyType::Ecdsa | KeyType::Rsa) => key,
+            (None, KeyType::Ca) => self.private_key.clone(),
+            _ => return None,
+        };
+        Some(Leaf {
+            cert,
+            key,
+            not_after,
+        })
+    }
+
+    /// Checks if the key of a persisted certificate is of the configured type, so certificates
+    /// are issued again after the `key_type` setting changed.
+    fn has_key_type(&self, leaf: &X509Certificate, ca: &X509Certificate) -> bool {
+        let algorithm = &leaf.public_key().algorithm.algorithm;
+        match self.settings.key_type {
+            KeyType::Ca => leaf.public_key().raw == ca.public_key().raw,
+            KeyType::Ecdsa => *algorithm == OID_KEY_TYPE_EC_PUBLIC_KEY,
+            KeyType::Rsa => *algorithm == OID_PKCS1_RSAENCRYPTION,
+        }
+    }
+
+    /// Writes a certificate to the cache directory. The key is only stored if it is not the CA's.
+    fn store_cert(&self, host: &str, leaf: &Leaf) {
+        let path = match self.cert_path(host) {
+            Some(p) => p,
+            None => return,
+        };
+
+        let mut contents = pem_block("CERTIFICATE", &leaf.cert.0);
+        if !matches!(self.settings.key_type, KeyType::Ca) {
+            contents += &pem_block("PRIVATE KEY", &leaf.key.0);
+        }
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).ok();
+        }
+        if let Err(e) = std::fs::write(&path, contents) {
+            warn!("Unable to store certificate at '{}'. {}", path.display(), e);
+        }
+    }
+}
+
+#[async_trait]
+impl CertificateAuthority for LeafAuthority {
+    async fn gen_server_config(&self, authority: &Authority) -> Arc<ServerConfig> {
+        let host = authority.host().to_lowercase();
+        if let Some(cached) = self.cache.get(&host) {
+            if cached.not_after > OffsetDateTime::now_utc() {
+                return cached.server_cfg;
+            }
+        }
+
+        let leaf = match self.load_cert(&host) {
+            Some(l) => l,
+            None => {
+                let leaf = self.gen_cert(&host);
+                self.store_cert(&host, &leaf);
+                leaf
+            }
+        };
+
+        let mut server_cfg = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![leaf.cert], leaf.key)
+            .expect("Failed to build ServerConfig");
+
+        server_cfg.alpn_protocols = if self.http1_hosts.contains(&host) {
+            vec![b"http/1.1".to_vec()]
+        } else {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        };
+
+        let server_cfg = Arc::new(server_cfg);
+        let cached = CachedConfig {
+            server_cfg: Arc::clone(&server_cfg),
+            not_after: leaf.not_after,
+        };
+        self.cache.insert(host, cached).await;
+
+        server_cfg
+    }
+}
+
+fn pem_block(label: &str, der: &[u8]) -> String {
+    let pem = pem::Pem {
+        tag: label.to_string(),
+        contents: der.to_vec(),
+    };
+    pem::encode(&pem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa};
+
+    fn authority(key_type: KeyType, cache_dir: &std::path::Path) -> LeafAuthority {
+        let mut params = CertificateParams::default();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.alg = &PKCS_ECDSA_P256_SHA256;
+        let ca = rcgen::Certificate::from_params(params).unwrap();
+
+        let settings = LeafCertSettings {
+            cache_dir: Some(cache_dir.to_string_lossy().to_string()),
+            key_type,
+            ..LeafCertSettings::default()
+        };
+        LeafAuthority::new(
+            rustls::PrivateKey(ca.serialize_private_key_der()),
+            rustls::Certificate(ca.serialize_der().unwrap()),
+            settings,
+            HashSet::new(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn rsa_leaf_certificates_get_their_own_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = authority(KeyType::Rsa, dir.path());
+
+        let leaf = ca.gen_cert("a.test");
+        let (_, cert) = parse_x509_certificate(&leaf.cert.0).unwrap();
+        assert_eq!(
+            cert.public_key().algorithm.algorithm,
+            OID_PKCS1_RSAENCRYPTION
+        );
+        assert_ne!(leaf.key.0, ca.private_key.0);
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![leaf.cert.clone()], leaf.key.clone())
+            .unwrap();
+
+        ca.store_cert("a.test", &leaf);
+        let loaded = ca.load_cert("a.test").unwrap();
+        assert_eq!(loaded.cert, leaf.cert);
+        assert_eq!(loaded.key, leaf.key);
+    }
+
+    #[test]
+    fn stored_certificates_of_another_key_type_are_issued_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = authority(KeyType::Ecdsa, dir.path());
+        let leaf = ca.gen_cert("a.test");
+        ca.store_cert("a.test", &leaf);
+        assert!(ca.load_cert("a.test").is_some());
+
+        let rsa = LeafAuthority {
+            settings: LeafCertSettings {
+                key_type: KeyType::Rsa,
+                ..ca.settings.clone()
+            },
+            ..ca
+        };
+        assert!(rsa.load_cert("a.test").is_none());
+    }
+}
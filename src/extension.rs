@@ -0,0 +1,242 @@
+use crate::{
+    passthrough::ClientAddrs,
+    rudy_tui::Req,
+    scanner::{self, Issue},
+};
+use hudsucker::{
+    async_trait::async_trait,
+    hyper::{Body, Request, Response},
+    tungstenite::Message,
+    HttpContext, MessageContext, MessageHandler, RequestOrResponse,
+};
+use std::{sync::Arc, time::Duration};
+
+/// Extensions added to rudy with the [`Builder`](crate::Builder), in the order they were added.
+pub type Extensions = Arc<[Box<dyn RudyExtension>]>;
+
+/// An extension compiled into a rudy binary. All methods have defaults which leave the traffic and
+/// the UI unchanged, so an extension only implements what it needs.
+///
+/// The hooks are those of hudsucker's `HttpHandler` and `MessageHandler`. They are called with
+/// the exchanges after the scripts have run, and the History shows the exchanges as the
+/// extensions left them.
+#[async_trait]
+pub trait RudyExtension: Send + Sync + 'static {
+    /// Name of the extension, shown for the requests it answers.
+    fn name(&self) -> &str;
+
+    /// Called with each request before it is sent upstream. Returning a response sends it to the
+    /// client instead, and the later extensions are skipped.
+    async fn handle_request(&self, _ctx: &HttpContext, req: Request<Body>) -> RequestOrResponse {
+        RequestOrResponse::Request(req)
+    }
+
+    /// Called with each response before it is sent to the client.
+    async fn handle_response(&self, _ctx: &HttpContext, res: Response<Body>) -> Response<Body> {
+        res
+    }
+
+    /// Called with each WebSocket message. Returning `None` drops the message.
+    async fn handle_message(
+        &self,
+        _ctx: &MessageContext,
+        _direction: Direction,
+        message: Message,
+    ) -> Option<Message> {
+        Some(message)
+    }
+
+    /// Tabs added after the built-in tabs of the terminal UI.
+    fn tabs(&self) -> Vec<Box<dyn ExtensionTab>> {
+        vec![]
+    }
+
+    /// Actions added to the context menu of History entries.
+    fn menu_actions(&self) -> Vec<Arc<dyn ExtensionAction>> {
+        vec![]
+    }
+}
+
+/// Direction of a WebSocket message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    ToServer,
+    ToClient,
+}
+
+/// A tab of the terminal UI added by an extension.
+pub trait ExtensionTab: Send {
+    /// Title of the tab, which is read whenever the tabs are drawn.
+    fn title(&self) -> String;
+
+    /// Returns the text shown in the tab, given the entry selected in the History tab and the
+    /// size of the tab.
+    fn render(&mut self, selected: Option<Exchange>, width: u16, height: u16) -> String;
+
+    /// Handles a key pressed while the tab is shown, written as in the `[keymap]` settings, such
+    /// as `j`, `ctrl-d` or `enter`. Returns true if the key was used, or false to let rudy handle
+    /// it.
+    fn handle_key(&mut self, _key: &str) -> bool {
+        false
+    }
+}
+
+/// An action of the History context menu added by an extension.
+pub trait ExtensionAction: Send + Sync {
+    fn title(&self) -> String;
+
+    /// Runs the action on the selected History entry. Returns a message shown in the filter bar.
+    fn run(&self, exchange: Exchange) -> Option<String>;
+}
+
+/// Read-only view of a History entry, given to the tabs and actions of extensions.
+#[derive(Clone, Copy)]
+pub struct Exchange<'a> {
+    req: &'a Req,
+}
+
+impl<'a> Exchange<'a> {
+    pub(crate) fn new(req: &'a Req) -> Self {
+        Exchange { req }
+    }
+
+    /// Number of the entry in the History.
+    pub fn id(&self) -> usize {
+        self.req.id
+    }
+
+    pub fn host(&self) -> &'a str {
+        &self.req.host
+    }
+
+    pub fn method(&self) -> &'a str {
+        &self.req.method
+    }
+
+    pub fn url(&self) -> &'a str {
+        &self.req.url
+    }
+
+    pub fn headers(&self) -> &'a [(String, String)] {
+        &self.req.headers
+    }
+
+    /// Returns the value of the first request header of a name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        scanner::header(&self.req.headers, name)
+    }
+
+    pub fn body(&self) -> &'a [u8] {
+        &self.req.body
+    }
+
+    /// Status of the response, or None if no response was received.
+    pub fn status(&self) -> Option<u16> {
+        self.req.status
+    }
+
+    pub fn response_headers(&self) -> &'a [(String, String)] {
+        &self.req.res_headers
+    }
+
+    /// Returns the value of the first response header of a name, ignoring case.
+    pub fn response_header(&self, name: &str) -> Option<&'a str> {
+        scanner::header(&self.req.res_headers, name)
+    }
+
+    /// Body of the response as it was received, so a compressed body is left compressed. Only
+    /// the start of large bodies is recorded.
+    pub fn response_body(&self) -> &'a [u8] {
+        &self.req.res_body
+    }
+
+    /// Error of the exchange, such as a failed connection or script.
+    pub fn error(&self) -> Option<&'a str> {
+        self.req.error.as_deref()
+    }
+
+    /// Time from sending the request to receiving the whole response.
+    pub fn duration(&self) -> Option<Duration> {
+        self.req.duration
+    }
+
+    pub fn comment(&self) -> &'a str {
+        &self.req.comment
+    }
+
+    pub fn tags(&self) -> &'a [String] {
+        &self.req.tags
+    }
+
+    /// Findings of the passive scanner.
+    pub fn issues(&self) -> &'a [Issue] {
+        &self.req.issues
+    }
+}
+
+/// Passes the WebSocket messages in one direction through the extensions.
+#[derive(Clone)]
+pub struct ExtensionMessages {
+    extensions: Extensions,
+    direction: Direction,
+    clients: ClientAddrs,
+}
+
+impl ExtensionMessages {
+    pub fn new(extensions: Extensions, direction: Direction, clients: ClientAddrs) -> Self {
+        ExtensionMessages {
+            extensions,
+            direction,
+            clients,
+        }
+    }
+}
+
+#[async_trait]
+impl MessageHandler for ExtensionMessages {
+    async fn handle_message(&mut self, ctx: &MessageContext, message: Message) -> Option<Message> {
+        let ctx = MessageContext {
+            client_addr: self.clients.get(ctx.client_addr).unwrap_or(ctx.client_addr),
+            server_uri: ctx.server_uri.clone(),
+        };
+        let mut message = message;
+        for extension in self.extensions.iter() {
+            message = extension
+                .handle_message(&ctx, self.direction, message)
+                .await?;
+        }
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Severity;
+
+    #[test]
+    fn exchange_reads_the_entry() {
+        let req = Req {
+            id: 2,
+            method: "POST".to_string(),
+            url: "http://a.test/login".to_string(),
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: b"a".to_vec(),
+            status: Some(401),
+            res_headers: vec![("WWW-Authenticate".to_string(), "Basic".to_string())],
+            issues: vec![Issue::new(Severity::Info, "Found", "")],
+            ..Req::default()
+        };
+        let exchange = Exchange::new(&req);
+        assert_eq!(exchange.id(), 2);
+        assert_eq!(exchange.method(), "POST");
+        assert_eq!(exchange.header("content-type"), Some("text/plain"));
+        assert_eq!(exchange.header("accept"), None);
+        assert_eq!(exchange.body(), b"a");
+        assert_eq!(exchange.status(), Some(401));
+        assert_eq!(exchange.response_header("www-authenticate"), Some("Basic"));
+        assert!(exchange.response_body().is_empty());
+        assert_eq!(exchange.issues()[0].title, "Found");
+        assert_eq!(exchange.error(), None);
+    }
+}
@@ -0,0 +1,555 @@
+use crate::rudy_tui::Req;
+use hudsucker::hyper::{Uri, Version};
+use std::collections::VecDeque;
+use time::OffsetDateTime;
+
+/// Short options of curl which change the request and take an argument.
+const SHORT_WITH_ARG: [&str; 8] = ["-X", "-H", "-A", "-e", "-b", "-u", "-d", "-F"];
+
+/// Options of curl which take an argument but don't change the request.
+const IGNORED_WITH_ARG: [&str; 29] = [
+    "-o",
+    "--output",
+    "-x",
+    "--proxy",
+    "-U",
+    "--proxy-user",
+    "-m",
+    "--max-time",
+    "--connect-timeout",
+    "--max-redirs",
+    "-w",
+    "--write-out",
+    "-D",
+    "--dump-header",
+    "-c",
+    "--cookie-jar",
+    "--cacert",
+    "-E",
+    "--cert",
+    "--key",
+    "--retry",
+    "--retry-delay",
+    "--retry-max-time",
+    "--resolve",
+    "--connect-to",
+    "--limit-rate",
+    "-r",
+    "--range",
+    "--interface",
+];
+
+/// Options of curl without an argument which don't change the request.
+const IGNORED: [&str; 29] = [
+    "-s",
+    "--silent",
+    "-S",
+    "--show-error",
+    "-L",
+    "--location",
+    "-k",
+    "--insecure",
+    "-v",
+    "--verbose",
+    "-i",
+    "--include",
+    "-f",
+    "--fail",
+    "--fail-with-body",
+    "-g",
+    "--globoff",
+    "-N",
+    "--no-buffer",
+    "-#",
+    "--progress-bar",
+    "--no-progress-meter",
+    "-q",
+    "--compressed",
+    "--path-as-is",
+    "--http1.1",
+    "--tlsv1.2",
+    "--tlsv1.3",
+    "-Z",
+];
+
+/// Imports a request from a curl command, or from a file containing a curl command or a raw
+/// HTTP/1.1 request.
+pub fn import(input: &str) -> Result<Req, String> {
+    let input = input.trim();
+    if input.starts_with("curl ") {
+        return parse_curl(input);
+    }
+
+    let contents =
+        std::fs::read(input).map_err(|e| format!("Unable to read '{}'. {}", input, e))?;
+    let text = String::from_utf8_lossy(&contents);
+    if text.trim_start().starts_with("curl ") {
+        parse_curl(text.trim())
+    } else {
+        parse_raw(&contents)
+    }
+}
+
+/// Checks if a curl command continues on the next line, because it ends with a backslash or in
+/// a quoted string. Pasted commands arrive one line at a time.
+pub fn is_continued(input: &str) -> bool {
+    if !input.trim_start().starts_with("curl ") {
+        return false;
+    }
+    let backslashes = input.chars().rev().take_while(|&c| c == '\\').count();
+    backslashes % 2 == 1 || matches!(shell_split(input), Err(e) if e.starts_with("Unterminated"))
+}
+
+/// Parses a curl command line.
+pub fn parse_curl(command: &str) -> Result<Req, String> {
+    let mut args: VecDeque<String> = shell_split(command)?.into_iter().skip(1).collect();
+
+    let mut url = None;
+    let mut method = None;
+    let mut headers: Vec<(String, String)> = vec![];
+    let mut data: Vec<Vec<u8>> = vec![];
+    let mut get = false;
+    let mut version = Version::HTTP_11;
+
+    while let Some(arg) = args.pop_front() {
+        // Short options can be combined, like `-sSL`, and the last one can take its argument
+        // attached, like `-XPOST` or `-sH 'X-Test: 1'`.
+        if arg.starts_with('-') && !arg.starts_with("--") && arg.chars().count() > 2 {
+            let split = 1 + arg[1..].chars().next().map_or(0, char::len_utf8);
+            let (option, rest) = arg.split_at(split);
+            if takes_argument(option) {
+                args.push_front(rest.to_string());
+            } else {
+                args.push_front(format!("-{}", rest));
+            }
+            args.push_front(option.to_string());
+            continue;
+        }
+
+        let option = arg.as_str();
+        let mut value = || {
+            args.pop_front()
+                .ok_or_else(|| format!("Missing argument for {}", option))
+        };
+
+        match option {
+            "-X" | "--request" => method = Some(value()?),
+            "-H" | "--header" => {
+                let header = value()?;
+                let (name, value) = header
+                    .split_once(':')
+                    .ok_or_else(|| format!("Invalid header '{}'", header))?;
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+            "-A" | "--user-agent" => headers.push(("User-Agent".to_string(), value()?)),
+            "-e" | "--referer" => headers.push(("Referer".to_string(), value()?)),
+            "-b" | "--cookie" => {
+                let cookie = value()?;
+                if !cookie.contains('=') {
+                    return Err(format!("Cookie files are not supported ('{}')", cookie));
+                }
+                headers.push(("Cookie".to_string(), cookie));
+            }
+            "-u" | "--user" => {
+                let credentials = base64::encode(value()?);
+                headers.push((
+                    "Authorization".to_string(),
+                    format!("Basic {}", credentials),
+                ));
+            }
+            "-d" | "--data" | "--data-ascii" | "--data-binary" => {
+                let value = value()?;
+                match value.strip_prefix('@') {
+                    Some(path) => data.push(
+                        std::fs::read(path)
+                            .map_err(|e| format!("Unable to read '{}'. {}", path, e))?,
+                    ),
+                    None => data.push(value.as_bytes().to_vec()),
+                }
+            }
+            "--data-raw" => data.push(value()?.as_bytes().to_vec()),
+            "--data-urlencode" => data.push(urlencode_data(&value()?).into_bytes()),
+            "--json" => {
+                data.push(value()?.as_bytes().to_vec());
+                headers.push(("Content-Type".to_string(), "application/json".to_string()));
+                headers.push(("Accept".to_string(), "application/json".to_string()));
+            }
+            "-F" | "--form" => return Err("Multipart forms (-F) are not supported".to_string()),
+            "-G" | "--get" => get = true,
+            "-I" | "--head" => method = Some("HEAD".to_string()),
+            "--url" => url = Some(value()?),
+            "--http2" | "--http2-prior-knowledge" => version = Version::HTTP_2,
+            "--http1.0" | "-0" => version = Version::HTTP_10,
+            o if IGNORED_WITH_ARG.contains(&o) => {
+                value()?;
+            }
+            o if IGNORED.contains(&o) => {}
+            o if o.starts_with('-') => return Err(format!("Unsupported curl option {}", o)),
+            u => url = Some(u.to_string()),
+        }
+    }
+
+    let mut url = url.ok_or("No URL in curl command")?;
+    if !url.contains("://") {
+        url = format!("http://{}", url);
+    }
+
+    let body = data.join(&b'&');
+    let mut req_body = vec![];
+    if get && !body.is_empty() {
+        url += if url.contains('?') { "&" } else { "?" };
+        url += &String::from_utf8_lossy(&body);
+    } else if !body.is_empty() {
+        if !has_header(&headers, "content-type") {
+            headers.push((
+                "Content-Type".to_string(),
+                "application/x-www-form-urlencoded".to_string(),
+            ));
+        }
+        req_body = body;
+    }
+
+    let method =
+        method.unwrap_or_else(|| if req_body.is_empty() { "GET" } else { "POST" }.to_string());
+    build(method, url, headers, req_body, version)
+}
+
+fn takes_argument(option: &str) -> bool {
+    SHORT_WITH_ARG.contains(&option) || IGNORED_WITH_ARG.contains(&option)
+}
+
+/// Parses a raw HTTP/1.1 request. The request is sent over HTTPS unless the target is an absolute
+/// URL or the Host header has port 80.
+pub fn parse_raw(bytes: &[u8]) -> Result<Req, String> {
+    let (head, body) = match find(bytes, b"\r\n\r\n") {
+        Some(i) => (&bytes[..i], &bytes[i + 4..]),
+        None => match find(bytes, b"\n\n") {
+            Some(i) => (&bytes[..i], &bytes[i + 2..]),
+            None => (bytes, &[][..]),
+        },
+    };
+    let head = std::str::from_utf8(head).map_err(|_| "Request head is not valid UTF-8")?;
+    let mut lines = head.lines().map(|l| l.trim_end_matches('\r'));
+
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(m), Some(t)) => (m.to_string(), t.to_string()),
+        _ => return Err(format!("Invalid request line '{}'", request_line)),
+    };
+    let version = match parts.next() {
+        Some("HTTP/1.0") => Version::HTTP_10,
+        _ => Version::HTTP_11,
+    };
+
+    let mut headers = vec![];
+    for line in lines.filter(|l| !l.is_empty()) {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid header '{}'", line))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut body = body.to_vec();
+    let length = header(&headers, "content-length").and_then(|l| l.parse::<usize>().ok());
+    if let Some(length) = length {
+        body.truncate(length);
+    }
+
+    let url = if target.contains("://") {
+        target
+    } else {
+        let host = header(&headers, "host").ok_or("No Host header in request")?;
+        let scheme = if host.ends_with(":80") {
+            "http"
+        } else {
+            "https"
+        };
+        format!("{}://{}{}", scheme, host, target)
+    };
+    build(method, url, headers, body, version)
+}
+
+fn build(
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    version: Version,
+) -> Result<Req, String> {
+    let uri = url
+        .parse::<Uri>()
+        .map_err(|e| format!("Invalid URL '{}'. {}", url, e))?;
+    let host = uri
+        .host()
+        .ok_or_else(|| format!("No host in URL '{}'", url))?;
+
+    Ok(Req {
+        host: host.to_string(),
+        method,
+        url,
+        headers,
+        body,
+        client_version: version,
+        timestamp: Some(OffsetDateTime::now_utc()),
+        ..Default::default()
+    })
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+fn has_header(headers: &[(String, String)], name: &str) -> bool {
+    header(headers, name).is_some()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Encodes a `--data-urlencode` value, which is `content`, `name=content` or `=content`.
+fn urlencode_data(value: &str) -> String {
+    let encode = |s: &str| form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>();
+    match value.split_once('=') {
+        Some(("", content)) => encode(content),
+        Some((name, content)) => format!("{}={}", name, encode(content)),
+        None => encode(value),
+    }
+}
+
+/// Splits a command line into arguments like a POSIX shell, including backslash line
+/// continuations and `$'...'` strings.
+fn shell_split(command: &str) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    let mut arg: Option<String> = None;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let s = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => s.push(c),
+                        None => return Err("Unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let s = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => s.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                s.push('\\');
+                                s.push(c);
+                            }
+                            None => return Err("Unterminated double quote".to_string()),
+                        },
+                        Some(c) => s.push(c),
+                        None => return Err("Unterminated double quote".to_string()),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                let s = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') => s.push(ansi_c_escape(&mut chars)?),
+                        Some(c) => s.push(c),
+                        None => return Err("Unterminated $' quote".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') | Some('\r') => {}
+                Some(c) => arg.get_or_insert_with(String::new).push(c),
+                None => {}
+            },
+            c if c.is_whitespace() => {
+                if let Some(a) = arg.take() {
+                    args.push(a);
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(a) = arg {
+        args.push(a);
+    }
+    Ok(args)
+}
+
+/// Reads the escape sequence after a backslash in a `$'...'` string.
+fn ansi_c_escape(chars: &mut impl Iterator<Item = char>) -> Result<char, String> {
+    let c = match chars.next() {
+        Some('n') => '\n',
+        Some('r') => '\r',
+        Some('t') => '\t',
+        Some('0') => '\0',
+        Some('e') => '\x1b',
+        Some('x') => {
+            let hex: String = chars.take(2).collect();
+            let byte =
+                u8::from_str_radix(&hex, 16).map_err(|_| format!("Invalid escape '\\x{}'", hex))?;
+            char::from(byte)
+        }
+        Some(c) => c,
+        None => return Err("Unterminated $' quote".to_string()),
+    };
+    Ok(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header<'a>(req: &'a Req, name: &str) -> Option<&'a str> {
+        super::header(&req.headers, name)
+    }
+
+    fn curl_error(command: &str) -> String {
+        match parse_curl(command) {
+            Ok(_) => panic!("Parsed '{}'", command),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn splits_like_a_shell() {
+        let args = shell_split("curl 'a b' \"c \\\"d\\\"\" e\\ f \\\n  $'g\\nh'").unwrap();
+        assert_eq!(args, ["curl", "a b", "c \"d\"", "e f", "g\nh"]);
+        assert_eq!(shell_split("a''b \"\"").unwrap(), ["ab", ""]);
+        assert_eq!(
+            shell_split("curl 'a").unwrap_err(),
+            "Unterminated single quote"
+        );
+        assert_eq!(
+            shell_split("curl \"a").unwrap_err(),
+            "Unterminated double quote"
+        );
+    }
+
+    #[test]
+    fn reads_ansi_c_escapes() {
+        let escape = |s: &str| ansi_c_escape(&mut s.chars());
+        assert_eq!(escape("n"), Ok('\n'));
+        assert_eq!(escape("t"), Ok('\t'));
+        assert_eq!(escape("x41"), Ok('A'));
+        assert_eq!(escape("'"), Ok('\''));
+        assert_eq!(escape("xzz"), Err("Invalid escape '\\xzz'".to_string()));
+        assert!(escape("").is_err());
+    }
+
+    #[test]
+    fn parses_curl_commands() {
+        let req = parse_curl(
+            "curl -X PUT 'https://a.test/x?y=1' -H 'X-Test: 1' -b 'a=1' -u user:pass \
+             -A agent --data-raw '{\"a\":1}' -H 'Content-Type: application/json'",
+        )
+        .unwrap();
+        assert_eq!(req.method, "PUT");
+        assert_eq!(req.url, "https://a.test/x?y=1");
+        assert_eq!(req.host, "a.test");
+        assert_eq!(header(&req, "x-test"), Some("1"));
+        assert_eq!(header(&req, "cookie"), Some("a=1"));
+        assert_eq!(header(&req, "authorization"), Some("Basic dXNlcjpwYXNz"));
+        assert_eq!(header(&req, "user-agent"), Some("agent"));
+        assert_eq!(header(&req, "content-type"), Some("application/json"));
+        assert_eq!(req.body, b"{\"a\":1}");
+    }
+
+    #[test]
+    fn parses_data_options() {
+        let req = parse_curl("curl a.test -d a=1 --data-urlencode 'b=x y'").unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.url, "http://a.test");
+        assert_eq!(req.body, b"a=1&b=x+y");
+        assert_eq!(
+            header(&req, "content-type"),
+            Some("application/x-www-form-urlencoded")
+        );
+
+        let req = parse_curl("curl -G http://a.test/?c=3 -d a=1 -d b=2").unwrap();
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.url, "http://a.test/?c=3&a=1&b=2");
+        assert!(req.body.is_empty());
+
+        let req = parse_curl("curl -I http://a.test/").unwrap();
+        assert_eq!(req.method, "HEAD");
+    }
+
+    #[test]
+    fn parses_combined_short_options() {
+        let req =
+            parse_curl("curl -sSLk -sH 'X-Test: 1' -XPOST -d@/dev/null http://a.test/").unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(header(&req, "x-test"), Some("1"));
+        assert_eq!(req.url, "http://a.test/");
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        assert_eq!(
+            curl_error("curl --frobnicate 5 http://a.test/"),
+            "Unsupported curl option --frobnicate"
+        );
+        assert_eq!(
+            curl_error("curl -sz http://a.test/"),
+            "Unsupported curl option -z"
+        );
+
+        let req = parse_curl("curl --max-redirs 5 -o /dev/null http://a.test/").unwrap();
+        assert_eq!(req.url, "http://a.test/");
+        assert!(parse_curl("curl -H").is_err());
+        assert!(parse_curl("curl -F a=1 http://a.test/").is_err());
+        assert_eq!(curl_error("curl -s"), "No URL in curl command");
+    }
+
+    #[test]
+    fn detects_continued_commands() {
+        assert!(is_continued("curl http://a.test/ \\"));
+        assert!(is_continued("curl http://a.test/ -d 'a"));
+        assert!(!is_continued("curl http://a.test/ \\\\"));
+        assert!(!is_continued("curl http://a.test/"));
+        assert!(!is_continued("/tmp/request.txt\\"));
+
+        let pasted = "curl http://a.test/ \\\n  -H 'X-Test: 1' \\\n  -d a=1";
+        let req = parse_curl(pasted).unwrap();
+        assert_eq!(header(&req, "x-test"), Some("1"));
+        assert_eq!(req.body, b"a=1");
+    }
+
+    #[test]
+    fn parses_raw_requests() {
+        let req = parse_raw(
+            b"POST /login?a=1 HTTP/1.1\r\nHost: a.test\r\nContent-Length: 3\r\n\r\nx=1extra",
+        )
+        .unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.url, "https://a.test/login?a=1");
+        assert_eq!(req.body, b"x=1");
+        assert_eq!(req.client_version, Version::HTTP_11);
+
+        let req = parse_raw(b"GET / HTTP/1.0\nHost: a.test:80\n\n").unwrap();
+        assert_eq!(req.url, "http://a.test:80/");
+        assert_eq!(req.client_version, Version::HTTP_10);
+
+        let req = parse_raw(b"GET http://b.test/x HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(req.host, "b.test");
+
+        assert!(parse_raw(b"GET / HTTP/1.1\r\n\r\n").is_err());
+        assert!(parse_raw(b"GET\r\n\r\n").is_err());
+        assert!(parse_raw(b"GET / HTTP/1.1\r\nHost a.test\r\n\r\n").is_err());
+    }
+}
@@ -0,0 +1,423 @@
+use crate::rudy_tui::Req;
+use regex::Regex;
+use ring::{
+    hmac,
+    rand::SystemRandom,
+    signature::{self, EcdsaKeyPair, RsaKeyPair},
+};
+use rustls_pemfile as pemfile;
+use serde_json::{Map, Value};
+use std::sync::OnceLock;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// Spellings of the `none` algorithm. Some libraries only reject the lower case one.
+pub const NONE_VARIANTS: [&str; 4] = ["none", "None", "NONE", "nOnE"];
+
+/// Claims holding a time, and how they are labeled.
+const TIME_CLAIMS: [(&str, &str); 3] =
+    [("iat", "Issued"), ("nbf", "Not before"), ("exp", "Expires")];
+
+/// A JSON Web Token split into its decoded header and claims.
+#[derive(Clone)]
+pub struct Jwt {
+    pub token: String,
+    pub header: Map<String, Value>,
+    pub claims: Map<String, Value>,
+    pub signature: String,
+}
+
+impl Jwt {
+    /// Parses a JWT. Returns None if the header and claims are not Base64url encoded JSON objects.
+    pub fn parse(token: &str) -> Option<Jwt> {
+        let mut parts = token.split('.');
+        let (header, claims, signature) = (parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some() {
+            return None;
+        }
+
+        let object = |part: &str| -> Option<Map<String, Value>> {
+            let json = base64::decode_config(part, base64::URL_SAFE_NO_PAD).ok()?;
+            match serde_json::from_slice(&json).ok()? {
+                Value::Object(map) => Some(map),
+                _ => None,
+            }
+        };
+        Some(Jwt {
+            token: token.to_string(),
+            header: object(header)?,
+            claims: object(claims)?,
+            signature: signature.to_string(),
+        })
+    }
+
+    pub fn alg(&self) -> &str {
+        self.header
+            .get("alg")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown")
+    }
+
+    pub fn describe(&self) -> String {
+        describe(&self.header, &self.claims)
+    }
+}
+
+/// Describes a header and claims as pretty JSON, followed by the times in the claims.
+pub fn describe(header: &Map<String, Value>, claims: &Map<String, Value>) -> String {
+    let pretty = |map: &Map<String, Value>| {
+        serde_json::to_string_pretty(map).expect("Failed to serialize JSON")
+    };
+    let mut p = format!("Header\n{}\n\nClaims\n{}\n", pretty(header), pretty(claims));
+
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let mut times = String::new();
+    for (claim, label) in TIME_CLAIMS {
+        let timestamp = match claims.get(claim).and_then(Value::as_i64) {
+            Some(t) => t,
+            None => continue,
+        };
+        let time = OffsetDateTime::from_unix_timestamp(timestamp)
+            .ok()
+            .and_then(|t| t.format(&Rfc3339).ok())
+            .unwrap_or_else(|| "invalid time".to_string());
+        let relative = if timestamp < now {
+            format!("{} ago", duration(now - timestamp))
+        } else {
+            format!("in {}", duration(timestamp - now))
+        };
+        times += &format!("  {}: {} ({})", label, time, relative);
+        if claim == "exp" && timestamp < now {
+            times += ", expired";
+        }
+        times += "\n";
+    }
+    if !times.is_empty() {
+        p += &format!("\nTimes\n{}", times);
+    }
+    p
+}
+
+/// Formats a number of seconds with its two largest units, such as `3d 4h`.
+fn duration(seconds: i64) -> String {
+    let (days, hours) = (seconds / 86_400, seconds % 86_400 / 3_600);
+    let (minutes, seconds) = (seconds % 3_600 / 60, seconds % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{}s", seconds),
+        (0, 0, m) => format!("{}m {}s", m, seconds),
+        (0, h, m) => format!("{}h {}m", h, m),
+        (d, h, _) => format!("{}d {}h", d, h),
+    }
+}
+
+/// A JWT found in an exchange and where it was found.
+pub struct Found {
+    pub location: String,
+    pub jwt: Jwt,
+}
+
+fn token_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    // The header and claims are JSON objects, so they start with the encoding of `{"`.
+    REGEX.get_or_init(|| {
+        Regex::new(r"eyJ[A-Za-z0-9_-]*\.eyJ[A-Za-z0-9_-]*\.[A-Za-z0-9_-]*")
+            .expect("Failed to compile JWT regex")
+    })
+}
+
+/// Finds the JWTs in the URL, headers, cookies and bodies of an exchange. Each token is only
+/// listed where it is found first.
+pub fn find(req: &Req) -> Vec<Found> {
+    let mut found: Vec<Found> = vec![];
+    let mut search = |location: String, text: &str| {
+        for m in token_regex().find_iter(text) {
+            if found.iter().any(|f| f.jwt.token == m.as_str()) {
+                continue;
+            }
+            if let Some(jwt) = Jwt::parse(m.as_str()) {
+                found.push(Found {
+                    location: location.clone(),
+                    jwt,
+                });
+            }
+        }
+    };
+
+    search("URL".to_string(), &req.url);
+    for (name, value) in &req.headers {
+        if name.eq_ignore_ascii_case("cookie") {
+            for cookie in value.split(';') {
+                let (name, value) = cookie.trim().split_once('=').unwrap_or((cookie, ""));
+                search(format!("Cookie {}", name), value);
+            }
+        } else {
+            search(format!("{} header", name), value);
+        }
+    }
+    search(
+        "Request body".to_string(),
+        &String::from_utf8_lossy(&req.body),
+    );
+    for (name, value) in &req.res_headers {
+        if name.eq_ignore_ascii_case("set-cookie") {
+            let name = value.split('=').next().unwrap_or_default().trim();
+            search(format!("Set-Cookie {}", name), value);
+        } else {
+            search(format!("{} response header", name), value);
+        }
+    }
+    search(
+        "Response body".to_string(),
+        &String::from_utf8_lossy(&req.res_body),
+    );
+    found
+}
+
+/// How an edited token is signed.
+pub enum Signing {
+    /// Keeps the signature of the original token, which no longer matches once it is edited.
+    Original(String),
+    /// Sets `alg` to a spelling of `none` and leaves out the signature.
+    None(&'static str),
+    /// Signs with HMAC and a secret.
+    Hmac(Vec<u8>),
+    /// Signs with an RSA or ECDSA private key.
+    PrivateKey(Box<PrivateKey>),
+    /// Signs with HMAC using the server's public key as the secret, for servers which verify
+    /// tokens with the algorithm named in the token.
+    KeyConfusion(Vec<u8>),
+}
+
+pub enum PrivateKey {
+    Rsa(RsaKeyPair),
+    /// An ECDSA key and the algorithm of its curve.
+    Ecdsa(EcdsaKeyPair, &'static str),
+}
+
+impl Signing {
+    pub fn describe(&self) -> String {
+        match self {
+            Signing::Original(_) => "original signature".to_string(),
+            Signing::None(alg) => format!("unsigned with alg {}", alg),
+            Signing::Hmac(_) => "HMAC with a secret".to_string(),
+            Signing::PrivateKey(key) => match **key {
+                PrivateKey::Rsa(_) => "RSA private key".to_string(),
+                PrivateKey::Ecdsa(_, alg) => format!("{} private key", alg),
+            },
+            Signing::KeyConfusion(_) => "HMAC with the public key (key confusion)".to_string(),
+        }
+    }
+}
+
+/// Loads an RSA or ECDSA private key from a PEM file. RSA keys can be PKCS#1 or PKCS#8 and ECDSA
+/// keys PKCS#8.
+pub fn load_private_key(path: &str) -> Result<PrivateKey, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Unable to read '{}'. {}", path, e))?;
+    let items = pemfile::read_all(&mut bytes.as_slice())
+        .map_err(|e| format!("Invalid PEM file '{}'. {}", path, e))?;
+
+    for item in items {
+        match item {
+            pemfile::Item::RSAKey(der) => {
+                return RsaKeyPair::from_der(&der)
+                    .map(PrivateKey::Rsa)
+                    .map_err(|e| format!("Invalid RSA key. {}", e));
+            }
+            pemfile::Item::PKCS8Key(der) => {
+                if let Ok(key) = RsaKeyPair::from_pkcs8(&der) {
+                    return Ok(PrivateKey::Rsa(key));
+                }
+                let curves = [
+                    (&signature::ECDSA_P256_SHA256_FIXED_SIGNING, "ES256"),
+                    (&signature::ECDSA_P384_SHA384_FIXED_SIGNING, "ES384"),
+                ];
+                for (curve, alg) in curves {
+                    if let Ok(key) = EcdsaKeyPair::from_pkcs8(curve, &der) {
+                        return Ok(PrivateKey::Ecdsa(key, alg));
+                    }
+                }
+                return Err("Unsupported PKCS#8 key. Use an RSA, P-256 or P-384 key".to_string());
+            }
+            pemfile::Item::ECKey(_) => {
+                return Err(
+                    "SEC1 EC keys are not supported. Convert the key to PKCS#8 with \
+                     `openssl pkcs8 -topk8 -nocrypt`"
+                        .to_string(),
+                );
+            }
+            _ => {}
+        }
+    }
+    Err(format!("No private key in '{}'", path))
+}
+
+/// Encodes a header and claims into a token signed as given. The `alg` of the header is set to
+/// match the signing, keeping its hash size where the header already names one of the family.
+pub fn encode(
+    header: &Map<String, Value>,
+    claims: &Map<String, Value>,
+    signing: &Signing,
+) -> Result<String, String> {
+    let mut header = header.clone();
+    let current = header
+        .get("alg")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let hmac_alg = match current.as_str() {
+        "HS384" | "HS512" => current.clone(),
+        _ => "HS256".to_string(),
+    };
+    let alg = match signing {
+        Signing::Original(_) => current,
+        Signing::None(alg) => alg.to_string(),
+        Signing::Hmac(_) | Signing::KeyConfusion(_) => hmac_alg,
+        Signing::PrivateKey(key) => match **key {
+            PrivateKey::Rsa(_) => match current.as_str() {
+                "RS384" | "RS512" | "PS256" | "PS384" | "PS512" => current,
+                _ => "RS256".to_string(),
+            },
+            PrivateKey::Ecdsa(_, alg) => alg.to_string(),
+        },
+    };
+    header.insert("alg".to_string(), Value::String(alg.clone()));
+
+    let part = |map: &Map<String, Value>| {
+        let json = serde_json::to_vec(map).expect("Failed to serialize JSON");
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    };
+    let input = format!("{}.{}", part(&header), part(claims));
+
+    let signature = match signing {
+        Signing::Original(signature) => return Ok(format!("{}.{}", input, signature)),
+        Signing::None(_) => vec![],
+        Signing::Hmac(key) | Signing::KeyConfusion(key) => {
+            let algorithm = match alg.as_str() {
+                "HS384" => hmac::HMAC_SHA384,
+                "HS512" => hmac::HMAC_SHA512,
+                _ => hmac::HMAC_SHA256,
+            };
+            let key = hmac::Key::new(algorithm, key);
+            hmac::sign(&key, input.as_bytes()).as_ref().to_vec()
+        }
+        Signing::PrivateKey(key) => match &**key {
+            PrivateKey::Rsa(key) => {
+                let padding: &'static dyn signature::RsaEncoding = match alg.as_str() {
+                    "RS384" => &signature::RSA_PKCS1_SHA384,
+                    "RS512" => &signature::RSA_PKCS1_SHA512,
+                    "PS256" => &signature::RSA_PSS_SHA256,
+                    "PS384" => &signature::RSA_PSS_SHA384,
+                    "PS512" => &signature::RSA_PSS_SHA512,
+                    _ => &signature::RSA_PKCS1_SHA256,
+                };
+                let mut signature = vec![0; key.public_modulus_len()];
+                key.sign(
+                    padding,
+                    &SystemRandom::new(),
+                    input.as_bytes(),
+                    &mut signature,
+                )
+                .map_err(|_| "Unable to sign with the RSA key".to_string())?;
+                signature
+            }
+            PrivateKey::Ecdsa(key, _) => key
+                .sign(&SystemRandom::new(), input.as_bytes())
+                .map_err(|_| "Unable to sign with the ECDSA key".to_string())?
+                .as_ref()
+                .to_vec(),
+        },
+    };
+    Ok(format!(
+        "{}.{}",
+        input,
+        base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn map(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => panic!("Not an object"),
+        }
+    }
+
+    #[test]
+    fn parse_decodes_header_and_claims() {
+        let jwt = Jwt::parse("eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJhIn0.c2ln").unwrap();
+        assert_eq!(jwt.alg(), "HS256");
+        assert_eq!(jwt.claims, map(json!({"sub": "a"})));
+        assert_eq!(jwt.signature, "c2ln");
+    }
+
+    #[test]
+    fn parse_rejects_invalid_tokens() {
+        assert!(Jwt::parse("eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJhIn0").is_none());
+        assert!(Jwt::parse("eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJhIn0.a.b").is_none());
+        // The claims are the JSON array [1].
+        assert!(Jwt::parse("eyJhbGciOiJIUzI1NiJ9.WzFd.").is_none());
+        assert!(Jwt::parse("eyJhbGciOiJIUzI1NiJ9.!!!.").is_none());
+    }
+
+    #[test]
+    fn encode_signs_with_hmac() {
+        let header = map(json!({"alg": "HS512", "typ": "JWT"}));
+        let claims = map(json!({"sub": "a"}));
+        let token = encode(&header, &claims, &Signing::Hmac(b"secret".to_vec())).unwrap();
+
+        let jwt = Jwt::parse(&token).unwrap();
+        assert_eq!(jwt.alg(), "HS512");
+        assert_eq!(jwt.claims, claims);
+        let (input, signature) = token.rsplit_once('.').unwrap();
+        let key = hmac::Key::new(hmac::HMAC_SHA512, b"secret");
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).unwrap();
+        assert!(hmac::verify(&key, input.as_bytes(), &signature).is_ok());
+    }
+
+    #[test]
+    fn encode_sets_alg_for_the_signing() {
+        let header = map(json!({"alg": "RS256"}));
+        let claims = map(json!({}));
+
+        let token = encode(&header, &claims, &Signing::None("nOnE")).unwrap();
+        assert!(token.ends_with('.'));
+        assert_eq!(Jwt::parse(&token).unwrap().alg(), "nOnE");
+
+        let signing = Signing::KeyConfusion(b"public key".to_vec());
+        let token = encode(&header, &claims, &signing).unwrap();
+        assert_eq!(Jwt::parse(&token).unwrap().alg(), "HS256");
+
+        let signing = Signing::Original("c2ln".to_string());
+        let token = encode(&header, &claims, &signing).unwrap();
+        assert_eq!(Jwt::parse(&token).unwrap().signature, "c2ln");
+    }
+
+    #[test]
+    fn find_lists_each_token_once() {
+        let token = "eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJhIn0.c2ln";
+        let req = Req {
+            url: format!("http://a.test/?t={}", token),
+            headers: vec![(
+                "Cookie".to_string(),
+                format!("a=1; session={}", token.replace("c2ln", "b3RoZXI")),
+            )],
+            res_body: format!("{{\"token\":\"{}\"}}", token).into_bytes(),
+            ..Req::default()
+        };
+        let found = find(&req);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].location, "URL");
+        assert_eq!(found[1].location, "Cookie session");
+    }
+
+    #[test]
+    fn durations_show_two_units() {
+        assert_eq!(duration(5), "5s");
+        assert_eq!(duration(65), "1m 5s");
+        assert_eq!(duration(3_720), "1h 2m");
+        assert_eq!(duration(90_061), "1d 1h");
+    }
+}
@@ -0,0 +1,148 @@
+//! Rudy is an intercepting proxy with a terminal UI. The `rudy` binary runs it as configured in
+//! `rudy.toml`. Teams can build their own binary with extensions added by a [`Builder`].
+//!
+//! The hooks of extensions take the hyper and hudsucker types of the exchanges, which are
+//! re-exported here. Rudy logs with `tracing`, and the binary installs the subscriber.
+
+use extension::ExtensionMessages;
+use hudsucker::ProxyBuilder;
+use passthrough::{ClientAddrs, InternalProxy, Passthrough};
+use settings::Settings;
+use std::{error::Error, net::SocketAddr, sync::Arc};
+use tokio::{net::TcpListener, sync::mpsc};
+
+mod active_scan;
+mod authority;
+mod extension;
+mod import;
+mod jwt;
+mod passthrough;
+mod project;
+mod proxy;
+mod repeater;
+mod rudy_tui;
+mod scanner;
+mod scripts;
+mod settings;
+mod upstream;
+
+pub use extension::{Direction, Exchange, ExtensionAction, ExtensionTab, RudyExtension};
+pub use hudsucker::{
+    async_trait::async_trait,
+    hyper::{Body, Request, Response},
+    tungstenite::Message,
+    HttpContext, MessageContext, RequestOrResponse,
+};
+pub use scanner::{Issue, Severity};
+
+async fn shutdown_signal() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install CTRL+C signal handler");
+}
+
+/// Builds and runs the proxy with a set of extensions.
+pub struct Builder {
+    settings_path: String,
+    extensions: Vec<Box<dyn RudyExtension>>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder {
+            settings_path: settings::SETTINGS_PATH.to_string(),
+            extensions: vec![],
+        }
+    }
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder::default()
+    }
+
+    /// Reads the settings from another file than `rudy.toml`.
+    pub fn settings_path(mut self, path: &str) -> Self {
+        self.settings_path = path.to_string();
+        self
+    }
+
+    /// Adds an extension. Extensions are called in the order they were added.
+    pub fn extension(mut self, extension: impl RudyExtension) -> Self {
+        self.extensions.push(Box::new(extension));
+        self
+    }
+
+    /// Runs the proxy and the terminal UI until the UI is closed. Returns an error if the settings,
+    /// project or CA cannot be loaded, the proxy cannot listen or stops, or the terminal fails.
+    pub async fn run(self) -> Result<(), Box<dyn Error>> {
+        let extensions: extension::Extensions = self.extensions.into();
+        let settings = Settings::load(&self.settings_path)?;
+        let passthrough = Arc::new(Passthrough::new(&settings));
+
+        let (tx, mut rx) = mpsc::channel(16);
+
+        let history = match &settings.project {
+            Some(path) => project::load(path)?,
+            None => vec![],
+        };
+        let client = upstream::client(&settings)?;
+        let scanner = active_scan::ActiveScanner::start(&settings, client.clone(), tx.clone());
+        let repeater = repeater::Repeater::new(client.clone(), tx.clone());
+        let app = rudy_tui::App::new(&mut rx, &settings, history, scanner, repeater, &extensions)?;
+
+        let ca = proxy::load_ca(&settings)?;
+        let scripts = settings.scripts_dir.as_deref().map(scripts::Scripts::load);
+
+        // Clients connect to the passthrough listener, which hands everything it does not tunnel
+        // itself to the intercepting proxy on an internal port.
+        let listen_addr = SocketAddr::from(([127, 0, 0, 1], settings.listen_port));
+        let listener = TcpListener::bind(listen_addr)
+            .await
+            .map_err(|e| format!("Unable to listen on {}. {}", listen_addr, e))?;
+
+        let (proxy_listener, proxy_addr) = std::net::TcpListener::bind(("127.0.0.1", 0))
+            .and_then(|l| l.local_addr().map(|addr| (l, addr)))
+            .map_err(|e| format!("Unable to start the internal proxy listener. {}", e))?;
+
+        let internal = InternalProxy {
+            addr: proxy_addr,
+            authority: ca.clone(),
+            clients: ClientAddrs::default(),
+        };
+        let proxy = ProxyBuilder::new()
+            .with_listener(proxy_listener)
+            .with_client(client.clone())
+            .with_ca(ca)
+            .with_http_handler(proxy::LogHandler::new(
+                tx.clone(),
+                client,
+                internal.clients.clone(),
+                listen_addr.to_string(),
+                scripts,
+                Arc::clone(&extensions),
+            ))
+            .with_incoming_message_handler(ExtensionMessages::new(
+                Arc::clone(&extensions),
+                Direction::ToClient,
+                internal.clients.clone(),
+            ))
+            .with_outgoing_message_handler(ExtensionMessages::new(
+                Arc::clone(&extensions),
+                Direction::ToServer,
+                internal.clients.clone(),
+            ))
+            .build();
+
+        let proxy = tokio::spawn(proxy.start(shutdown_signal()));
+        tokio::spawn(passthrough::serve(listener, internal, passthrough, tx));
+
+        rudy_tui::run(app, async move {
+            match proxy.await {
+                Ok(res) => res.map_err(|e| format!("The proxy stopped. {}", e)),
+                Err(e) => Err(format!("The proxy stopped. {}", e)),
+            }
+        })
+        .await
+    }
+}
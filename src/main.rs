@@ -1,41 +1,15 @@
-use hudsucker::ProxyBuilder;
-use std::net::SocketAddr;
-use tokio::sync::mpsc;
-use tracing::error;
-
-mod proxy;
-mod rudy_tui;
-
-async fn shutdown_signal() {
-    tokio::signal::ctrl_c()
-        .await
-        .expect("Failed to install CTRL+C signal handler");
-}
+use std::process::exit;
+use tracing_subscriber::{prelude::*, EnvFilter};
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
-
-    let (tx, mut rx) = mpsc::channel(16);
-    let listen_port = 8080;
-
-    let app = rudy_tui::App::new(&mut rx);
-
-    let ca = proxy::load_ca("cert/ca.crt", "cert/ca.key");
-
-    let proxy = ProxyBuilder::new()
-        .with_addr(SocketAddr::from(([127, 0, 0, 1], listen_port)))
-        .with_rustls_client()
-        .with_ca(ca)
-        .with_http_handler(proxy::LogHandler { tx })
-        .build();
-
-    // println!("Now listening on 127.0.0.1:{}", listen_port);
-
-    tokio::spawn(async move { proxy.start(shutdown_signal()).await });
-
-    // if let Err(e) = proxy.start(shutdown_signal()).await {
-    //     error!("{}", e);
-    // }
-    rudy_tui::run(app).await.unwrap();
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+
+    if let Err(e) = rudy::Builder::new().run().await {
+        eprintln!("{}", e);
+        exit(1)
+    }
 }
@@ -0,0 +1,448 @@
+use crate::{
+    authority::LeafAuthority,
+    rudy_tui::{Req, Tunnel},
+    settings::{host_matches, Settings},
+    upstream::NoVerification,
+};
+use hudsucker::{
+    certificate_authority::CertificateAuthority,
+    hyper::http::uri::Authority,
+    rustls::{ClientConfig, ServerName},
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+    task::{Context, Poll},
+    time::Instant,
+};
+use time::OffsetDateTime;
+use tokio::{
+    io::{copy_bidirectional, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::Sender,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::error;
+
+/// Maximum size of a request head read from a client before it is handed to the proxy.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Host patterns whose CONNECT tunnels are passed through without decrypting them.
+pub struct Passthrough {
+    patterns: RwLock<Vec<String>>,
+    failures: Mutex<HashMap<String, u32>>,
+    auto_after: Option<u32>,
+}
+
+impl Passthrough {
+    pub fn new(settings: &Settings) -> Self {
+        Passthrough {
+            patterns: RwLock::new(settings.passthrough.clone()),
+            failures: Mutex::new(HashMap::new()),
+            auto_after: settings.auto_passthrough,
+        }
+    }
+
+    /// Checks if connections to a host should be passed through.
+    pub fn matches(&self, host: &str) -> bool {
+        self.patterns
+            .read()
+            .unwrap()
+            .iter()
+            .any(|p| host_matches(p, host))
+    }
+
+    /// Records a failed client TLS handshake for a host. The host is added to the list once the
+    /// number of failures reaches the `auto_passthrough` setting.
+    fn record_failure(&self, host: &str) {
+        let limit = match self.auto_after {
+            Some(l) => l,
+            None => return,
+        };
+
+        let mut failures = self.failures.lock().unwrap();
+        let count = failures.entry(host.to_lowercase()).or_insert(0);
+        *count += 1;
+
+        if *count >= limit && !self.matches(host) {
+            self.patterns.write().unwrap().push(host.to_lowercase());
+        }
+    }
+
+    /// Forgets the failed handshakes of a host once a client completes one, so only consecutive
+    /// failures count towards the `auto_passthrough` setting.
+    fn record_success(&self, host: &str) {
+        self.failures.lock().unwrap().remove(&host.to_lowercase());
+    }
+}
+
+/// Addresses of the clients whose connections were handed to the intercepting proxy, by the local
+/// address of the connection to the proxy. The proxy only sees connections from this listener.
+#[derive(Clone, Default)]
+pub struct ClientAddrs(Arc<Mutex<HashMap<SocketAddr, SocketAddr>>>);
+
+impl ClientAddrs {
+    /// Returns the address of the client behind a connection to the proxy.
+    pub fn get(&self, proxy_conn: SocketAddr) -> Option<SocketAddr> {
+        self.0.lock().unwrap().get(&proxy_conn).copied()
+    }
+}
+
+/// Entry of a connection in the [`ClientAddrs`], removed when the connection is closed.
+struct ClientAddr {
+    clients: ClientAddrs,
+    proxy_conn: SocketAddr,
+}
+
+impl ClientAddr {
+    fn new(clients: &ClientAddrs, proxy_conn: SocketAddr, client: SocketAddr) -> Self {
+        clients.0.lock().unwrap().insert(proxy_conn, client);
+        ClientAddr {
+            clients: clients.clone(),
+            proxy_conn,
+        }
+    }
+}
+
+impl Drop for ClientAddr {
+    fn drop(&mut self) {
+        self.clients.0.lock().unwrap().remove(&self.proxy_conn);
+    }
+}
+
+/// The intercepting proxy which connections are handed to.
+#[derive(Clone)]
+pub struct InternalProxy {
+    pub addr: SocketAddr,
+    pub authority: LeafAuthority,
+    pub clients: ClientAddrs,
+}
+
+/// Accepts client connections. CONNECT requests for passthrough hosts are tunneled straight to
+/// the upstream server, everything else is handed to the intercepting proxy.
+pub async fn serve(
+    listener: TcpListener,
+    proxy: InternalProxy,
+    passthrough: Arc<Passthrough>,
+    tx: Sender<Req>,
+) {
+    let listener_addr = listener
+        .local_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+
+    loop {
+        let (client, client_addr) = match listener.accept().await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        let proxy = proxy.clone();
+        let passthrough = Arc::clone(&passthrough);
+        let tx = tx.clone();
+        let listener_addr = listener_addr.clone();
+        tokio::spawn(async move {
+            let conn = Conn {
+                client,
+                client_addr,
+                listener_addr,
+            };
+            if let Err(e) = handle_conn(conn, proxy, passthrough, tx).await {
+                error!("Connection error: {}", e);
+            }
+        });
+    }
+}
+
+/// A client connection accepted by the listener.
+struct Conn {
+    client: TcpStream,
+    client_addr: SocketAddr,
+    listener_addr: String,
+}
+
+async fn handle_conn(
+    mut conn: Conn,
+    proxy: InternalProxy,
+    passthrough: Arc<Passthrough>,
+    tx: Sender<Req>,
+) -> io::Result<()> {
+    let (head, rest) = read_head(&mut conn.client).await?;
+
+    let request_line = String::from_utf8_lossy(&head);
+    let mut parts = request_line.split_whitespace();
+    let connect = match (parts.next(), parts.next()) {
+        (Some("CONNECT"), Some(target)) => target.parse::<Authority>().ok(),
+        _ => None,
+    };
+    if let Some(authority) = &connect {
+        if passthrough.matches(authority.host()) {
+            return tunnel(conn, authority.clone(), rest, tx).await;
+        }
+    }
+
+    let mut proxy_conn = TcpStream::connect(proxy.addr).await?;
+    let _client_addr = ClientAddr::new(&proxy.clients, proxy_conn.local_addr()?, conn.client_addr);
+    proxy_conn.write_all(&head).await?;
+
+    match connect {
+        Some(authority) => {
+            let client = Prefixed::new(rest, conn.client);
+            intercept(client, proxy_conn, &proxy, &authority, &passthrough).await
+        }
+        None => {
+            proxy_conn.write_all(&rest).await?;
+            copy_bidirectional(&mut conn.client, &mut proxy_conn)
+                .await
+                .map(|_| ())
+        }
+    }
+}
+
+/// Relays a CONNECT tunnel to the intercepting proxy. TLS from the client is accepted here rather
+/// than by the proxy, so failed handshakes can be counted for the `auto_passthrough` setting.
+///
+/// hudsucker only serves connections from its own listener and does the TLS handshake of a tunnel
+/// itself, so the decrypted traffic is passed to it over a second TLS session. Its certificate is
+/// not verified, as the connection never leaves this process's loopback listener.
+async fn intercept(
+    mut client: Prefixed<TcpStream>,
+    mut proxy_conn: TcpStream,
+    proxy: &InternalProxy,
+    authority: &Authority,
+    passthrough: &Passthrough,
+) -> io::Result<()> {
+    let (response, rest) = read_head(&mut proxy_conn).await?;
+    client.write_all(&response).await?;
+    let mut proxy_conn = Prefixed::new(rest, proxy_conn);
+
+    if !client.starts_with(b"\x16\x03").await? {
+        copy_bidirectional(&mut client, &mut proxy_conn).await?;
+        return Ok(());
+    }
+
+    let server_config = proxy.authority.gen_server_config(authority).await;
+    let mut client = match TlsAcceptor::from(server_config).accept(client).await {
+        Ok(s) => {
+            passthrough.record_success(authority.host());
+            s
+        }
+        Err(e) => {
+            passthrough.record_failure(authority.host());
+            return Err(e);
+        }
+    };
+
+    let mut client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(NoVerification))
+        .with_no_client_auth();
+    client_config.alpn_protocols = client
+        .get_ref()
+        .1
+        .alpn_protocol()
+        .into_iter()
+        .map(<[u8]>::to_vec)
+        .collect();
+    let name = ServerName::try_from("rudy.internal").expect("Failed to parse server name");
+    let mut proxy_conn = TlsConnector::from(Arc::new(client_config))
+        .connect(name, proxy_conn)
+        .await?;
+
+    copy_bidirectional(&mut client, &mut proxy_conn).await?;
+    Ok(())
+}
+
+/// Reads until the end of a request or response head. Returns the head and any bytes which were
+/// read after it, such as the start of a TLS handshake sent along with a CONNECT request.
+async fn read_head(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+
+    loop {
+        if let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = head.split_off(end + 4);
+            return Ok((head, rest));
+        }
+        if head.len() >= MAX_HEAD_SIZE {
+            return Ok((head, Vec::new()));
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok((head, Vec::new()));
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// A stream which first yields bytes that were already read from it.
+struct Prefixed<S> {
+    prefix: Vec<u8>,
+    read: usize,
+    inner: S,
+}
+
+impl<S: AsyncRead + Unpin> Prefixed<S> {
+    fn new(prefix: Vec<u8>, inner: S) -> Self {
+        Prefixed {
+            prefix,
+            read: 0,
+            inner,
+        }
+    }
+
+    /// Checks if the stream starts with the bytes, without consuming them.
+    async fn starts_with(&mut self, bytes: &[u8]) -> io::Result<bool> {
+        let mut buf = [0; 1024];
+        while self.prefix.len() - self.read < bytes.len() {
+            let n = self.inner.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(false);
+            }
+            self.prefix.extend_from_slice(&buf[..n]);
+        }
+        Ok(self.prefix[self.read..].starts_with(bytes))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Prefixed<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.read < self.prefix.len() {
+            let n = buf.remaining().min(self.prefix.len() - self.read);
+            buf.put_slice(&self.prefix[self.read..self.read + n]);
+            self.read += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Prefixed<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Tunnels a CONNECT request to the upstream server and adds it to the history once it closes.
+/// `rest` holds bytes the client sent after the request, which are passed on first.
+async fn tunnel(
+    mut conn: Conn,
+    authority: Authority,
+    rest: Vec<u8>,
+    tx: Sender<Req>,
+) -> io::Result<()> {
+    let host = authority.host().to_string();
+    let port = authority.port_u16().unwrap_or(443);
+    let started = Instant::now();
+    let timestamp = OffsetDateTime::now_utc();
+
+    let mut upstream = match TcpStream::connect((host.as_str(), port)).await {
+        Ok(s) => s,
+        Err(e) => {
+            conn.client
+                .write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n")
+                .await?;
+            let mut entry = Req::upstream_error(&host, authority.as_str(), e.to_string());
+            entry.client = conn.client_addr.to_string();
+            tx.send(entry).await.ok();
+            return Ok(());
+        }
+    };
+
+    conn.client
+        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+        .await?;
+    upstream.write_all(&rest).await?;
+
+    let (bytes_sent, bytes_received) = copy_bidirectional(&mut conn.client, &mut upstream)
+        .await
+        .unwrap_or_default();
+
+    let entry = Req {
+        host,
+        method: "CONNECT".to_string(),
+        url: authority.to_string(),
+        tunnel: Some(Tunnel {
+            bytes_sent: bytes_sent + rest.len() as u64,
+            bytes_received,
+        }),
+        duration: Some(started.elapsed()),
+        timestamp: Some(timestamp),
+        client: conn.client_addr.to_string(),
+        listener: conn.listener_addr,
+        ..Default::default()
+    };
+    tx.send(entry).await.ok();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn read_head_keeps_bytes_after_the_head() {
+        let mut stream: &[u8] = b"CONNECT a.test:443 HTTP/1.1\r\n\r\n\x16\x03\x01";
+        let (head, rest) = read_head(&mut stream).await.unwrap();
+        assert_eq!(head, b"CONNECT a.test:443 HTTP/1.1\r\n\r\n");
+        assert_eq!(rest, b"\x16\x03\x01");
+
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\n";
+        let (head, rest) = read_head(&mut stream).await.unwrap();
+        assert_eq!(head, b"GET / HTTP/1.1\r\n");
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn prefixed_streams_yield_the_prefix_first() {
+        let mut stream = Prefixed::new(b"\x16".to_vec(), &b"\x03abc"[..]);
+        assert!(stream.starts_with(b"\x16\x03").await.unwrap());
+        assert!(!stream.starts_with(b"GET").await.unwrap());
+
+        let mut read = Vec::new();
+        stream.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"\x16\x03abc");
+
+        let mut stream = Prefixed::new(Vec::new(), &b"a"[..]);
+        assert!(!stream.starts_with(b"\x16\x03").await.unwrap());
+    }
+
+    #[test]
+    fn only_consecutive_failures_add_hosts() {
+        let passthrough = Passthrough {
+            patterns: RwLock::new(Vec::new()),
+            failures: Mutex::new(HashMap::new()),
+            auto_after: Some(2),
+        };
+        passthrough.record_failure("A.test");
+        passthrough.record_success("a.test");
+        passthrough.record_failure("a.test");
+        assert!(!passthrough.matches("a.test"));
+
+        passthrough.record_failure("a.test");
+        assert!(passthrough.matches("a.test"));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::rudy_tui::Req;
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{self, Write},
+};
+
+/// History of a project, saved as JSON along with its annotations.
+#[derive(Deserialize)]
+struct Project {
+    history: Vec<Req>,
+}
+
+/// Loads the history saved in a project file. The history is empty if the file does not exist.
+pub fn load(path: &str) -> Result<Vec<Req>, String> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(format!("Unable to load project file at '{}'. {}", path, e)),
+    };
+
+    serde_json::from_reader::<_, Project>(io::BufReader::new(file))
+        .map(|p| p.history)
+        .map_err(|e| format!("Invalid project file at '{}'. {}", path, e))
+}
+
+/// Saves the history to a project file.
+pub fn save<'a>(path: &str, history: impl Iterator<Item = Cow<'a, Req>>) -> io::Result<()> {
+    write(path, history)
+}
+
+/// Exports history entries, including their annotations, as JSON.
+pub fn export<'a>(path: &str, entries: impl Iterator<Item = Cow<'a, Req>>) -> io::Result<()> {
+    write(path, entries)
+}
+
+/// Writes entries one at a time, so entries read from disk don't all have to be in memory. They
+/// are written to a temporary file which then replaces the file at `path`, so a failed write
+/// leaves the previous file intact.
+fn write<'a>(path: &str, entries: impl Iterator<Item = Cow<'a, Req>>) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    let result = write_entries(&tmp_path, entries).and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        fs::remove_file(&tmp_path).ok();
+    }
+    result
+}
+
+fn write_entries<'a>(path: &str, entries: impl Iterator<Item = Cow<'a, Req>>) -> io::Result<()> {
+    let mut file = io::BufWriter::new(File::create(path)?);
+    file.write_all(b"{\"history\":[")?;
+    for (i, req) in entries.enumerate() {
+        if i > 0 {
+            file.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut file, req.as_ref())?;
+    }
+    file.write_all(b"]}")?;
+    file.into_inner()?.sync_all()
+}
+
+/// Serializes bytes as base64.
+pub mod base64_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        base64::decode(s).map_err(D::Error::custom)
+    }
+}
+
+/// Serializes HTTP versions as strings such as `HTTP/1.1`.
+pub mod version {
+    use hudsucker::hyper::Version;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(version: &Version, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:?}", version))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Version, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse(&s).ok_or_else(|| D::Error::custom(format!("unknown HTTP version '{}'", s)))
+    }
+
+    pub fn parse(s: &str) -> Option<Version> {
+        match s {
+            "HTTP/0.9" => Some(Version::HTTP_09),
+            "HTTP/1.0" => Some(Version::HTTP_10),
+            "HTTP/1.1" => Some(Version::HTTP_11),
+            "HTTP/2.0" => Some(Version::HTTP_2),
+            "HTTP/3.0" => Some(Version::HTTP_3),
+            _ => None,
+        }
+    }
+
+    pub mod option {
+        use hudsucker::hyper::Version;
+        use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            version: &Option<Version>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match version {
+                Some(v) => super::serialize(v, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Version>, D::Error> {
+            match Option::<String>::deserialize(deserializer)? {
+                Some(s) => super::parse(&s)
+                    .map(Some)
+                    .ok_or_else(|| D::Error::custom(format!("unknown HTTP version '{}'", s))),
+                None => Ok(None),
+            }
+        }
+    }
+}
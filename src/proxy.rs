@@ -1,94 +1,770 @@
+use crate::{
+    authority::LeafAuthority,
+    extension::Extensions,
+    passthrough::ClientAddrs,
+    rudy_tui::Req,
+    scanner,
+    scripts::{Message, Scripts},
+    settings::{ClientCert, Settings},
+    upstream::{self, TlsInfo, UpstreamConnector},
+};
 use hudsucker::{
     async_trait::async_trait,
-    certificate_authority::RcgenAuthority,
-    hyper::{body::to_bytes, Body, Request, Response},
+    hyper::{
+        body::{to_bytes, HttpBody},
+        header::{self, HeaderName, HeaderValue},
+        http::response,
+        Body, Client, HeaderMap, Request, Response, StatusCode, Version,
+    },
     *,
 };
 use rustls_pemfile as pemfile;
-use std::process::exit;
-use std::str;
+use std::{sync::Arc, time::Instant};
+use time::OffsetDateTime;
 use tokio::sync::mpsc::Sender;
+use tracing::warn;
+
+/// Largest part of a response body which is recorded in the History. The rest is passed on to the
+/// client without being kept.
+const MAX_CAPTURED_BODY: usize = 32 * 1024 * 1024;
 
 #[derive(Clone)]
 pub struct LogHandler {
-    pub tx: Sender<String>,
+    tx: Sender<Req>,
+    client: Client<UpstreamConnector>,
+    clients: ClientAddrs,
+    /// Address of the listener clients connect to.
+    listener: String,
+    req: Option<Req>,
+    started: Instant,
+    scripts: Option<Arc<Scripts>>,
+    extensions: Extensions,
+}
+
+impl LogHandler {
+    pub fn new(
+        tx: Sender<Req>,
+        client: Client<UpstreamConnector>,
+        clients: ClientAddrs,
+        listener: String,
+        scripts: Option<Arc<Scripts>>,
+        extensions: Extensions,
+    ) -> Self {
+        LogHandler {
+            tx,
+            client,
+            clients,
+            listener,
+            req: None,
+            started: Instant::now(),
+            scripts,
+            extensions,
+        }
+    }
+
+    /// Returns the context with the address of the client, rather than that of the listener
+    /// which hands connections to the proxy.
+    fn client_ctx(&self, ctx: &HttpContext) -> HttpContext {
+        HttpContext {
+            client_addr: self.clients.get(ctx.client_addr).unwrap_or(ctx.client_addr),
+        }
+    }
+
+    /// Records a request whose body could not be read with the error, and answers it with a Bad
+    /// Gateway response as it cannot be sent upstream.
+    fn reject(&self, ctx: &HttpContext, mut entry: Req, error: String) -> RequestOrResponse {
+        add_errors(&mut entry, vec![error]);
+        self.set_client(ctx, &mut entry);
+        let tx = self.tx.clone();
+        tokio::spawn(async move { tx.send(entry).await.ok() });
+        RequestOrResponse::Response(bad_gateway())
+    }
+
+    /// Records the client and listener the request was received on.
+    fn set_client(&self, ctx: &HttpContext, entry: &mut Req) {
+        entry.client = ctx.client_addr.to_string();
+        entry.listener = self.listener.clone();
+    }
 }
 
 #[async_trait]
 impl HttpHandler for LogHandler {
-    async fn handle_request(
-        &mut self,
-        _ctx: &HttpContext,
-        req: Request<Body>,
-    ) -> RequestOrResponse {
-        self.tx.send("Sending message".to_string()).await.unwrap();
-        RequestOrResponse::Request(format_req(req).await)
+    async fn handle_request(&mut self, ctx: &HttpContext, req: Request<Body>) -> RequestOrResponse {
+        let ctx = &self.client_ctx(ctx);
+        let (captured, mut entry) = capture_req(req).await;
+        let mut req = match captured {
+            Ok(r) => r,
+            Err(e) => return self.reject(ctx, entry, e),
+        };
+        if let Some(scripts) = &self.scripts {
+            let scripts = Arc::clone(scripts);
+            (req, entry) = run_hooks(move || {
+                let req = run_request_hooks(&scripts, req, &mut entry);
+                (req, entry)
+            })
+            .await;
+        }
+        for extension in self.extensions.iter() {
+            match extension.handle_request(ctx, req).await {
+                RequestOrResponse::Request(r) => req = r,
+                RequestOrResponse::Response(res) => {
+                    // The request is not sent upstream, so the exchange is complete.
+                    self.set_client(ctx, &mut entry);
+                    entry.comment = format!("Answered by {}", extension.name());
+                    record_head(&res, &mut entry);
+                    return RequestOrResponse::Response(capture_body(res, entry, self.tx.clone()));
+                }
+            }
+        }
+        if !self.extensions.is_empty() {
+            let error = entry.error.take();
+            let captured;
+            (captured, entry) = capture_req(req).await;
+            entry.error = error;
+            req = match captured {
+                Ok(r) => r,
+                Err(e) => return self.reject(ctx, entry, e),
+            };
+        }
+
+        // WebSocket handshakes are answered by hudsucker without calling the response handler.
+        if is_upgrade(&req) {
+            return RequestOrResponse::Request(req);
+        }
+        self.set_client(ctx, &mut entry);
+        self.req = Some(entry);
+        self.started = Instant::now();
+
+        // The request is sent here rather than by hudsucker, which drops a request whose
+        // connection fails without the error. This way the error stays with its own request.
+        match self.client.request(normalize_request(req)).await {
+            Ok(res) => RequestOrResponse::Response(self.handle_response(ctx, res).await),
+            Err(e) => {
+                if let Some(mut entry) = self.req.take() {
+                    add_errors(&mut entry, vec![upstream::error_chain(&e)]);
+                    entry.duration = Some(self.started.elapsed());
+                    let tx = self.tx.clone();
+                    tokio::spawn(async move { tx.send(entry).await.ok() });
+                }
+                RequestOrResponse::Response(bad_gateway())
+            }
+        }
+    }
+
+    async fn handle_response(&mut self, ctx: &HttpContext, res: Response<Body>) -> Response<Body> {
+        let ctx = &self.client_ctx(ctx);
+        let mut entry = match self.req.take() {
+            Some(e) => e,
+            None => return res,
+        };
+
+        entry.tls = res.extensions().get::<TlsInfo>().cloned();
+        record_head(&res, &mut entry);
+        let mut res = res;
+        if let Some(scripts) = &self.scripts {
+            // The hooks need the whole body, so it is only buffered if there are any.
+            if !is_event_stream(&entry) && scripts.has_hook("on_response") {
+                let (parts, body) = res.into_parts();
+                match to_bytes(body).await {
+                    Ok(body) => {
+                        entry.res_body = body.to_vec();
+                        let scripts = Arc::clone(scripts);
+                        (res, entry) = run_hooks(move || {
+                            let res = run_response_hooks(&scripts, parts, &mut entry);
+                            (res, entry)
+                        })
+                        .await;
+                    }
+                    Err(e) => {
+                        add_errors(
+                            &mut entry,
+                            vec![format!("Failed to read response body. {}", e)],
+                        );
+                        entry.duration = Some(self.started.elapsed());
+                        tokio::spawn(scan_and_record(entry, self.tx.clone()));
+                        return bad_gateway();
+                    }
+                }
+            }
+        }
+        if !self.extensions.is_empty() {
+            for extension in self.extensions.iter() {
+                res = extension.handle_response(ctx, res).await;
+            }
+            record_head(&res, &mut entry);
+        }
+
+        entry.duration = Some(self.started.elapsed());
+        capture_body(res, entry, self.tx.clone())
     }
+}
 
-    async fn handle_response(&mut self, _ctx: &HttpContext, res: Response<Body>) -> Response<Body> {
-        // println!("{:?}", res);
-        res
+impl Drop for LogHandler {
+    /// Records a request which got no response, such as one whose client went away while it was
+    /// waiting for the upstream server.
+    fn drop(&mut self) {
+        let mut entry = match self.req.take() {
+            Some(e) => e,
+            None => return,
+        };
+        add_errors(&mut entry, vec!["No response received".to_string()]);
+        entry.duration = Some(self.started.elapsed());
+        let tx = self.tx.clone();
+        tokio::spawn(async move { tx.send(entry).await.ok() });
     }
 }
 
-async fn format_req(req: Request<Body>) -> Request<Body> {
-    let new_req: Request<Body>;
-    let (parts, body) = req.into_parts();
+/// Records the status line and headers of a response in a history entry.
+fn record_head(res: &Response<Body>, entry: &mut Req) {
+    entry.status = Some(res.status().as_u16());
+    entry.upstream_version = Some(res.version());
+    entry.res_headers = header_list(res.headers());
+}
 
-    let mut output = "----------\n".to_string();
-    output += &format!("{} {} {:?}\n", parts.method, parts.uri, parts.version);
-    for h in &parts.headers {
-        output += &format!("{}: {:?}\n", h.0, h.1)
+/// Passes the body of a response on to the client while copying up to [`MAX_CAPTURED_BODY`] of it
+/// into the history entry, which is recorded once the body has been sent. A body which fails to
+/// be read aborts the response, as the client has already received its headers.
+fn capture_body(res: Response<Body>, mut entry: Req, tx: Sender<Req>) -> Response<Body> {
+    // Event streams never end, so they are recorded without their bodies.
+    if is_event_stream(&entry) {
+        tokio::spawn(scan_and_record(entry, tx));
+        return res;
     }
 
-    if parts.headers.contains_key("Content-Length")
+    let (parts, mut body) = res.into_parts();
+    let (mut sender, new_body) = Body::channel();
+    entry.res_body.clear();
+    tokio::spawn(async move {
+        let mut complete = true;
+        while let Some(chunk) = body.data().await {
+            let chunk = match chunk {
+                Ok(c) => c,
+                Err(e) => {
+                    add_errors(
+                        &mut entry,
+                        vec![format!("Failed to read response body. {}", e)],
+                    );
+                    complete = false;
+                    break;
+                }
+            };
+            let room = MAX_CAPTURED_BODY.saturating_sub(entry.res_body.len());
+            if chunk.len() > room {
+                entry.res_body_truncated = true;
+            }
+            entry
+                .res_body
+                .extend_from_slice(&chunk[..chunk.len().min(room)]);
+            if sender.send_data(chunk).await.is_err() {
+                let error = "The client closed the connection during the response".to_string();
+                add_errors(&mut entry, vec![error]);
+                complete = false;
+                break;
+            }
+        }
+        if !complete {
+            sender.abort();
+        } else if let Ok(Some(trailers)) = body.trailers().await {
+            sender.send_trailers(trailers).await.ok();
+        }
+        scan_and_record(entry, tx).await;
+    });
+    Response::from_parts(parts, new_body)
+}
+
+/// Runs the passive checks on a blocking thread, so that scanning large bodies does not hold up
+/// the proxy, and then sends the entry to the History.
+async fn scan_and_record(entry: Req, tx: Sender<Req>) {
+    let scanned = tokio::task::spawn_blocking(move || {
+        let mut entry = entry;
+        entry.issues = scanner::passive(&entry);
+        entry
+    })
+    .await;
+    match scanned {
+        Ok(entry) => {
+            tx.send(entry).await.ok();
+        }
+        Err(e) => warn!("Passive scan failed. {}", e),
+    }
+}
+
+fn bad_gateway() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .body(Body::empty())
+        .expect("Failed to build response")
+}
+
+/// Prepares a request for the upstream client as hudsucker does: hyper adds the Host header,
+/// cookies are joined into one header for HTTP/1.1 and the version is left to the connection.
+fn normalize_request(req: Request<Body>) -> Request<Body> {
+    let (mut parts, body) = req.into_parts();
+    parts.headers.remove(header::HOST);
+    if let header::Entry::Occupied(cookies) = parts.headers.entry(header::COOKIE) {
+        let joined = cookies
+            .remove_entry_mult()
+            .1
+            .map(|c| c.to_str().unwrap_or_default().to_string())
+            .collect::<Vec<_>>()
+            .join("; ");
+        if let Ok(value) = HeaderValue::from_str(&joined) {
+            parts.headers.insert(header::COOKIE, value);
+        }
+    }
+    parts.version = Version::HTTP_11;
+    Request::from_parts(parts, body)
+}
+
+fn is_upgrade(req: &Request<Body>) -> bool {
+    req.headers()
+        .get("upgrade")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"))
+}
+
+fn is_event_stream(entry: &Req) -> bool {
+    entry.mime_type() == Some("text/event-stream")
+}
+
+/// Builds a history entry from a request, buffering its body if it has one. The request is
+/// returned along with the entry, or the error if its body could not be read.
+async fn capture_req(req: Request<Body>) -> (Result<Request<Body>, String>, Req) {
+    let (parts, body) = req.into_parts();
+
+    let host = match parts.uri.host() {
+        Some(h) => h.to_string(),
+        None => parts
+            .headers
+            .get("Host")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default()
+            .to_string(),
+    };
+
+    let mut entry = Req {
+        host,
+        method: parts.method.to_string(),
+        url: parts.uri.to_string(),
+        headers: header_list(&parts.headers),
+        client_version: parts.version,
+        timestamp: Some(OffsetDateTime::now_utc()),
+        ..Default::default()
+    };
+
+    let new_req = if parts.headers.contains_key("Content-Length")
         || parts.headers.contains_key("Transfer-Encoding")
     {
-        let body_bytes = to_bytes(body).await.unwrap();
-        output += "body:\n";
-        output += str::from_utf8(&body_bytes).unwrap();
+        match to_bytes(body).await {
+            Ok(body_bytes) => {
+                entry.body = body_bytes.to_vec();
+                Ok(Request::from_parts(parts, Body::from(body_bytes)))
+            }
+            Err(e) => Err(format!("Failed to read request body. {}", e)),
+        }
+    } else {
+        Ok(Request::from_parts(parts, body))
+    };
+
+    (new_req, entry)
+}
+
+/// Runs script hooks on a blocking thread, so that slow scripts do not hold up a worker thread of
+/// the proxy.
+async fn run_hooks<T: Send + 'static>(hooks: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(hooks)
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+/// Runs the `on_request` hooks of the scripts on a request whose body was buffered, and records
+/// the request they return in the history entry.
+fn run_request_hooks(scripts: &Scripts, req: Request<Body>, entry: &mut Req) -> Request<Body> {
+    let mut message = Message {
+        method: entry.method.clone(),
+        uri: entry.url.clone(),
+        status: 0,
+        headers: entry.headers.clone(),
+        body: entry.body.clone(),
+    };
+    let mut errors = scripts.run("on_request", &mut message);
+    let body_changed = message.body != entry.body;
+    if body_changed {
+        message.remove_header("transfer-encoding");
+        message.set_header("content-length", &message.body.len().to_string());
+    }
 
-        let new_body = Body::from(body_bytes);
-        new_req = Request::from_parts(parts, new_body);
+    let (mut parts, body) = req.into_parts();
+    match message.method.parse() {
+        Ok(method) => parts.method = method,
+        Err(_) => errors.push(format!(
+            "Invalid method '{}' set by a script",
+            message.method
+        )),
+    }
+    match message.uri.parse() {
+        Ok(uri) => parts.uri = uri,
+        Err(_) => errors.push(format!("Invalid URI '{}' set by a script", message.uri)),
+    }
+    match header_map(&message.headers) {
+        Ok(headers) => parts.headers = headers,
+        Err(e) => errors.push(e),
+    }
+    let body = if body_changed {
+        Body::from(message.body.clone())
     } else {
-        new_req = Request::from_parts(parts, body);
+        body
+    };
+
+    entry.method = parts.method.to_string();
+    entry.url = parts.uri.to_string();
+    entry.headers = header_list(&parts.headers);
+    entry.body = message.body;
+    add_errors(entry, errors);
+    Request::from_parts(parts, body)
+}
+
+/// Runs the `on_response` hooks of the scripts on a response whose body was buffered into the
+/// history entry, and records the response they return.
+fn run_response_hooks(
+    scripts: &Scripts,
+    mut parts: response::Parts,
+    entry: &mut Req,
+) -> Response<Body> {
+    let mut message = Message {
+        method: entry.method.clone(),
+        uri: entry.url.clone(),
+        status: entry.status.unwrap_or_default().into(),
+        headers: entry.res_headers.clone(),
+        body: entry.res_body.clone(),
+    };
+    let mut errors = scripts.run("on_response", &mut message);
+    if message.body != entry.res_body {
+        message.remove_header("transfer-encoding");
+        message.set_header("content-length", &message.body.len().to_string());
     }
 
-    // println!("{}", output);
+    match u16::try_from(message.status)
+        .ok()
+        .and_then(|s| StatusCode::from_u16(s).ok())
+    {
+        Some(status) => parts.status = status,
+        None => errors.push(format!("Invalid status {} set by a script", message.status)),
+    }
+    match header_map(&message.headers) {
+        Ok(headers) => parts.headers = headers,
+        Err(e) => errors.push(e),
+    }
+
+    entry.status = Some(parts.status.as_u16());
+    entry.res_headers = header_list(&parts.headers);
+    entry.res_body = message.body.clone();
+    add_errors(entry, errors);
+    Response::from_parts(parts, Body::from(message.body))
+}
 
-    new_req
+fn header_map(headers: &[(String, String)]) -> Result<HeaderMap, String> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("Invalid header name '{}' set by a script", name))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| format!("Invalid value of header {} set by a script", name))?;
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+fn add_errors(entry: &mut Req, errors: Vec<String>) {
+    if !errors.is_empty() {
+        let mut all: Vec<String> = entry.error.take().into_iter().collect();
+        all.extend(errors);
+        entry.error = Some(all.join("; "));
+    }
+}
+
+pub fn header_list(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).to_string(),
+            )
+        })
+        .collect()
 }
 
 /// Loads the certificate authority and private key for the proxy server.
-pub fn load_ca(cert_path: &str, key_path: &str) -> RcgenAuthority {
-    let ca_cert_bytes = match std::fs::read_to_string(cert_path) {
-        Ok(b) => b,
-        Err(e) => {
-            eprintln!("Unable to load cert file at '{}'. {}", cert_path, e);
-            exit(1)
+pub fn load_ca(settings: &Settings) -> Result<LeafAuthority, String> {
+    let cert_path = &settings.ca_cert;
+    let key_path = &settings.ca_key;
+
+    let ca_cert_bytes = std::fs::read_to_string(cert_path)
+        .map_err(|e| format!("Unable to load cert file at '{}'. {}", cert_path, e))?;
+
+    let private_key_bytes = std::fs::read_to_string(key_path)
+        .map_err(|e| format!("Unable to load key file at '{}'. {}", key_path, e))?;
+
+    let ca_cert = pemfile::certs(&mut ca_cert_bytes.as_bytes())
+        .ok()
+        .and_then(|certs| certs.into_iter().next())
+        .map(rustls::Certificate)
+        .ok_or_else(|| format!("No certificate found in '{}'", cert_path))?;
+
+    let private_key = pemfile::pkcs8_private_keys(&mut private_key_bytes.as_bytes())
+        .ok()
+        .and_then(|keys| keys.into_iter().next())
+        .map(rustls::PrivateKey)
+        .ok_or_else(|| format!("No PKCS#8 private key found in '{}'", key_path))?;
+
+    let http1_hosts = settings
+        .hosts
+        .iter()
+        .filter(|h| !h.http2)
+        .map(|h| h.host.to_lowercase())
+        .collect();
+
+    LeafAuthority::new(
+        private_key,
+        ca_cert,
+        settings.leaf_certs.clone(),
+        http1_hosts,
+    )
+    .map_err(|e| format!("Unable to use the CA certificate. {}", e))
+}
+
+/// Loads a client certificate chain and private key to present to upstream servers.
+pub fn load_client_cert(
+    client_cert: &ClientCert,
+) -> Result<(Vec<rustls::Certificate>, rustls::PrivateKey), String> {
+    match client_cert {
+        ClientCert::Pem { cert, key } => {
+            let cert_bytes = read_file(cert, "client certificate")?;
+            let key_bytes = read_file(key, "client key")?;
+
+            let certs = pemfile::certs(&mut cert_bytes.as_slice())
+                .map_err(|e| format!("Invalid client certificate at '{}'. {}", cert, e))?
+                .into_iter()
+                .map(rustls::Certificate)
+                .collect();
+
+            let key = pemfile::read_all(&mut key_bytes.as_slice())
+                .map_err(|e| format!("Invalid client key at '{}'. {}", key, e))?
+                .into_iter()
+                .find_map(|item| match item {
+                    pemfile::Item::PKCS8Key(k)
+                    | pemfile::Item::RSAKey(k)
+                    | pemfile::Item::ECKey(k) => Some(rustls::PrivateKey(k)),
+                    _ => None,
+                })
+                .ok_or_else(|| format!("No private key found in '{}'", key))?;
+
+            Ok((certs, key))
         }
-    };
+        ClientCert::Pkcs12 { pkcs12, password } => {
+            let pfx_bytes = read_file(pkcs12, "PKCS#12 archive")?;
+            let pfx = p12::PFX::parse(&pfx_bytes)
+                .map_err(|e| format!("Invalid PKCS#12 archive at '{}'. {:?}", pkcs12, e))?;
 
-    let private_key_bytes = match std::fs::read_to_string(key_path) {
-        Ok(b) => b,
-        Err(e) => {
-            eprintln!("Unable to load key file at '{}'. {}", key_path, e);
-            exit(1)
+            let certs = pfx
+                .cert_x509_bags(password)
+                .map_err(|e| {
+                    format!(
+                        "Unable to decrypt the certificates in '{}'. {:?}",
+                        pkcs12, e
+                    )
+                })?
+                .into_iter()
+                .map(rustls::Certificate)
+                .collect();
+
+            let key = pfx
+                .key_bags(password)
+                .map_err(|e| format!("Unable to decrypt the private key in '{}'. {:?}", pkcs12, e))?
+                .into_iter()
+                .next()
+                .map(rustls::PrivateKey)
+                .ok_or_else(|| format!("No private key found in '{}'", pkcs12))?;
+
+            Ok((certs, key))
         }
-    };
+    }
+}
+
+/// Loads a PEM bundle of CA certificates to trust for upstream connections.
+pub fn load_ca_bundle(path: &str) -> Result<Vec<rustls::Certificate>, String> {
+    let bundle_bytes = read_file(path, "CA bundle")?;
+
+    Ok(pemfile::certs(&mut bundle_bytes.as_slice())
+        .map_err(|e| format!("Invalid CA bundle at '{}'. {}", path, e))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect())
+}
 
-    let ca_cert = rustls::Certificate(
-        pemfile::certs(&mut ca_cert_bytes.as_bytes())
-            .expect("Failed to parse CA certificate")
-            .remove(0),
-    );
+fn read_file(path: &str, name: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Unable to load {} at '{}'. {}", name, path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(source: &str) -> (tempfile::TempDir, Arc<Scripts>) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.rhai"), source).unwrap();
+        let scripts = Scripts::load(dir.path().to_str().unwrap());
+        (dir, scripts)
+    }
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn header_map_keeps_repeated_headers() {
+        let map = header_map(&[pair("A", "1"), pair("a", "2"), pair("B", "3")]).unwrap();
+        assert_eq!(map.get_all("a").iter().count(), 2);
+        assert_eq!(
+            header_list(&map),
+            [pair("a", "1"), pair("a", "2"), pair("b", "3")]
+        );
+        assert_eq!(
+            header_map(&[pair("bad name", "1")]).unwrap_err(),
+            "Invalid header name 'bad name' set by a script"
+        );
+        assert_eq!(
+            header_map(&[pair("A", "1\n2")]).unwrap_err(),
+            "Invalid value of header a set by a script"
+        );
+    }
 
-    let private_key = rustls::PrivateKey(
-        pemfile::pkcs8_private_keys(&mut private_key_bytes.as_bytes())
-            .expect("Failed to parse private key")
-            .remove(0),
-    );
+    #[tokio::test]
+    async fn failed_request_bodies_are_recorded() {
+        let chunks: Vec<Result<&str, std::io::Error>> =
+            vec![Ok("a"), Err(std::io::Error::other("stream broke"))];
+        let req = Request::post("http://a.test/")
+            .header("content-length", "2")
+            .body(Body::wrap_stream(futures::stream::iter(chunks)))
+            .unwrap();
 
-    RcgenAuthority::new(private_key, ca_cert, 1_000)
-        .expect("Failed to create Certificate Authority")
+        let (captured, entry) = capture_req(req).await;
+        let error = captured.map(|_| ()).unwrap_err();
+        assert!(
+            error.starts_with("Failed to read request body."),
+            "{}",
+            error
+        );
+        assert_eq!(entry.url, "http://a.test/");
+        assert_eq!(entry.host, "a.test");
+    }
+
+    #[tokio::test]
+    async fn request_hooks_update_content_length() {
+        let (_dir, scripts) = load(r#"fn on_request() { this.body = "changed"; }"#);
+        let mut entry = Req {
+            method: "POST".to_string(),
+            url: "http://a.test/".to_string(),
+            headers: vec![pair("content-length", "1"), pair("x-a", "1")],
+            body: b"a".to_vec(),
+            ..Req::default()
+        };
+        let req = Request::post("http://a.test/")
+            .header("content-length", "1")
+            .header("x-a", "1")
+            .body(Body::from("a"))
+            .unwrap();
+
+        let req = run_request_hooks(&scripts, req, &mut entry);
+        assert_eq!(req.headers()["content-length"], "7");
+        assert_eq!(to_bytes(req.into_body()).await.unwrap(), "changed");
+        assert_eq!(entry.body, b"changed");
+        assert_eq!(
+            entry.headers,
+            [pair("x-a", "1"), pair("content-length", "7")]
+        );
+        assert!(entry.error.is_none());
+    }
+
+    #[tokio::test]
+    async fn response_hooks_update_content_length_and_record_errors() {
+        let (_dir, scripts) =
+            load(r#"fn on_response() { this.body = "longer body"; this.status = 1000; }"#);
+        let mut entry = Req {
+            status: Some(200),
+            res_headers: vec![pair("transfer-encoding", "chunked")],
+            res_body: b"body".to_vec(),
+            ..Req::default()
+        };
+        let (parts, _) = Response::builder()
+            .header("transfer-encoding", "chunked")
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let res = run_response_hooks(&scripts, parts, &mut entry);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-length"], "11");
+        assert!(!res.headers().contains_key("transfer-encoding"));
+        assert_eq!(to_bytes(res.into_body()).await.unwrap(), "longer body");
+        assert_eq!(entry.res_body, b"longer body");
+        assert_eq!(
+            entry.error.as_deref(),
+            Some("Invalid status 1000 set by a script")
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_hooks_keep_the_request() {
+        let (_dir, scripts) = load(r#"fn on_request() { this.body = "x"; loop {} }"#);
+        let mut entry = Req {
+            method: "GET".to_string(),
+            url: "http://a.test/".to_string(),
+            ..Req::default()
+        };
+        let req = Request::get("http://a.test/").body(Body::empty()).unwrap();
+
+        let req = run_request_hooks(&scripts, req, &mut entry);
+        assert!(!req.headers().contains_key("content-length"));
+        assert!(entry.body.is_empty());
+        assert_eq!(
+            entry.error.as_deref(),
+            Some("a.rhai was stopped in on_request after 10000000 operations")
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_errors_stay_with_their_request() {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = closed.local_addr().unwrap();
+        drop(closed);
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let mut handler = LogHandler::new(
+            tx,
+            upstream::client(&Settings::default()).unwrap(),
+            ClientAddrs::default(),
+            String::new(),
+            None,
+            Arc::new([]),
+        );
+        let ctx = HttpContext {
+            client_addr: "127.0.0.1:1".parse().unwrap(),
+        };
+        let req = Request::get(format!("http://{}/", addr))
+            .body(Body::empty())
+            .unwrap();
+
+        match handler.handle_request(&ctx, req).await {
+            RequestOrResponse::Response(res) => assert_eq!(res.status(), StatusCode::BAD_GATEWAY),
+            RequestOrResponse::Request(_) => panic!("the request was left to hudsucker"),
+        }
+        let entry = rx.recv().await.unwrap();
+        let error = entry.error.unwrap();
+        assert!(error.contains("refused"), "{}", error);
+        assert!(entry.status.is_none());
+        drop(handler);
+        assert!(rx.try_recv().is_err());
+    }
 }
@@ -0,0 +1,61 @@
+use crate::{
+    rudy_tui::Req,
+    upstream::{self, UpstreamConnector},
+};
+use hudsucker::hyper::{Client, Version};
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+
+/// Time to wait for the response to a request sent again.
+const TIME_LIMIT: Duration = Duration::from_secs(30);
+
+/// Request headers which are not copied to the sent request, because hyper sets the framing
+/// headers itself.
+const SKIPPED_HEADERS: [&str; 7] = [
+    "connection",
+    "content-length",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Sends requests from the History, such as imported ones, with the upstream client. The
+/// exchanges are added to the History.
+#[derive(Clone)]
+pub struct Repeater {
+    client: Client<UpstreamConnector>,
+    tx: Sender<Req>,
+}
+
+impl Repeater {
+    pub fn new(client: Client<UpstreamConnector>, tx: Sender<Req>) -> Self {
+        Repeater { client, tx }
+    }
+
+    /// Sends a request in the background.
+    pub fn send(&self, req: &Req) {
+        let req = Req {
+            host: req.host.clone(),
+            method: req.method.clone(),
+            url: req.url.clone(),
+            headers: req
+                .headers
+                .iter()
+                .filter(|(name, _)| !SKIPPED_HEADERS.contains(&name.to_lowercase().as_str()))
+                .cloned()
+                .collect(),
+            body: req.body.clone(),
+            client_version: Version::HTTP_11,
+            comment: format!("Sent from request {}", req.id),
+            ..Default::default()
+        };
+        let client = self.client.clone();
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let entry = upstream::send(&client, &req, TIME_LIMIT).await;
+            tx.send(entry).await.ok();
+        });
+    }
+}
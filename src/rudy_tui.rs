@@ -1,78 +1,519 @@
+mod clipboard;
+mod columns;
+mod compare;
+mod decoder;
+mod detail;
+mod filter;
+mod history;
+mod issues;
+mod keymap;
+mod menu;
+mod mouse;
+mod site_map;
+mod snippet;
+mod theme;
+mod tokens;
+
+use crate::{
+    active_scan::ActiveScanner,
+    extension::{Exchange, ExtensionAction, ExtensionTab, Extensions},
+    import,
+    jwt::{self, Signing},
+    project,
+    repeater::Repeater,
+    scanner::Issue,
+    settings::{host_matches, Action, Column, ColumnSettings, EditAction, Settings},
+    upstream::TlsInfo,
+};
+use columns::Sort;
+use compare::{Compare, Part};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{
+        DisableMouseCapture, EnableMouseCapture, Event, EventStream, KeyCode, KeyEvent,
+        KeyModifiers,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use std::{error::Error, io};
+use decoder::{Decoder, Step};
+use detail::{Detail, DetailTab};
+use filter::{regex_error, Filter};
+use futures::StreamExt;
+use history::{History, Stored};
+use hudsucker::hyper::{Uri, Version};
+use issues::Issues;
+use keymap::{Key, Keymap};
+use menu::{ContextMenu, MenuAction};
+use mouse::Areas;
+use serde::{Deserialize, Serialize};
+use site_map::SiteMap;
+use snippet::Snippet;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    error::Error,
+    future::Future,
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use theme::Theme;
+use time::OffsetDateTime;
+use tokens::{SignAction, Tokens};
 use tokio::sync::mpsc::Receiver;
 use tui::{
     backend::{Backend, CrosstermBackend},
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    text::{Span, Spans},
-    widgets::{Block, Borders, Paragraph, Row, Table, TableState, Tabs},
+    text::{Span, Spans, Text},
+    widgets::{
+        Block, Borders, Cell, Clear, List, ListItem, ListState, Paragraph, Row, Table, TableState,
+        Tabs, Wrap,
+    },
     Frame, Terminal,
 };
 
+/// Interval at which the UI is redrawn when nothing else happens.
+const TICK_RATE: Duration = Duration::from_millis(250);
+
+/// Time spent reading entries from disk for a search on each redraw.
+const SEARCH_TIME: Duration = Duration::from_millis(50);
+
+/// Titles of the built-in tabs.
+const TAB_TITLES: [&str; 8] = [
+    "Intercept",
+    "History",
+    "Map",
+    "Settings",
+    "Compare",
+    "Decoder",
+    "Tokens",
+    "Issues",
+];
+
+/// Index of the first tab added by an extension, after the built-in tabs.
+const EXTENSION_TABS_START: usize = TAB_TITLES.len();
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Req {
-    id: usize,
-    host: String,
-    method: &'static str,
-    url: &'static str,
-    status: usize,
-    headers: Vec<&'static str>,
+    pub id: usize,
+    pub host: String,
+    pub method: String,
+    pub url: String,
+    pub status: Option<u16>,
+    pub headers: Vec<(String, String)>,
+    #[serde(with = "project::base64_bytes")]
+    pub body: Vec<u8>,
+    pub res_headers: Vec<(String, String)>,
+    #[serde(with = "project::base64_bytes")]
+    pub res_body: Vec<u8>,
+    /// Set if only the start of a large response body was recorded.
+    pub res_body_truncated: bool,
+    pub error: Option<String>,
+    pub tls: Option<TlsInfo>,
+    pub tunnel: Option<Tunnel>,
+    pub duration: Option<Duration>,
+    #[serde(with = "project::version")]
+    pub client_version: Version,
+    #[serde(with = "project::version::option")]
+    pub upstream_version: Option<Version>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub timestamp: Option<OffsetDateTime>,
+    /// Address of the client which sent the request.
+    pub client: String,
+    /// Address of the proxy listener which received the request.
+    pub listener: String,
+    pub highlight: Option<Highlight>,
+    pub comment: String,
+    pub tags: Vec<String>,
+    /// Findings of the passive scanner.
+    pub issues: Vec<Issue>,
+    /// Location of the entry on disk once its bodies have been spilled or it has been evicted
+    /// from memory.
+    #[serde(skip)]
+    pub stored: Option<Stored>,
+}
+
+/// Traffic of a CONNECT tunnel which was passed through without decrypting it.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Tunnel {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// Color used to highlight a history entry.
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Highlight {
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Cyan,
+    Blue,
+    Magenta,
+    Gray,
+}
+
+impl Highlight {
+    pub const ALL: [Highlight; 8] = [
+        Highlight::Red,
+        Highlight::Orange,
+        Highlight::Yellow,
+        Highlight::Green,
+        Highlight::Cyan,
+        Highlight::Blue,
+        Highlight::Magenta,
+        Highlight::Gray,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Highlight::Red => "red",
+            Highlight::Orange => "orange",
+            Highlight::Yellow => "yellow",
+            Highlight::Green => "green",
+            Highlight::Cyan => "cyan",
+            Highlight::Blue => "blue",
+            Highlight::Magenta => "magenta",
+            Highlight::Gray => "gray",
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Highlight::Red => Color::Red,
+            Highlight::Orange => Color::Rgb(255, 165, 0),
+            Highlight::Yellow => Color::Yellow,
+            Highlight::Green => Color::Green,
+            Highlight::Cyan => Color::Cyan,
+            Highlight::Blue => Color::Blue,
+            Highlight::Magenta => Color::Magenta,
+            Highlight::Gray => Color::Gray,
+        }
+    }
+
+    /// Returns the highlight after `current`, cycling back to no highlight after the last one.
+    pub fn next(current: Option<Highlight>) -> Option<Highlight> {
+        match current {
+            None => Some(Highlight::ALL[0]),
+            Some(h) => {
+                let i = Highlight::ALL
+                    .iter()
+                    .position(|c| *c == h)
+                    .unwrap_or_default();
+                Highlight::ALL.get(i + 1).copied()
+            }
+        }
+    }
 }
 
 impl Req {
-    fn to_row(&self) -> Row<'static> {
-        Row::new(vec![
-            self.id.to_string(),
-            self.host.to_string(),
-            self.method.to_string(),
-            self.url.to_string(),
-            self.status.to_string(),
-        ])
+    /// Returns a copy of the request to add to the History, with `original` replaced by
+    /// `replacement` in the URL, headers and body. Content-Length is updated to the new body.
+    pub fn with_replaced(&self, original: &[u8], replacement: &[u8]) -> Req {
+        let text = |s: &str| match (
+            std::str::from_utf8(original),
+            std::str::from_utf8(replacement),
+        ) {
+            (Ok(o), Ok(r)) if !o.is_empty() => s.replace(o, r),
+            _ => s.to_string(),
+        };
+        let body = replace_bytes(&self.body, original, replacement);
+        let headers = self
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if name.eq_ignore_ascii_case("content-length") {
+                    body.len().to_string()
+                } else {
+                    text(value)
+                };
+                (name.clone(), value)
+            })
+            .collect();
+        Req {
+            host: self.host.clone(),
+            method: self.method.clone(),
+            url: text(&self.url),
+            headers,
+            body,
+            client_version: self.client_version,
+            listener: self.listener.clone(),
+            timestamp: Some(OffsetDateTime::now_utc()),
+            ..Default::default()
+        }
     }
 
-    fn to_paragraph(&self) -> Paragraph<'static> {
-        let mut p = format!("{} {}\n", self.method, self.url);
-        p += &format!("Host: {}\n", self.host);
-        for h in &self.headers {
-            p += &format!("{}\n", h)
+    /// Checks if the URL, headers or body of the request contain the bytes.
+    pub fn contains(&self, bytes: &[u8]) -> bool {
+        let found = |haystack: &[u8]| haystack.windows(bytes.len()).any(|w| w == bytes);
+        !bytes.is_empty()
+            && (found(self.url.as_bytes())
+                || self
+                    .headers
+                    .iter()
+                    .any(|(_, value)| found(value.as_bytes()))
+                || found(&self.body))
+    }
+
+    /// Creates a history entry for a request which could not be sent upstream.
+    pub fn upstream_error(host: &str, url: &str, error: String) -> Self {
+        Req {
+            host: host.to_string(),
+            url: url.to_string(),
+            error: Some(error),
+            timestamp: Some(OffsetDateTime::now_utc()),
+            ..Default::default()
         }
+    }
 
-        Paragraph::new(p)
+    /// Returns the HTTP/2 pseudo-headers of the request, which hyper folds into the URI.
+    fn pseudo_headers(&self) -> Vec<(&'static str, String)> {
+        let uri = self.url.parse::<Uri>().unwrap_or_default();
+        vec![
+            (":method", self.method.clone()),
+            (":scheme", uri.scheme_str().unwrap_or_default().to_string()),
+            (
+                ":authority",
+                uri.authority().map(|a| a.to_string()).unwrap_or_default(),
+            ),
+            (
+                ":path",
+                uri.path_and_query()
+                    .map(|p| p.to_string())
+                    .unwrap_or_default(),
+            ),
+        ]
     }
+
+    /// Returns the MIME type of the response, without parameters.
+    pub fn mime_type(&self) -> Option<&str> {
+        self.res_headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .and_then(|(_, value)| value.split(';').next())
+            .map(|m| m.trim())
+    }
+
+    /// Length of the request body, which may have been moved to disk.
+    pub fn body_len(&self) -> u64 {
+        match &self.stored {
+            Some(s) => s.body_len,
+            None => self.body.len() as u64,
+        }
+    }
+
+    /// Length of the response body, which may have been moved to disk.
+    pub fn res_body_len(&self) -> u64 {
+        match &self.stored {
+            Some(s) => s.res_body_len,
+            None => self.res_body.len() as u64,
+        }
+    }
+
+    /// Checks if the request has query or body parameters.
+    fn has_params(&self) -> bool {
+        let has_query = self
+            .url
+            .parse::<Uri>()
+            .ok()
+            .and_then(|uri| uri.query().map(|q| !q.is_empty()))
+            .unwrap_or(false);
+
+        has_query || self.body_len() > 0
+    }
+
+    /// Returns the Alt-Svc header of the response if it advertises HTTP/3.
+    fn http3_alt_svc(&self) -> Option<&str> {
+        self.res_headers
+            .iter()
+            .find(|(name, value)| name.eq_ignore_ascii_case("alt-svc") && value.contains("h3"))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Replaces every occurrence of `original` in the bytes.
+fn replace_bytes(bytes: &[u8], original: &[u8], replacement: &[u8]) -> Vec<u8> {
+    if original.is_empty() {
+        return bytes.to_vec();
+    }
+    let mut replaced = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(original) {
+            replaced.extend_from_slice(replacement);
+            i += original.len();
+        } else {
+            replaced.push(bytes[i]);
+            i += 1;
+        }
+    }
+    replaced
+}
+
+/// Text being typed into the filter bar of the History tab.
+pub struct Input {
+    pub mode: InputMode,
+    pub text: String,
+}
+
+pub enum InputMode {
+    Search,
+    Filter,
+    /// Comment of the selected entry.
+    Comment,
+    /// Space separated tags of the selected entry.
+    Tags,
+    /// Search in the detail viewer.
+    Detail,
+    /// A curl command or the path of a file to import a request from.
+    Import,
+    /// Input of the Decoder tab.
+    Decoder,
+    /// Edit of a claim of the token in the Tokens tab.
+    Claim,
+    HmacSecret,
+    /// Path of a private key to sign the token with.
+    PrivateKey,
+    /// Path of the public key used as the HMAC secret of the token.
+    PublicKey,
 }
 
 pub struct App<'a> {
-    pub titles: Vec<&'static str>,
     pub index: usize,
-    pub history: Vec<Req>,
+    pub history: History,
     pub history_state: TableState,
-    pub receiver: &'a mut Receiver<String>,
+    /// Index of the first visible row of the History table.
+    pub history_offset: usize,
+    /// Indices of the history entries which pass the current filter, in the current sort order.
+    visible: Vec<usize>,
+    /// Whether `visible` needs to be recomputed.
+    visible_dirty: bool,
+    /// Number of history entries which were checked against the filter for `visible`.
+    visible_checked: usize,
+    /// Entries on disk which a search has not read yet, as reading them all at once would freeze
+    /// the UI.
+    visible_pending: Vec<usize>,
+    pub receiver: &'a mut Receiver<Req>,
+    pub filter: Filter,
+    pub sort: Sort,
+    pub columns: Vec<ColumnSettings>,
+    pub site_map: SiteMap,
+    pub detail: Detail,
+    pub compare: Compare,
+    pub decoder: Decoder,
+    pub tokens: Tokens,
+    pub issues: Issues,
+    pub active_scan: ActiveScanner,
+    repeater: Repeater,
+    pub extension_tabs: Vec<Box<dyn ExtensionTab>>,
+    /// Actions added to the context menu by extensions.
+    pub extension_actions: Vec<Arc<dyn ExtensionAction>>,
+    /// Height of the History table set by dragging the split above the detail viewer.
+    pub split: Option<u16>,
+    /// Whether the split is being dragged.
+    pub dragging_split: bool,
+    /// Context menu of the selected History entry, if open.
+    pub menu: Option<ContextMenu>,
+    /// Screen areas from the last draw.
+    pub areas: Areas,
+    pub keymap: Keymap,
+    pub theme: Theme,
+    /// Whether the help overlay listing the key bindings is shown.
+    pub show_help: bool,
+    pub help_scroll: u16,
+    /// Host patterns which are in scope.
+    pub scope: Vec<String>,
+    pub input: Option<Input>,
+    pub input_error: Option<String>,
+    /// Path of the project file the History is saved to.
+    pub project: Option<String>,
+    /// Result of the last action, shown in the filter bar.
+    pub message: Option<String>,
+    /// Set when saving the project failed on quit, so quitting again does not save.
+    save_failed: bool,
 }
 
 impl<'a> App<'a> {
-    pub fn new(rx: &'a mut Receiver<String>) -> Self {
-        App {
-            titles: vec!["Intercept", "History", "Settings"],
+    pub fn new(
+        rx: &'a mut Receiver<Req>,
+        settings: &Settings,
+        history: Vec<Req>,
+        active_scan: ActiveScanner,
+        repeater: Repeater,
+        extensions: &Extensions,
+    ) -> Result<Self, String> {
+        let extension_tabs: Vec<Box<dyn ExtensionTab>> =
+            extensions.iter().flat_map(|e| e.tabs()).collect();
+        let mut app = App {
             index: 0,
-            history: vec![],
+            history: History::new(&settings.storage)?,
             history_state: TableState::default(),
+            history_offset: 0,
+            visible: vec![],
+            visible_dirty: true,
+            visible_checked: 0,
+            visible_pending: vec![],
             receiver: rx,
+            filter: Filter::default(),
+            sort: Sort::default(),
+            columns: settings.history_columns.clone(),
+            site_map: SiteMap::default(),
+            detail: Detail::default(),
+            compare: Compare::new(&settings.compare),
+            decoder: Decoder::default(),
+            tokens: Tokens::default(),
+            issues: Issues::default(),
+            active_scan,
+            repeater,
+            extension_tabs,
+            extension_actions: extensions.iter().flat_map(|e| e.menu_actions()).collect(),
+            split: None,
+            dragging_split: false,
+            menu: None,
+            areas: Areas::default(),
+            keymap: Keymap::new(&settings.keymap),
+            theme: Theme::load(&settings.theme),
+            show_help: false,
+            help_scroll: 0,
+            scope: settings.scope.clone(),
+            input: None,
+            input_error: None,
+            project: settings.project.clone(),
+            message: None,
+            save_failed: false,
+        };
+
+        for req in history {
+            app.history.push(req);
         }
+        Ok(app)
+    }
+
+    /// Returns the titles of the built-in tabs followed by those of the extensions.
+    pub fn titles(&self) -> Vec<String> {
+        TAB_TITLES
+            .iter()
+            .map(|t| t.to_string())
+            .chain(self.extension_tabs.iter().map(|t| t.title()))
+            .collect()
+    }
+
+    fn tab_count(&self) -> usize {
+        TAB_TITLES.len() + self.extension_tabs.len()
     }
 
     pub fn next_tab(&mut self) {
-        self.index = (self.index + 1) % self.titles.len();
+        self.index = (self.index + 1) % self.tab_count();
     }
 
     pub fn previous_tab(&mut self) {
         if self.index > 0 {
             self.index -= 1;
         } else {
-            self.index = self.titles.len() - 1;
+            self.index = self.tab_count() - 1;
         }
     }
 
@@ -80,10 +521,123 @@ impl<'a> App<'a> {
         self.index = index;
     }
 
+    pub fn add_to_history(&mut self, mut req: Req) {
+        req.id = self.history.len() + 1;
+        self.history.push(req);
+    }
+
+    /// Recomputes the entries shown in the History table if the filter or sort order changed
+    /// since the last time, or adds the new entries which pass the filter.
+    pub fn refresh_visible(&mut self) {
+        let deadline = Instant::now() + SEARCH_TIME;
+        let history = &self.history;
+        if self.visible_dirty {
+            let mut visible = vec![];
+            self.visible_pending.clear();
+            for i in 0..history.len() {
+                match self.filter.matches_entry(history, i, &self.scope, deadline) {
+                    Some(true) => visible.push(i),
+                    Some(false) => {}
+                    None => self.visible_pending.push(i),
+                }
+            }
+            // Summaries which were moved to disk are read once for sorting, rather than for
+            // every comparison.
+            let mut visible: Vec<(usize, Cow<Req>)> =
+                visible.into_iter().map(|i| (i, history.get(i))).collect();
+            visible.sort_by(|(_, a), (_, b)| self.sort.compare(a, b));
+
+            self.visible = visible.into_iter().map(|(i, _)| i).collect();
+            self.visible_dirty = false;
+        } else {
+            let pending = std::mem::take(&mut self.visible_pending);
+            for i in pending
+                .into_iter()
+                .chain(self.visible_checked..history.len())
+            {
+                match self.filter.matches_entry(history, i, &self.scope, deadline) {
+                    Some(true) => {
+                        let (sort, req) = (&self.sort, history.get(i));
+                        let pos = self.visible.partition_point(|&v| {
+                            sort.compare(&history.get(v), &req) != Ordering::Greater
+                        });
+                        self.visible.insert(pos, i);
+                    }
+                    Some(false) => {}
+                    None => self.visible_pending.push(i),
+                }
+            }
+        }
+        self.visible_checked = history.len();
+    }
+
+    pub fn next_sort_column(&mut self) {
+        self.sort.next_column();
+        self.visible_dirty = true;
+    }
+
+    pub fn reverse_sort(&mut self) {
+        self.sort.reverse();
+        self.visible_dirty = true;
+    }
+
+    /// Returns the index in the history of the entry selected in the History table.
+    fn selected_hist_index(&self) -> Option<usize> {
+        let i = self.history_state.selected()?;
+        self.visible.get(i).copied()
+    }
+
+    /// Returns the whole history entry selected in the History table.
+    pub fn selected_hist_item(&self) -> Option<Cow<'_, Req>> {
+        self.selected_hist_index()
+            .map(|i| self.history.full_cached(i))
+    }
+
+    /// Highlights the selected entry with the next color.
+    pub fn cycle_highlight(&mut self) {
+        if let Some(i) = self.selected_hist_index() {
+            let req = self.history.get_mut(i);
+            req.highlight = Highlight::next(req.highlight);
+            self.visible_dirty = true;
+        }
+    }
+
+    /// Saves the History to the project file, if one is configured.
+    pub fn save_project(&mut self) -> io::Result<()> {
+        let path = match &self.project {
+            Some(p) => p,
+            None => {
+                self.message = Some("No project file configured".to_string());
+                return Ok(());
+            }
+        };
+
+        let history = &self.history;
+        project::save(path, (0..history.len()).map(|i| history.full(i)))?;
+        self.message = Some(format!("Saved project to {}", path));
+        Ok(())
+    }
+
+    /// Exports the entries shown in the History table to a JSON file in the current directory.
+    pub fn export_history(&mut self) {
+        let history = &self.history;
+        let entries = self.visible.iter().map(|&i| history.full(i));
+        let path = format!(
+            "rudy-export-{}.json",
+            OffsetDateTime::now_utc().unix_timestamp()
+        );
+
+        self.message = Some(match project::export(&path, entries) {
+            Ok(()) => format!("Exported {} entries to {}", self.visible.len(), path),
+            Err(e) => format!("Unable to export History. {}", e),
+        });
+    }
+
     pub fn next_hist_item(&mut self) {
+        let len = self.visible.len();
         let i = match self.history_state.selected() {
             Some(i) => {
-                if !self.history.is_empty() && i >= self.history.len() - 1 {
+                if len > 0 && i >= len - 1 {
                     i
                 } else {
                     i + 1
@@ -92,6 +646,21 @@ impl<'a> App<'a> {
             None => 0,
         };
         self.history_state.select(Some(i));
+        self.detail.reset_scroll();
+    }
+
+    pub fn first_hist_item(&mut self) {
+        if !self.visible.is_empty() {
+            self.history_state.select(Some(0));
+            self.detail.reset_scroll();
+        }
+    }
+
+    pub fn last_hist_item(&mut self) {
+        if !self.visible.is_empty() {
+            self.history_state.select(Some(self.visible.len() - 1));
+            self.detail.reset_scroll();
+        }
     }
 
     pub fn prev_hist_item(&mut self) {
@@ -106,10 +675,542 @@ impl<'a> App<'a> {
             None => 0,
         };
         self.history_state.select(Some(i));
+        self.detail.reset_scroll();
+    }
+
+    /// Scrolls the detail viewer to the next search match.
+    pub fn next_detail_match(&mut self) {
+        if let Some(i) = self.selected_hist_index() {
+            self.detail.next_match(&self.history.full_cached(i));
+        }
+    }
+
+    pub fn next_map_item(&mut self) {
+        let len = self.site_map.lines(&self.history).len();
+        self.site_map.next(len);
+    }
+
+    pub fn last_map_item(&mut self) {
+        let len = self.site_map.lines(&self.history).len();
+        self.site_map.state.select(len.checked_sub(1));
+    }
+
+    /// Expands or collapses the node selected in the site map.
+    pub fn toggle_map_node(&mut self) {
+        let lines = self.site_map.lines(&self.history);
+        if let Some(line) = self.site_map.state.selected().and_then(|i| lines.get(i)) {
+            self.site_map.toggle(line);
+        }
+    }
+
+    /// Filters the History to the requests below the node selected in the site map.
+    pub fn filter_to_map_node(&mut self) {
+        let lines = self.site_map.lines(&self.history);
+        if let Some(line) = self.site_map.state.selected().and_then(|i| lines.get(i)) {
+            self.apply_filter(line.query.clone());
+        }
+    }
+
+    /// Filters the History with a query and shows it.
+    fn apply_filter(&mut self, query: String) {
+        self.input = Some(Input {
+            mode: InputMode::Filter,
+            text: query,
+        });
+        self.submit_input();
+        self.go_to_tab(1);
+    }
+
+    /// Marks the selected entry for comparison and shows the Compare tab once two entries are
+    /// marked.
+    pub fn mark_for_comparison(&mut self) {
+        let i = match self.selected_hist_index() {
+            Some(i) => i,
+            None => return,
+        };
+        if self.compare.mark(i) {
+            self.go_to_tab(4);
+        } else {
+            self.message = Some(format!(
+                "Marked {} for comparison, press {} on another entry to compare them",
+                self.history.get(i).id,
+                self.keymap.key(Action::Compare)
+            ));
+        }
+    }
+
+    /// Sends the body shown in the Request or Response detail tab, or the text of the other
+    /// detail tabs, to the Decoder.
+    pub fn send_to_decoder(&mut self) {
+        let input = match self.selected_hist_item() {
+            Some(req) => match self.detail.tab {
+                DetailTab::Request if !req.body.is_empty() => req.body.clone(),
+                DetailTab::Response if !req.res_body.is_empty() => req.res_body.clone(),
+                tab => tab.content(&req).into_bytes(),
+            },
+            None => return,
+        };
+        self.decoder.set_input(input);
+        self.decoder.entry = self.selected_hist_index();
+        self.go_to_tab(5);
+    }
+
+    /// Opens the menu of steps which can be added to the Decoder chain.
+    pub fn open_step_menu(&mut self) {
+        let steps = self.areas.decoder_steps;
+        let actions = Step::all().into_iter().map(MenuAction::AddStep).collect();
+        self.menu = Some(ContextMenu::new(steps.x + 2, steps.y + 1, actions));
+    }
+
+    /// Copies the output of the selected Decoder row to the clipboard.
+    pub fn copy_decoder_output(&mut self) {
+        let output = match self.decoder.selected_output() {
+            Some(o) => o,
+            None => return,
+        };
+        self.message = Some(match clipboard::copy(&String::from_utf8_lossy(&output)) {
+            Ok(()) => "Copied output".to_string(),
+            Err(e) => format!("Unable to copy output. {}", e),
+        });
+    }
+
+    /// Adds a copy of the request the Decoder input was sent from to the History, with the input
+    /// replaced by the selected output.
+    pub fn add_decoder_request(&mut self) {
+        let entry = match self.decoder.entry {
+            Some(e) => e,
+            None => {
+                self.message = Some(format!(
+                    "Press {} on a History entry to put the Decoder output into its request",
+                    self.keymap.key(Action::SendToDecoder)
+                ));
+                return;
+            }
+        };
+        let edited = self.decoder.replace_in(&self.history.full(entry));
+        self.message = Some(match edited {
+            Ok(edited) => {
+                self.add_to_history(edited);
+                format!(
+                    "Added request {} with the Decoder output to History, press {} there to send it",
+                    self.history.len(),
+                    self.keymap.key(Action::Send)
+                )
+            }
+            Err(e) => e,
+        });
+    }
+
+    /// Loads the JWTs of the selected History entry into the Tokens tab if another entry was
+    /// selected or its response arrived since they were loaded.
+    pub fn sync_tokens(&mut self) {
+        let i = match self.selected_hist_index() {
+            Some(i) => i,
+            None => return,
+        };
+        let response = self.history.response_state(i);
+        if !self.tokens.is_loaded(i, response) {
+            let found = jwt::find(&self.history.full(i));
+            self.tokens.load(i, response, found);
+        }
+    }
+
+    /// Opens the menu of ways to sign the edited token.
+    pub fn open_token_menu(&mut self) {
+        if self.tokens.found.is_empty() {
+            return;
+        }
+        let list = self.areas.token_list;
+        let mut actions: Vec<MenuAction> = SignAction::all()
+            .into_iter()
+            .map(MenuAction::Sign)
+            .collect();
+        actions.push(MenuAction::AddTokenRequest);
+        actions.push(MenuAction::ResetToken);
+        self.menu = Some(ContextMenu::new(list.x + 2, list.y + 1, actions));
+    }
+
+    pub fn sign_token(&mut self, action: SignAction) {
+        match action {
+            SignAction::Original => {
+                let signature = match self.tokens.found.get(self.tokens.selected) {
+                    Some(f) => f.jwt.signature.clone(),
+                    None => return,
+                };
+                self.tokens.set_signing(Signing::Original(signature));
+            }
+            SignAction::None(alg) => self.tokens.set_signing(Signing::None(alg)),
+            SignAction::Hmac => self.start_input(InputMode::HmacSecret),
+            SignAction::PrivateKey => self.start_input(InputMode::PrivateKey),
+            SignAction::KeyConfusion => self.start_input(InputMode::PublicKey),
+        }
+    }
+
+    /// Copies the edited token to the clipboard.
+    pub fn copy_token(&mut self) {
+        let token = match &self.tokens.output {
+            Ok(t) if !t.is_empty() => t.clone(),
+            _ => return,
+        };
+        self.message = Some(match clipboard::copy(&token) {
+            Ok(()) => "Copied token".to_string(),
+            Err(e) => format!("Unable to copy token. {}", e),
+        });
+    }
+
+    /// Adds a copy of the request the token was found in, with the edited token in place of the
+    /// original one, to the History.
+    pub fn add_token_request(&mut self) {
+        let (entry, original, token) = match (
+            self.tokens.entry,
+            self.tokens.original(),
+            &self.tokens.output,
+        ) {
+            (Some(entry), Some(original), Ok(token)) => {
+                (entry, original.to_string(), token.clone())
+            }
+            _ => return,
+        };
+        let edited = tokens::replace_token(&self.history.full(entry), &original, &token);
+        self.add_to_history(edited);
+        self.message = Some(format!(
+            "Added request {} with the edited token to History",
+            self.history.len()
+        ));
+    }
+
+    /// Selects an entry in the History tab, clearing the search and filter if they hide it.
+    pub fn show_in_history(&mut self, i: usize) {
+        self.refresh_visible();
+        if !self.visible.contains(&i) {
+            self.filter = Filter::default();
+            self.visible_dirty = true;
+            self.refresh_visible();
+        }
+        if let Some(row) = self.visible.iter().position(|&v| v == i) {
+            self.history_state.select(Some(row));
+            self.detail.reset_scroll();
+        }
+        self.go_to_tab(1);
+    }
+
+    /// Shows the exchange of the selected issue in the History tab.
+    pub fn show_issue_in_history(&mut self) {
+        if let Some(i) = self.issues.selected_entry() {
+            self.show_in_history(i);
+        }
+    }
+
+    /// Sends the selected request again. The exchange is added to the History once it completes.
+    pub fn send_selected(&mut self) {
+        if let Some(req) = self.selected_hist_item() {
+            self.repeater.send(&req);
+            let id = req.id;
+            self.message = Some(format!("Sending request {}", id));
+        }
+    }
+
+    /// Queues the active checks of the selected entry if its host is in scope.
+    pub fn start_active_scan(&mut self) {
+        let i = match self.selected_hist_index() {
+            Some(i) => i,
+            None => return,
+        };
+        let req = self.history.full(i);
+        self.message = Some(if self.scope.is_empty() {
+            "Set a scope to run the active scan on".to_string()
+        } else if req.status.is_none() {
+            format!("Request {} has no response to compare probes with", req.id)
+        } else if !self.scope.iter().any(|p| host_matches(p, &req.host)) {
+            format!("{} is not in scope", req.host)
+        } else {
+            let count = self.active_scan.scan(&req);
+            format!("Queued {} probes of request {}", count, req.id)
+        });
+    }
+
+    pub fn toggle_active_scan(&mut self) {
+        self.message = Some(if self.active_scan.toggle_pause() {
+            "Paused the active scan".to_string()
+        } else {
+            "Resumed the active scan".to_string()
+        });
+    }
+
+    /// Opens the context menu of the selected History entry next to its row.
+    pub fn open_menu(&mut self) {
+        let mut actions = MenuAction::ALL.to_vec();
+        actions.extend((0..self.extension_actions.len()).map(MenuAction::Extension));
+        self.open_menu_with(actions);
+    }
+
+    /// Opens a context menu with the given actions for the selected History entry.
+    pub fn open_menu_with(&mut self, actions: Vec<MenuAction>) {
+        let selected = match self.history_state.selected() {
+            Some(i) if self.index == 1 && i < self.visible.len() => i,
+            _ => return,
+        };
+        let table = self.areas.table;
+        let row = table.y + 1 + selected.saturating_sub(self.history_offset) as u16;
+        self.menu = Some(ContextMenu::new(table.x + 2, row, actions));
+    }
+
+    /// Runs the action selected in the context menu and closes it.
+    pub fn run_menu_action(&mut self) {
+        let menu = match self.menu.take() {
+            Some(m) => m,
+            None => return,
+        };
+        let action = match menu.selected() {
+            Some(a) => a,
+            None => return,
+        };
+
+        match action {
+            MenuAction::Highlight => self.cycle_highlight(),
+            MenuAction::Comment => self.start_input(InputMode::Comment),
+            MenuAction::Tags => self.start_input(InputMode::Tags),
+            MenuAction::FilterHost => {
+                if let Some(host) = self.selected_hist_item().map(|r| r.host.clone()) {
+                    self.apply_filter(format!("host:{}", host));
+                }
+            }
+            MenuAction::Export => self.export_history(),
+            MenuAction::Compare => self.mark_for_comparison(),
+            MenuAction::SendToDecoder => self.send_to_decoder(),
+            MenuAction::Send => self.send_selected(),
+            MenuAction::ActiveScan => self.start_active_scan(),
+            MenuAction::Extension(i) => self.run_extension_action(i),
+            MenuAction::AddStep(step) => self.decoder.add_step(step),
+            MenuAction::Sign(action) => self.sign_token(action),
+            MenuAction::AddTokenRequest => self.add_token_request(),
+            MenuAction::ResetToken => self.tokens.reset(),
+            MenuAction::Snippets => {
+                self.menu = Some(ContextMenu::new(
+                    menu.column,
+                    menu.row,
+                    MenuAction::snippets(),
+                ));
+            }
+            MenuAction::CopyAs(snippet) => self.copy_snippet(snippet),
+            MenuAction::SaveAs(snippet) => self.save_snippet(snippet),
+        }
+    }
+
+    /// Runs an action added to the context menu by an extension on the selected entry.
+    pub fn run_extension_action(&mut self, i: usize) {
+        let action = match self.extension_actions.get(i) {
+            Some(a) => Arc::clone(a),
+            None => return,
+        };
+        if let Some(req) = self.selected_hist_item() {
+            let message = action.run(Exchange::new(&req));
+            drop(req);
+            self.message = message;
+        }
+    }
+
+    /// Copies the selected request as a snippet to the clipboard.
+    pub fn copy_snippet(&mut self, snippet: Snippet) {
+        let code = match self.selected_hist_item() {
+            Some(req) => snippet.render(&req),
+            None => return,
+        };
+        self.message = Some(match clipboard::copy(&code) {
+            Ok(()) => format!("Copied request as {}", snippet.title()),
+            Err(e) => format!("Unable to copy request. {}", e),
+        });
+    }
+
+    /// Saves the selected request as a snippet to a file in the current directory.
+    pub fn save_snippet(&mut self, snippet: Snippet) {
+        let (id, code) = match self.selected_hist_item() {
+            Some(req) => (req.id, snippet.render(&req)),
+            None => return,
+        };
+        let path = format!("rudy-request-{}.{}", id, snippet.file_suffix());
+        self.message = Some(match std::fs::write(&path, code) {
+            Ok(()) => format!("Saved request as {} to {}", snippet.title(), path),
+            Err(e) => format!("Unable to save request. {}", e),
+        });
+    }
+
+    /// Opens the filter bar for typing a search, filter or annotation, prefilled with the
+    /// current one.
+    pub fn start_input(&mut self, mode: InputMode) {
+        let text = match mode {
+            InputMode::Search => self.filter.search_text.clone(),
+            InputMode::Filter => self.filter.query.clone(),
+            InputMode::Comment => match self.selected_hist_item() {
+                Some(req) => req.comment.clone(),
+                None => return,
+            },
+            InputMode::Tags => match self.selected_hist_item() {
+                Some(req) => req.tags.join(" "),
+                None => return,
+            },
+            InputMode::Detail => self.detail.search_text.clone(),
+            InputMode::Import => String::new(),
+            InputMode::Decoder => match String::from_utf8(self.decoder.input.clone()) {
+                Ok(text) => text,
+                Err(_) => {
+                    self.message = Some("Binary Decoder input can't be edited".to_string());
+                    return;
+                }
+            },
+            InputMode::Claim
+            | InputMode::HmacSecret
+            | InputMode::PrivateKey
+            | InputMode::PublicKey => String::new(),
+        };
+        if !matches!(
+            mode,
+            InputMode::Decoder
+                | InputMode::Claim
+                | InputMode::HmacSecret
+                | InputMode::PrivateKey
+                | InputMode::PublicKey
+        ) {
+            self.go_to_tab(1);
+        }
+        self.input = Some(Input { mode, text });
+        self.input_error = None;
+        self.message = None;
+    }
+
+    /// Applies the text typed into the filter bar.
+    pub fn submit_input(&mut self) {
+        let input = match self.input.take() {
+            Some(i) => i,
+            None => return,
+        };
+
+        match input.mode {
+            InputMode::Search => {
+                if let Err(e) = self.filter.set_search(&input.text) {
+                    self.input_error = Some(e);
+                    self.input = Some(input);
+                    return;
+                }
+            }
+            InputMode::Filter => {
+                if let Err(e) = self.filter.set_query(&input.text) {
+                    self.input_error = Some(e);
+                    self.input = Some(input);
+                    return;
+                }
+            }
+            InputMode::Comment => {
+                if let Some(i) = self.selected_hist_index() {
+                    self.history.get_mut(i).comment = input.text.trim().to_string();
+                    self.visible_dirty = true;
+                }
+                return;
+            }
+            InputMode::Tags => {
+                if let Some(i) = self.selected_hist_index() {
+                    self.history.get_mut(i).tags =
+                        input.text.split_whitespace().map(String::from).collect();
+                    self.visible_dirty = true;
+                }
+                return;
+            }
+            InputMode::Detail => {
+                if let Err(e) = self.detail.set_search(&input.text) {
+                    self.input_error = Some(regex_error(&e));
+                    self.input = Some(input);
+                    return;
+                }
+                self.detail.scroll_y = 0;
+                self.next_detail_match();
+                return;
+            }
+            InputMode::Decoder => {
+                self.decoder.set_input(input.text.into_bytes());
+                return;
+            }
+            InputMode::Claim => {
+                if let Err(e) = self.tokens.edit(&input.text) {
+                    self.input_error = Some(e);
+                    self.input = Some(input);
+                }
+                return;
+            }
+            InputMode::HmacSecret => {
+                self.tokens
+                    .set_signing(Signing::Hmac(input.text.into_bytes()));
+                return;
+            }
+            InputMode::PrivateKey => {
+                match jwt::load_private_key(input.text.trim()) {
+                    Ok(key) => self.tokens.set_signing(Signing::PrivateKey(Box::new(key))),
+                    Err(e) => {
+                        self.input_error = Some(e);
+                        self.input = Some(input);
+                    }
+                }
+                return;
+            }
+            InputMode::PublicKey => {
+                let path = input.text.trim();
+                match std::fs::read(path) {
+                    Ok(key) => self.tokens.set_signing(Signing::KeyConfusion(key)),
+                    Err(e) => {
+                        self.input_error = Some(format!("Unable to read '{}'. {}", path, e));
+                        self.input = Some(input);
+                    }
+                }
+                return;
+            }
+            InputMode::Import => {
+                match import::import(&input.text) {
+                    Ok(req) => self.import_request(req),
+                    Err(e) => {
+                        self.input_error = Some(e);
+                        self.input = Some(input);
+                    }
+                }
+                return;
+            }
+        }
+
+        self.visible_dirty = true;
+        self.refresh_visible();
+        let selected = if self.visible.is_empty() {
+            None
+        } else {
+            Some(0)
+        };
+        self.history_state.select(selected);
+    }
+
+    /// Adds an imported request to the History and selects it if it is shown.
+    fn import_request(&mut self, req: Req) {
+        self.add_to_history(req);
+        let id = self.history.len();
+        self.refresh_visible();
+        if let Some(i) = self.visible.iter().position(|&i| i + 1 == id) {
+            self.history_state.select(Some(i));
+            self.detail.reset_scroll();
+        }
+        self.message = Some(format!(
+            "Imported request {}. Press {} to send it",
+            id,
+            self.keymap.key(Action::Send)
+        ));
+    }
+
+    pub fn cancel_input(&mut self) {
+        self.input = None;
+        self.input_error = None;
     }
 }
 
-pub async fn run<'a>(app: App<'a>) -> Result<(), Box<dyn Error>> {
+/// Runs the UI until it is closed or the proxy stops, which ends the UI with the proxy's error.
+pub async fn run<'a>(
+    app: App<'a>,
+    proxy: impl Future<Output = Result<(), String>>,
+) -> Result<(), Box<dyn Error>> {
     // setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -117,9 +1218,7 @@ pub async fn run<'a>(app: App<'a>) -> Result<(), Box<dyn Error>> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    // create app and run it
-    // let app = App::new();
-    let res = run_app(&mut terminal, app).await;
+    let res = run_app(&mut terminal, app, proxy).await;
 
     // restore terminal
     disable_raw_mode()?;
@@ -130,52 +1229,251 @@ pub async fn run<'a>(app: App<'a>) -> Result<(), Box<dyn Error>> {
     )?;
     terminal.show_cursor()?;
 
-    if let Err(err) = res {
-        println!("{:?}", err)
-    }
-
-    Ok(())
+    res
 }
 
 pub async fn run_app<'a, B: Backend>(
     terminal: &mut Terminal<B>,
     mut app: App<'a>,
-) -> io::Result<()> {
+    proxy: impl Future<Output = Result<(), String>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut events = EventStream::new();
+    let mut tick = tokio::time::interval(TICK_RATE);
+    tokio::pin!(proxy);
+
     loop {
+        app.refresh_visible();
         terminal.draw(|f| ui(f, &mut app))?;
 
-        if let Event::Key(key) = event::read()? {
-            match key.code {
-                KeyCode::Char('q') => return Ok(()),
-                KeyCode::Tab => app.next_tab(),
-                KeyCode::BackTab => app.previous_tab(),
-                KeyCode::Char('i') => app.go_to_tab(0),
-                KeyCode::Char('h') => app.go_to_tab(1),
-                KeyCode::Char('s') => app.go_to_tab(2),
-                KeyCode::Char('j') => app.next_hist_item(),
-                KeyCode::Char('k') => app.prev_hist_item(),
-                _ => {}
+        tokio::select! {
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) => {
+                    if handle_key(&mut app, key) {
+                        if app.save_failed {
+                            return Ok(());
+                        }
+                        match app.save_project() {
+                            Ok(()) => return Ok(()),
+                            Err(e) => {
+                                app.save_failed = true;
+                                app.go_to_tab(1);
+                                app.message = Some(format!(
+                                    "Unable to save project. {}. Quit again to quit without saving",
+                                    e
+                                ));
+                            }
+                        }
+                    }
+                }
+                Some(Ok(Event::Mouse(mouse))) => mouse::handle_mouse(&mut app, mouse),
+                // Resize events only need a redraw.
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => return Ok(app.save_project()?),
+            },
+            Some(req) = app.receiver.recv() => {
+                app.add_to_history(req);
+                // Add everything else that arrived in the meantime before redrawing.
+                while let Ok(req) = app.receiver.try_recv() {
+                    app.add_to_history(req);
+                }
             }
-        } else {
-            while let Some(message) = app.receiver.recv().await {
-                let id = app.history.len() + 1;
-                let method = "GET";
-                let url = "http://test.com";
-                let status = 200;
-                let headers = vec![""];
-                app.history.push(Req {
-                    id,
-                    host: message,
-                    method,
-                    url,
-                    status,
-                    headers,
-                })
+            res = &mut proxy => {
+                // The project is still saved, but the proxy's error is the one reported.
+                let saved = app.save_project();
+                res?;
+                return Ok(saved?);
             }
+            _ = tick.tick() => {}
+            // Carries on with a search of the entries on disk.
+            _ = std::future::ready(()), if !app.visible_pending.is_empty() => {}
         }
     }
 }
 
+/// Handles a key press. Returns true if the app should quit.
+fn handle_key(app: &mut App, key: KeyEvent) -> bool {
+    app.refresh_visible();
+    if let Some(input) = &mut app.input {
+        match app.keymap.edit_action(key) {
+            // Pasted curl commands arrive one line at a time.
+            Some(EditAction::Submit)
+                if matches!(input.mode, InputMode::Import) && import::is_continued(&input.text) =>
+            {
+                input.text.push('\n')
+            }
+            Some(EditAction::Submit) => app.submit_input(),
+            Some(EditAction::Cancel) => app.cancel_input(),
+            Some(EditAction::DeleteChar) => {
+                input.text.pop();
+            }
+            Some(EditAction::DeleteWord) => {
+                let end = input.text.trim_end().len();
+                let start = input.text[..end].rfind(' ').map_or(0, |i| i + 1);
+                input.text.truncate(start);
+            }
+            Some(EditAction::Clear) => input.text.clear(),
+            None => {
+                if let KeyCode::Char(c) = key.code {
+                    if !key
+                        .modifiers
+                        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+                    {
+                        input.text.push(c);
+                    }
+                }
+            }
+        }
+        return false;
+    }
+
+    if !app.show_help && app.menu.is_none() && app.index >= EXTENSION_TABS_START {
+        let tab = &mut app.extension_tabs[app.index - EXTENSION_TABS_START];
+        if tab.handle_key(&Key::from(key).to_string()) {
+            return false;
+        }
+    }
+
+    let action = match app.keymap.action(key) {
+        Some(a) => a,
+        None => return false,
+    };
+
+    if app.show_help {
+        match action {
+            Action::Down | Action::ScrollDown => {
+                app.help_scroll = app.help_scroll.saturating_add(1)
+            }
+            Action::Up | Action::ScrollUp => app.help_scroll = app.help_scroll.saturating_sub(1),
+            Action::Help | Action::Close => app.show_help = false,
+            Action::Quit => return true,
+            _ => {}
+        }
+        return false;
+    }
+
+    if let Some(menu) = &mut app.menu {
+        match action {
+            Action::Down => menu.next(),
+            Action::Up => menu.previous(),
+            Action::Select => app.run_menu_action(),
+            Action::Close => app.menu = None,
+            _ => {}
+        }
+        return false;
+    }
+
+    match action {
+        Action::Quit => return true,
+        Action::Help => {
+            app.show_help = true;
+            app.help_scroll = 0;
+        }
+        Action::Close => {}
+        Action::NextTab => app.next_tab(),
+        Action::PreviousTab => app.previous_tab(),
+        Action::InterceptTab => app.go_to_tab(0),
+        Action::HistoryTab => app.go_to_tab(1),
+        Action::MapTab => app.go_to_tab(2),
+        Action::SettingsTab => app.go_to_tab(3),
+        Action::CompareTab => app.go_to_tab(4),
+        Action::DecoderTab => app.go_to_tab(5),
+        Action::TokensTab => app.go_to_tab(6),
+        Action::IssuesTab => app.go_to_tab(7),
+        Action::Down if app.index == 2 => app.next_map_item(),
+        Action::Up if app.index == 2 => app.site_map.previous(),
+        Action::First if app.index == 2 => app.site_map.state.select(Some(0)),
+        Action::Last if app.index == 2 => app.last_map_item(),
+        Action::Toggle if app.index == 2 => app.toggle_map_node(),
+        Action::Select if app.index == 2 => app.filter_to_map_node(),
+        Action::Down | Action::ScrollDown if app.index == 4 => app.compare.scroll_down(1),
+        Action::Up | Action::ScrollUp if app.index == 4 => app.compare.scroll_up(1),
+        Action::PageDown if app.index == 4 => app.compare.scroll_down(20),
+        Action::PageUp if app.index == 4 => app.compare.scroll_up(20),
+        Action::First if app.index == 4 => app.compare.scroll_y = 0,
+        Action::Toggle if app.index == 4 => app.compare.toggle_granularity(),
+        Action::NextDetailTab | Action::PreviousDetailTab if app.index == 4 => {
+            app.compare.next_part()
+        }
+        Action::NextMatch if app.index == 4 => app.compare.next_change(),
+        Action::Down if app.index == 5 => app.decoder.next(),
+        Action::Up if app.index == 5 => app.decoder.previous(),
+        Action::ScrollDown if app.index == 5 => app.decoder.scroll_down(1),
+        Action::ScrollUp if app.index == 5 => app.decoder.scroll_up(1),
+        Action::PageDown if app.index == 5 => app.decoder.scroll_down(20),
+        Action::PageUp if app.index == 5 => app.decoder.scroll_up(20),
+        Action::Select if app.index == 5 => app.open_step_menu(),
+        Action::Toggle if app.index == 5 => app.decoder.flip_step(),
+        Action::CopyAs if app.index == 5 => app.copy_decoder_output(),
+        Action::Send if app.index == 5 => app.add_decoder_request(),
+        Action::Edit if app.index == 5 => app.start_input(InputMode::Decoder),
+        Action::RemoveStep if app.index == 5 => app.decoder.remove_step(),
+        Action::Down if app.index == 6 => app.tokens.next(),
+        Action::Up if app.index == 6 => app.tokens.previous(),
+        Action::ScrollDown if app.index == 6 => app.tokens.scroll_down(1),
+        Action::ScrollUp if app.index == 6 => app.tokens.scroll_up(1),
+        Action::PageDown if app.index == 6 => app.tokens.scroll_down(20),
+        Action::PageUp if app.index == 6 => app.tokens.scroll_up(20),
+        Action::Select if app.index == 6 => app.open_token_menu(),
+        Action::CopyAs if app.index == 6 => app.copy_token(),
+        Action::Edit if app.index == 6 && !app.tokens.found.is_empty() => {
+            app.start_input(InputMode::Claim)
+        }
+        Action::Down if app.index == 7 => app.issues.next(),
+        Action::Up if app.index == 7 => app.issues.previous(),
+        Action::First if app.index == 7 => app.issues.select(0),
+        Action::Last if app.index == 7 => {
+            let last = app.issues.groups.len().saturating_sub(1);
+            app.issues.select(last)
+        }
+        Action::ScrollDown if app.index == 7 => app.issues.scroll_down(1),
+        Action::ScrollUp if app.index == 7 => app.issues.scroll_up(1),
+        Action::PageDown if app.index == 7 => app.issues.scroll_down(20),
+        Action::PageUp if app.index == 7 => app.issues.scroll_up(20),
+        Action::Select if app.index == 7 => app.show_issue_in_history(),
+        Action::NextMatch if app.index == 7 => app.issues.next_exchange(),
+        Action::Down => app.next_hist_item(),
+        Action::Up => app.prev_hist_item(),
+        Action::First => app.first_hist_item(),
+        Action::Last => app.last_hist_item(),
+        Action::Toggle => {}
+        Action::Select => app.open_menu(),
+        Action::CopyAs => app.open_menu_with(MenuAction::snippets()),
+        Action::Search => app.start_input(InputMode::Search),
+        Action::Filter => app.start_input(InputMode::Filter),
+        Action::SortColumn => app.next_sort_column(),
+        Action::ReverseSort => app.reverse_sort(),
+        Action::Highlight => app.cycle_highlight(),
+        Action::Comment => app.start_input(InputMode::Comment),
+        Action::Tags => app.start_input(InputMode::Tags),
+        Action::Save => {
+            if let Err(e) = app.save_project() {
+                app.message = Some(format!("Unable to save project. {}", e));
+            }
+        }
+        Action::Export => app.export_history(),
+        Action::NextDetailTab => app.detail.next_tab(),
+        Action::PreviousDetailTab => app.detail.previous_tab(),
+        Action::ScrollDown => app.detail.scroll_down(1),
+        Action::ScrollUp => app.detail.scroll_up(1),
+        Action::ScrollRight => app.detail.scroll_right(4),
+        Action::ScrollLeft => app.detail.scroll_left(4),
+        Action::PageDown => app.detail.scroll_down(20),
+        Action::PageUp => app.detail.scroll_up(20),
+        Action::ToggleWrap => app.detail.toggle_wrap(),
+        Action::FindInDetail => app.start_input(InputMode::Detail),
+        Action::Import => app.start_input(InputMode::Import),
+        Action::NextMatch => app.next_detail_match(),
+        Action::Compare => app.mark_for_comparison(),
+        Action::SendToDecoder => app.send_to_decoder(),
+        Action::Send => app.send_selected(),
+        Action::ActiveScan => app.start_active_scan(),
+        Action::PauseScan => app.toggle_active_scan(),
+        Action::Edit | Action::RemoveStep => {}
+    }
+    false
+}
+
 fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
     let size = f.size();
     let chunks = Layout::default()
@@ -188,25 +1486,26 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
     f.render_widget(block, size);
 
     let titles = app
-        .titles
-        .iter()
+        .titles()
+        .into_iter()
         .map(|t| {
-            let (first, rest) = t.split_at(1);
+            let (first, rest) = t.split_at(t.chars().next().map_or(0, char::len_utf8));
             Spans::from(vec![
-                Span::styled(first, Style::default().fg(Color::Red)),
-                Span::styled(rest, Style::default().fg(Color::Blue)),
+                Span::styled(first.to_string(), Style::default().fg(app.theme.tab_key)),
+                Span::styled(rest.to_string(), Style::default().fg(app.theme.tab)),
             ])
         })
         .collect();
     let tabs = Tabs::new(titles)
         .block(Block::default().borders(Borders::ALL).title("Rudy"))
         .select(app.index)
-        .style(Style::default().fg(Color::Red))
+        .style(Style::default().fg(app.theme.tab))
         .highlight_style(
             Style::default()
                 .add_modifier(Modifier::BOLD)
-                .bg(Color::Black),
+                .bg(app.theme.tab_selected_bg),
         );
+    app.areas.tabs = Block::default().borders(Borders::ALL).inner(chunks[0]);
     f.render_widget(tabs, chunks[0]);
 
     match app.index {
@@ -215,18 +1514,45 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
             chunks[1],
         ),
         1 => render_history(f, app, chunks[1]),
-        2 => f.render_widget(
+        2 => render_site_map(f, app, chunks[1]),
+        3 => f.render_widget(
             Block::default().title("Inner 2").borders(Borders::ALL),
             chunks[1],
         ),
-        _ => unreachable!(),
+        4 => render_compare(f, app, chunks[1]),
+        5 => render_decoder(f, app, chunks[1]),
+        6 => render_tokens(f, app, chunks[1]),
+        7 => render_issues(f, app, chunks[1]),
+        i => render_extension_tab(f, app, i - EXTENSION_TABS_START, chunks[1]),
     };
+
+    if app.menu.is_some() {
+        render_menu(f, app);
+    }
+    if app.show_help {
+        render_help(f, app);
+    }
 }
 
 fn render_history<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
+    let visible = app.visible.len();
+    let total = app.history.len();
+
     // Outer block
+    let count = if visible == total {
+        total.to_string()
+    } else {
+        format!("{} of {}", visible, total)
+    };
+    let direction = if app.sort.descending { "desc" } else { "asc" };
+    let title = format!(
+        "History ({}, sorted by {} {})",
+        count,
+        app.sort.column.title(),
+        direction
+    );
     let block = Block::default()
-        .title("History")
+        .title(title)
         .title_alignment(Alignment::Center)
         .borders(Borders::ALL);
     f.render_widget(block, area);
@@ -234,48 +1560,815 @@ fn render_history<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
     let h_chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(2)
-        .constraints([Constraint::Min(10), Constraint::Min(40)].as_ref())
+        .constraints(
+            [
+                Constraint::Length(1),
+                app.split.map_or(Constraint::Min(10), Constraint::Length),
+                Constraint::Min(if app.split.is_some() { 0 } else { 40 }),
+            ]
+            .as_ref(),
+        )
         .split(area);
 
-    // History list view
-    let rows: Vec<Row> = app.history.iter().map(|r| r.to_row()).collect();
+    render_filter_bar(f, app, h_chunks[0]);
+
+    // History list view. Only the rows which fit on the screen are built, so the table stays
+    // fast with a large history.
+    let height = h_chunks[1].height.saturating_sub(1) as usize;
+    let selected = app.history_state.selected().unwrap_or_default();
+    if selected < app.history_offset {
+        app.history_offset = selected;
+    } else if height > 0 && selected >= app.history_offset + height {
+        app.history_offset = selected + 1 - height;
+    }
+    let page_end = visible.min(app.history_offset + height);
+    let page = &app.visible[app.history_offset.min(page_end)..page_end];
+
+    let theme = &app.theme;
+    let rows: Vec<Row> = page
+        .iter()
+        .map(|&i| {
+            let req = app.history.get(i);
+            if let Some(h) = req.highlight {
+                let row = Row::new(app.columns.iter().map(|c| c.column.cell(&req)));
+                return row.style(Style::default().fg(theme.highlighted_fg).bg(h.color()));
+            }
+
+            Row::new(app.columns.iter().map(|c| {
+                let color = match c.column {
+                    Column::Method => Some(theme.method(&req.method)),
+                    Column::Status => req.status.map(|s| theme.status(s)),
+                    _ => None,
+                };
+                let cell = Cell::from(c.column.cell(&req));
+                match color {
+                    Some(color) => cell.style(Style::default().fg(color)),
+                    None => cell,
+                }
+            }))
+        })
+        .collect();
+    let header = app.columns.iter().map(|c| {
+        if c.column == app.sort.column {
+            let arrow = if app.sort.descending { "▼" } else { "▲" };
+            format!("{} {}", c.column.title(), arrow)
+        } else {
+            c.column.title().to_string()
+        }
+    });
+    let widths: Vec<Constraint> = app.columns.iter().map(Column::constraint).collect();
     let table = Table::new(rows)
-        .style(Style::default().fg(Color::White))
-        .header(
-            Row::new(vec!["ID", "Host", "Method", "URL", "Status"])
-                .style(Style::default().fg(Color::Yellow)),
+        .style(Style::default().fg(theme.text))
+        .header(Row::new(header).style(Style::default().fg(theme.accent)))
+        .widths(&widths)
+        .highlight_style(theme.selected());
+    let mut page_state = TableState::default();
+    page_state.select(
+        app.history_state
+            .selected()
+            .map(|i| i.saturating_sub(app.history_offset)),
+    );
+    app.areas.table = h_chunks[1];
+    f.render_stateful_widget(table, h_chunks[1], &mut page_state);
+
+    render_detail(f, app, h_chunks[2]);
+}
+
+fn render_detail<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(2), Constraint::Min(0)].as_ref())
+        .split(area);
+    app.areas.detail_tabs = chunks[0];
+    app.areas.detail = chunks[1];
+
+    let titles = DetailTab::ALL
+        .iter()
+        .map(|t| Spans::from(t.title()))
+        .collect();
+    let tabs = Tabs::new(titles)
+        .block(Block::default().borders(Borders::TOP))
+        .select(app.detail.tab_index())
+        .style(Style::default().fg(app.theme.detail_tab))
+        .highlight_style(
+            Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(app.theme.detail_tab_selected),
+        );
+    f.render_widget(tabs, chunks[0]);
+
+    let text = match app.selected_hist_index() {
+        Some(i) => app
+            .detail
+            .text(&app.history.full_cached(i), &app.theme, chunks[1]),
+        None => Text::default(),
+    };
+    let detail = Paragraph::new(text).scroll((app.detail.scroll_y, app.detail.scroll_x));
+    f.render_widget(detail, chunks[1]);
+}
+
+fn render_compare<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
+    let block = Block::default()
+        .title("Compare")
+        .title_alignment(Alignment::Center)
+        .borders(Borders::ALL);
+    f.render_widget(block, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(2)
+        .constraints(
+            [
+                Constraint::Length(1),
+                Constraint::Length(2),
+                Constraint::Min(0),
+            ]
+            .as_ref(),
         )
-        .widths(&[
-            Constraint::Length(5),
-            Constraint::Min(30),
-            Constraint::Min(10),
-            Constraint::Min(40),
-            Constraint::Min(10),
-        ])
+        .split(area);
+    app.areas.compare_tabs = chunks[1];
+    app.areas.compare = chunks[2];
+
+    let compare_key = app.keymap.key(Action::Compare);
+    let (first, second) = match (app.compare.first, app.compare.second) {
+        (Some(first), Some(second)) => (first, second),
+        (Some(first), None) => {
+            let hint = format!(
+                "Marked {}. Press {} on another History entry to compare them.",
+                app.history.get(first).id,
+                compare_key
+            );
+            let hint = Paragraph::new(hint).style(Style::default().fg(app.theme.dim));
+            f.render_widget(hint, chunks[0]);
+            return;
+        }
+        _ => {
+            let hint = format!(
+                "Press {} on two History entries to compare them.",
+                compare_key
+            );
+            let hint = Paragraph::new(hint).style(Style::default().fg(app.theme.dim));
+            f.render_widget(hint, chunks[0]);
+            return;
+        }
+    };
+
+    let (first_id, second_id) = (app.history.get(first).id, app.history.get(second).id);
+    let granularity = app.compare.granularity;
+    let (part, scroll_y) = (app.compare.part, app.compare.scroll_y);
+    let theme = &app.theme;
+    let diff = match app.compare.diff(&app.history, theme) {
+        Some(d) => d,
+        None => {
+            let hint = format!("Comparing {} and {}…", first_id, second_id);
+            let hint = Paragraph::new(hint).style(Style::default().fg(theme.dim));
+            f.render_widget(hint, chunks[0]);
+            return;
+        }
+    };
+
+    let summary = if diff.too_large {
+        "too many differences to show them one by one".to_string()
+    } else {
+        match diff.changes.len() {
+            0 => "identical".to_string(),
+            1 => "1 difference".to_string(),
+            n => format!("{} differences", n),
+        }
+    };
+    let info = Spans::from(vec![
+        Span::styled(
+            format!(
+                "{} and {} by {}: {}",
+                first_id,
+                second_id,
+                granularity.title(),
+                summary
+            ),
+            Style::default().fg(theme.accent),
+        ),
+        Span::styled(
+            format!(
+                "  ({} words or bytes, {} next difference)",
+                app.keymap.key(Action::Toggle),
+                app.keymap.key(Action::NextMatch)
+            ),
+            Style::default().fg(theme.dim),
+        ),
+    ]);
+    f.render_widget(Paragraph::new(info), chunks[0]);
+
+    let titles = Part::ALL.iter().map(|p| Spans::from(p.title())).collect();
+    let selected = Part::ALL
+        .iter()
+        .position(|&p| p == part)
+        .unwrap_or_default();
+    let tabs = Tabs::new(titles)
+        .block(Block::default().borders(Borders::TOP))
+        .select(selected)
+        .style(Style::default().fg(theme.detail_tab))
         .highlight_style(
             Style::default()
                 .add_modifier(Modifier::BOLD)
-                .fg(Color::Green)
-                .bg(Color::Gray),
+                .fg(theme.detail_tab_selected),
         );
-    f.render_stateful_widget(table, h_chunks[0], &mut app.history_state);
+    f.render_widget(tabs, chunks[1]);
 
-    // History detail view
-    let detail = match app.history_state.selected() {
-        Some(i) => {
-            if !app.history.is_empty() {
-                app.history[i].to_paragraph()
-            } else {
-                Paragraph::new("")
+    let text = Text::from(diff.lines.clone());
+    let diff = Paragraph::new(text).scroll((scroll_y, 0));
+    f.render_widget(diff, chunks[2]);
+}
+
+fn render_decoder<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
+    let block = Block::default()
+        .title("Decoder")
+        .title_alignment(Alignment::Center)
+        .borders(Borders::ALL);
+    f.render_widget(block, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(2)
+        .constraints([Constraint::Length(2), Constraint::Min(0)].as_ref())
+        .split(area);
+    let panes = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(
+            [
+                Constraint::Length(40),
+                Constraint::Length(1),
+                Constraint::Min(0),
+            ]
+            .as_ref(),
+        )
+        .split(chunks[1]);
+    app.areas.decoder_steps = panes[0];
+    app.areas.decoder_output = panes[2];
+
+    if matches!(&app.input, Some(i) if matches!(i.mode, InputMode::Decoder)) {
+        render_filter_bar(f, app, chunks[0]);
+    } else {
+        let mut spans = vec![Span::styled(
+            format!(
+                "{} add a step, {} decode or encode, {} remove, {} edit input, {} copy output, {} \
+                 add to the request",
+                app.keymap.key(Action::Select),
+                app.keymap.key(Action::Toggle),
+                app.keymap.key(Action::RemoveStep),
+                app.keymap.key(Action::Edit),
+                app.keymap.key(Action::CopyAs),
+                app.keymap.key(Action::Send)
+            ),
+            Style::default().fg(app.theme.dim),
+        )];
+        if let Some(message) = &app.message {
+            spans.push(Span::styled(
+                format!("  {}", message),
+                Style::default().fg(app.theme.info),
+            ));
+        }
+        f.render_widget(Paragraph::new(Spans::from(spans)), chunks[0]);
+    }
+
+    let theme = &app.theme;
+    let steps = app.decoder.steps.clone();
+    let selected = app.decoder.selected;
+    let scroll_y = app.decoder.scroll_y;
+    let outputs = app.decoder.outputs();
+
+    let size = |i: usize| match outputs.get(i) {
+        Some(Ok(bytes)) => format!(" ({} bytes)", bytes.len()),
+        Some(Err(_)) => " (failed)".to_string(),
+        None => String::new(),
+    };
+    let mut items = vec![ListItem::new(format!("Input{}", size(0)))];
+    for (i, step) in steps.iter().enumerate() {
+        let item = ListItem::new(format!("{}. {}{}", i + 1, step.title(), size(i + 1)));
+        items.push(match outputs.get(i + 1) {
+            Some(Err(_)) => item.style(Style::default().fg(theme.error)),
+            Some(Ok(_)) => item,
+            None => item.style(Style::default().fg(theme.dim)),
+        });
+    }
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::RIGHT))
+        .highlight_style(theme.selected());
+    let mut state = ListState::default();
+    state.select(Some(selected));
+    f.render_stateful_widget(list, panes[0], &mut state);
+
+    let output = match outputs.get(selected) {
+        Some(Ok(bytes)) => Paragraph::new(decoder::display(bytes)),
+        Some(Err(e)) => Paragraph::new(e.clone()).style(Style::default().fg(theme.error)),
+        None => Paragraph::new("Not run because an earlier step failed")
+            .style(Style::default().fg(theme.dim)),
+    };
+    let output = output.wrap(Wrap { trim: false }).scroll((scroll_y, 0));
+    f.render_widget(output, panes[2]);
+}
+
+fn render_tokens<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
+    app.sync_tokens();
+    let block = Block::default()
+        .title("Tokens")
+        .title_alignment(Alignment::Center)
+        .borders(Borders::ALL);
+    f.render_widget(block, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(2)
+        .constraints([Constraint::Length(2), Constraint::Min(0)].as_ref())
+        .split(area);
+    let panes = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(
+            [
+                Constraint::Length(40),
+                Constraint::Length(1),
+                Constraint::Min(0),
+            ]
+            .as_ref(),
+        )
+        .split(chunks[1]);
+    app.areas.token_list = panes[0];
+    app.areas.token_detail = panes[2];
+
+    let editing = matches!(
+        &app.input,
+        Some(i) if matches!(
+            i.mode,
+            InputMode::Claim | InputMode::HmacSecret | InputMode::PrivateKey | InputMode::PublicKey
+        )
+    );
+    if editing {
+        render_filter_bar(f, app, chunks[0]);
+    } else {
+        let mut spans = vec![Span::styled(
+            format!(
+                "{} edit a claim, {} sign or add to History, {} copy token",
+                app.keymap.key(Action::Edit),
+                app.keymap.key(Action::Select),
+                app.keymap.key(Action::CopyAs)
+            ),
+            Style::default().fg(app.theme.dim),
+        )];
+        if let Some(message) = &app.message {
+            spans.push(Span::styled(
+                format!("  {}", message),
+                Style::default().fg(app.theme.info),
+            ));
+        }
+        f.render_widget(Paragraph::new(Spans::from(spans)), chunks[0]);
+    }
+
+    let theme = &app.theme;
+    let tokens = &app.tokens;
+    if tokens.found.is_empty() {
+        let hint = if tokens.entry.is_some() {
+            "No JWTs in the selected History entry"
+        } else {
+            "Select a History entry to inspect its JWTs"
+        };
+        let hint = Paragraph::new(hint).style(Style::default().fg(theme.dim));
+        f.render_widget(hint, chunks[1]);
+        return;
+    }
+
+    let items: Vec<ListItem> = tokens
+        .found
+        .iter()
+        .map(|f| ListItem::new(format!("{} ({})", f.location, f.jwt.alg())))
+        .collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::RIGHT))
+        .highlight_style(theme.selected());
+    let mut state = ListState::default();
+    state.select(Some(tokens.selected));
+    f.render_stateful_widget(list, panes[0], &mut state);
+
+    let label = Style::default().fg(theme.accent);
+    let mut lines = vec![Spans::from(vec![
+        Span::styled("Signing: ", label),
+        Span::raw(tokens.signing.describe()),
+    ])];
+    match &tokens.output {
+        Ok(token) => {
+            if tokens.original() != Some(token.as_str()) {
+                lines.push(Spans::from(Span::styled("Edited", label)));
             }
+            lines.push(Spans::from(token.clone()));
         }
-        None => Paragraph::new(""),
+        Err(e) => lines.push(Spans::from(Span::styled(
+            e.clone(),
+            Style::default().fg(theme.error),
+        ))),
+    }
+    lines.push(Spans::default());
+    let description = match tokens.output.as_deref().ok().and_then(jwt::Jwt::parse) {
+        Some(edited) => edited.describe(),
+        None => jwt::describe(&tokens.header, &tokens.claims),
+    };
+    lines.extend(description.lines().map(|l| Spans::from(l.to_string())));
+
+    let detail = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .scroll((tokens.scroll_y, 0));
+    f.render_widget(detail, panes[2]);
+}
+
+fn render_issues<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
+    app.issues.update(&app.history);
+    let block = Block::default()
+        .title("Issues")
+        .title_alignment(Alignment::Center)
+        .borders(Borders::ALL);
+    f.render_widget(block, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(2)
+        .constraints([Constraint::Length(2), Constraint::Min(0)].as_ref())
+        .split(area);
+    let panes = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(
+            [
+                Constraint::Percentage(50),
+                Constraint::Length(1),
+                Constraint::Min(0),
+            ]
+            .as_ref(),
+        )
+        .split(chunks[1]);
+    app.areas.issue_list = panes[0];
+    app.areas.issue_detail = panes[2];
+
+    let theme = &app.theme;
+    let mut spans = vec![];
+    for (severity, count) in app.issues.counts() {
+        spans.push(Span::styled(
+            format!("{} {}  ", count, severity.name().to_lowercase()),
+            Style::default().fg(theme.severity(severity)),
+        ));
+    }
+    spans.push(Span::styled(
+        format!(
+            "{} show in History, {} next exchange",
+            app.keymap.key(Action::Select),
+            app.keymap.key(Action::NextMatch)
+        ),
+        Style::default().fg(theme.dim),
+    ));
+    if let Some(message) = &app.message {
+        spans.push(Span::styled(
+            format!("  {}", message),
+            Style::default().fg(theme.info),
+        ));
+    }
+    let mut lines = vec![Spans::from(spans)];
+    let scan = app.active_scan.status();
+    if scan.sent > 0 || scan.queued > 0 || scan.paused {
+        lines.push(Spans::from(Span::styled(
+            format!(
+                "Active scan{}: {} queued, {} running, {} sent, {} found  {} {}",
+                if scan.paused { " paused" } else { "" },
+                scan.queued,
+                scan.running,
+                scan.sent,
+                scan.found,
+                app.keymap.key(Action::PauseScan),
+                if scan.paused { "resume" } else { "pause" },
+            ),
+            Style::default().fg(theme.dim),
+        )));
+    }
+    f.render_widget(Paragraph::new(lines), chunks[0]);
+
+    let issues = &mut app.issues;
+    if issues.groups.is_empty() {
+        let hint =
+            Paragraph::new("No issues found by the scanners").style(Style::default().fg(theme.dim));
+        f.render_widget(hint, chunks[1]);
+        return;
+    }
+
+    issues.scroll_to_selected(panes[0].height as usize);
+    let items: Vec<ListItem> = issues
+        .groups
+        .iter()
+        .skip(issues.offset)
+        .map(|g| {
+            ListItem::new(Spans::from(vec![
+                Span::styled(
+                    format!("{:<7}", g.severity.name()),
+                    Style::default().fg(theme.severity(g.severity)),
+                ),
+                Span::raw(format!("{}  ", g.title)),
+                Span::styled(
+                    format!("{} ({})", g.host, g.found.len()),
+                    Style::default().fg(theme.dim),
+                ),
+            ]))
+        })
+        .collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::RIGHT))
+        .highlight_style(theme.selected());
+    let mut state = ListState::default();
+    state.select(Some(issues.selected - issues.offset));
+    f.render_stateful_widget(list, panes[0], &mut state);
+
+    let group = &issues.groups[issues.selected];
+    let label = Style::default().fg(theme.accent);
+    let (entry, evidence) = &group.found[issues.exchange];
+    let req = app.history.get(*entry);
+    let mut lines = vec![
+        Spans::from(Span::styled(
+            group.title.clone(),
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+        Spans::from(vec![
+            Span::styled("Severity: ", label),
+            Span::styled(
+                group.severity.name(),
+                Style::default().fg(theme.severity(group.severity)),
+            ),
+        ]),
+        Spans::from(vec![
+            Span::styled("Host: ", label),
+            Span::raw(group.host.clone()),
+        ]),
+        Spans::default(),
+        Spans::from(vec![
+            Span::styled(
+                format!(
+                    "Exchange {} of {}: ",
+                    issues.exchange + 1,
+                    group.found.len()
+                ),
+                label,
+            ),
+            Span::raw(format!("request {}", req.id)),
+        ]),
+        Spans::from(format!("{} {}", req.method, req.url)),
+    ];
+    if !evidence.is_empty() {
+        lines.push(Spans::from(vec![
+            Span::styled("Evidence: ", label),
+            Span::raw(evidence.clone()),
+        ]));
+    }
+
+    let detail = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .scroll((issues.scroll_y, 0));
+    f.render_widget(detail, panes[2]);
+}
+
+fn render_site_map<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
+    let block = Block::default()
+        .title(format!(
+            "Site map ({} to expand, {} to show in History)",
+            app.keymap.key(Action::Toggle),
+            app.keymap.key(Action::Select)
+        ))
+        .title_alignment(Alignment::Center)
+        .borders(Borders::ALL);
+    let inner = block.inner(area);
+    app.areas.site_map = inner;
+
+    // Like the History table, only the visible page is rendered, so the offset of the first line
+    // is known when the list is clicked.
+    let lines = app.site_map.lines(&app.history);
+    let height = inner.height as usize;
+    let selected = app.site_map.state.selected().unwrap_or_default();
+    if selected < app.site_map.offset {
+        app.site_map.offset = selected;
+    } else if height > 0 && selected >= app.site_map.offset + height {
+        app.site_map.offset = selected + 1 - height;
     }
-    .block(
-        Block::default()
-            .title("Request")
-            .title_alignment(Alignment::Center)
-            .borders(Borders::TOP),
+    let page_end = lines.len().min(app.site_map.offset + height);
+
+    let theme = &app.theme;
+    let items: Vec<ListItem> = lines
+        .into_iter()
+        .take(page_end)
+        .skip(app.site_map.offset)
+        .map(|line| {
+            let marker = match (line.has_children, line.expanded) {
+                (false, _) => "  ",
+                (true, false) => "▸ ",
+                (true, true) => "▾ ",
+            };
+            let mut spans = vec![
+                Span::raw(format!(
+                    "{}{}{}",
+                    "  ".repeat(line.depth),
+                    marker,
+                    line.label
+                )),
+                Span::styled(
+                    format!("  ({}) ", line.count),
+                    Style::default().fg(theme.accent),
+                ),
+            ];
+            spans.extend(line.statuses.iter().map(|&status| {
+                Span::styled(
+                    format!(" {}", status),
+                    Style::default().fg(theme.status(status)),
+                )
+            }));
+            ListItem::new(Spans::from(spans))
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(theme.selected());
+    let mut page_state = ListState::default();
+    page_state.select(
+        app.site_map
+            .state
+            .selected()
+            .map(|i| i.saturating_sub(app.site_map.offset)),
+    );
+    f.render_stateful_widget(list, area, &mut page_state);
+}
+
+/// Renders the key bindings of the active keymap over the current tab.
+fn render_help<B: Backend>(f: &mut Frame<B>, app: &App) {
+    let help_lines = |title: &'static str, bindings: Vec<(String, &'static str)>| {
+        let mut lines = vec![Spans::from(Span::styled(
+            title,
+            Style::default()
+                .fg(app.theme.accent)
+                .add_modifier(Modifier::BOLD),
+        ))];
+        lines.extend(bindings.into_iter().map(|(keys, description)| {
+            Spans::from(vec![
+                Span::styled(
+                    format!("  {:<24}", keys),
+                    Style::default().fg(app.theme.info),
+                ),
+                Span::raw(description),
+            ])
+        }));
+        lines
+    };
+    let mut lines = help_lines("Navigation", app.keymap.navigation_help());
+    lines.push(Spans::default());
+    lines.extend(help_lines("Typing", app.keymap.input_help()));
+
+    let size = f.size();
+    let width = 100.min(size.width);
+    let height = (lines.len() as u16 + 2).min(size.height);
+    let area = Rect {
+        x: (size.width - width) / 2,
+        y: (size.height - height) / 2,
+        width,
+        height,
+    };
+
+    let help = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .title(format!("Keys ({} to close)", app.keymap.key(Action::Close)))
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL),
+        )
+        .scroll((app.help_scroll, 0));
+    f.render_widget(Clear, area);
+    f.render_widget(help, area);
+}
+
+/// Renders a tab added by an extension with the text it returns for the selected History entry.
+fn render_extension_tab<B: Backend>(f: &mut Frame<B>, app: &mut App, i: usize, area: Rect) {
+    let block = Block::default()
+        .title(app.extension_tabs[i].title())
+        .title_alignment(Alignment::Center)
+        .borders(Borders::ALL);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let selected = app
+        .selected_hist_index()
+        .map(|i| app.history.full_cached(i));
+    let tab = &mut app.extension_tabs[i];
+    let text = tab.render(
+        selected.as_deref().map(Exchange::new),
+        inner.width,
+        inner.height,
     );
-    f.render_widget(detail, h_chunks[1]);
+    f.render_widget(Paragraph::new(text), inner);
+}
+
+/// Renders the context menu at the position it was opened, moved to fit on the screen.
+fn render_menu<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    let menu = match &mut app.menu {
+        Some(m) => m,
+        None => return,
+    };
+
+    let extension_actions = &app.extension_actions;
+    let title = |action: &MenuAction| match action {
+        MenuAction::Extension(i) => extension_actions[*i].title(),
+        action => action.title(),
+    };
+
+    let size = f.size();
+    let width = menu
+        .actions
+        .iter()
+        .map(|a| title(a).chars().count() as u16 + 4)
+        .max()
+        .unwrap_or_default()
+        .min(size.width);
+    let height = (menu.actions.len() as u16 + 2).min(size.height);
+    let area = Rect {
+        x: menu.column.min(size.width - width),
+        y: menu.row.min(size.height - height),
+        width,
+        height,
+    };
+
+    let items: Vec<ListItem> = menu
+        .actions
+        .iter()
+        .map(|a| ListItem::new(title(a)))
+        .collect();
+    let block = Block::default().borders(Borders::ALL);
+    app.areas.menu = block.inner(area);
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(app.theme.selected());
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(list, area, &mut menu.state);
+}
+
+fn render_filter_bar<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
+    let line = match &app.input {
+        Some(input) => {
+            let prompt = match input.mode {
+                InputMode::Search => "Search: ",
+                InputMode::Filter => "Filter: ",
+                InputMode::Comment => "Comment: ",
+                InputMode::Tags => "Tags: ",
+                InputMode::Detail => "Find in detail: ",
+                InputMode::Import => "Import curl command or file: ",
+                InputMode::Decoder => "Decoder input: ",
+                InputMode::Claim => "Set claim (name=value, header.name=value or -name): ",
+                InputMode::HmacSecret => "HMAC secret: ",
+                InputMode::PrivateKey => "Private key file: ",
+                InputMode::PublicKey => "Public key file: ",
+            };
+            let mut spans = vec![
+                Span::styled(prompt, Style::default().fg(app.theme.accent)),
+                Span::raw(input.text.replace('\n', " ")),
+                Span::styled(" ", Style::default().bg(app.theme.cursor)),
+            ];
+            if let Some(e) = &app.input_error {
+                spans.push(Span::styled(
+                    format!("  {}", e),
+                    Style::default().fg(app.theme.error),
+                ));
+            }
+            Spans::from(spans)
+        }
+        None => {
+            let mut spans = if app.filter.search_text.is_empty() && app.filter.query.is_empty() {
+                vec![Span::styled(
+                    format!(
+                        "Press {} to search, {} to filter, {} for help",
+                        app.keymap.key(Action::Search),
+                        app.keymap.key(Action::Filter),
+                        app.keymap.key(Action::Help)
+                    ),
+                    Style::default().fg(app.theme.dim),
+                )]
+            } else {
+                vec![
+                    Span::styled("Search: ", Style::default().fg(app.theme.accent)),
+                    Span::raw(app.filter.search_text.clone()),
+                    Span::styled("  Filter: ", Style::default().fg(app.theme.accent)),
+                    Span::raw(app.filter.query.clone()),
+                ]
+            };
+            if !app.visible_pending.is_empty() {
+                spans.push(Span::styled(
+                    format!("  Searching {} entries on disk", app.visible_pending.len()),
+                    Style::default().fg(app.theme.dim),
+                ));
+            }
+            if let Some(message) = &app.message {
+                spans.push(Span::styled(
+                    format!("  {}", message),
+                    Style::default().fg(app.theme.info),
+                ));
+            }
+            Spans::from(spans)
+        }
+    };
+
+    f.render_widget(Paragraph::new(line), area);
 }
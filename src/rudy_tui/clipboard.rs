@@ -0,0 +1,18 @@
+use std::io::{self, Write};
+
+/// Copies text to the system clipboard with the OSC 52 escape sequence. The terminal sets the
+/// clipboard, so this works over SSH as long as the terminal supports it.
+pub fn copy(text: &str) -> io::Result<()> {
+    let sequence = format!("\x1b]52;c;{}\x07", base64::encode(text));
+
+    // tmux only passes escape sequences through to the terminal if they are wrapped.
+    let sequence = if std::env::var_os("TMUX").is_some() {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    };
+
+    let mut stdout = io::stdout();
+    stdout.write_all(sequence.as_bytes())?;
+    stdout.flush()
+}
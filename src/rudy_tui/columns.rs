@@ -0,0 +1,148 @@
+use super::Req;
+use crate::settings::{Column, ColumnSettings};
+use std::cmp::Ordering;
+use tui::layout::Constraint;
+
+/// Column and direction the History table is sorted by.
+pub struct Sort {
+    pub column: Column,
+    pub descending: bool,
+}
+
+impl Default for Sort {
+    fn default() -> Self {
+        Sort {
+            column: Column::Id,
+            descending: false,
+        }
+    }
+}
+
+impl Sort {
+    /// Sorts by the next column, in the order of [`Column::ALL`].
+    pub fn next_column(&mut self) {
+        let i = Column::ALL
+            .iter()
+            .position(|c| *c == self.column)
+            .unwrap_or_default();
+        self.column = Column::ALL[(i + 1) % Column::ALL.len()];
+    }
+
+    pub fn reverse(&mut self) {
+        self.descending = !self.descending;
+    }
+
+    pub fn compare(&self, a: &Req, b: &Req) -> Ordering {
+        let ordering = self.column.compare(a, b).then(a.id.cmp(&b.id));
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+impl Column {
+    pub const ALL: [Column; 12] = [
+        Column::Id,
+        Column::Host,
+        Column::Method,
+        Column::Url,
+        Column::Status,
+        Column::Length,
+        Column::Duration,
+        Column::Time,
+        Column::Mime,
+        Column::Listener,
+        Column::Comment,
+        Column::Tags,
+    ];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Column::Id => "ID",
+            Column::Host => "Host",
+            Column::Method => "Method",
+            Column::Url => "URL",
+            Column::Status => "Status",
+            Column::Length => "Length",
+            Column::Duration => "Duration",
+            Column::Time => "Time",
+            Column::Mime => "MIME type",
+            Column::Listener => "Listener",
+            Column::Comment => "Comment",
+            Column::Tags => "Tags",
+        }
+    }
+
+    fn default_constraint(&self) -> Constraint {
+        match self {
+            Column::Id => Constraint::Length(5),
+            Column::Host => Constraint::Min(30),
+            Column::Method => Constraint::Min(10),
+            Column::Url => Constraint::Min(40),
+            Column::Status => Constraint::Min(10),
+            Column::Length => Constraint::Length(10),
+            Column::Duration => Constraint::Length(10),
+            Column::Time => Constraint::Length(10),
+            Column::Mime => Constraint::Min(20),
+            Column::Listener => Constraint::Length(16),
+            Column::Comment => Constraint::Min(30),
+            Column::Tags => Constraint::Min(20),
+        }
+    }
+
+    pub fn constraint(settings: &ColumnSettings) -> Constraint {
+        match settings.width {
+            Some(w) => Constraint::Length(w),
+            None => settings.column.default_constraint(),
+        }
+    }
+
+    pub fn cell(&self, req: &Req) -> String {
+        match self {
+            Column::Id => req.id.to_string(),
+            Column::Host => req.host.clone(),
+            Column::Method => req.method.clone(),
+            Column::Url => req.url.clone(),
+            Column::Status => match (req.status, &req.error, &req.tunnel) {
+                (_, Some(_), _) => "Error".to_string(),
+                (_, None, Some(_)) => "Tunnel".to_string(),
+                (Some(s), None, None) => s.to_string(),
+                (None, None, None) => String::new(),
+            },
+            Column::Length => length(req).to_string(),
+            Column::Duration => req
+                .duration
+                .map(|d| format!("{} ms", d.as_millis()))
+                .unwrap_or_default(),
+            Column::Time => req
+                .timestamp
+                .map(|t| format!("{:02}:{:02}:{:02}", t.hour(), t.minute(), t.second()))
+                .unwrap_or_default(),
+            Column::Mime => req.mime_type().unwrap_or_default().to_string(),
+            Column::Listener => req.listener.clone(),
+            Column::Comment => req.comment.clone(),
+            Column::Tags => req.tags.join(", "),
+        }
+    }
+
+    fn compare(&self, a: &Req, b: &Req) -> Ordering {
+        match self {
+            Column::Id => a.id.cmp(&b.id),
+            Column::Status => a.status.cmp(&b.status),
+            Column::Length => length(a).cmp(&length(b)),
+            Column::Duration => a.duration.cmp(&b.duration),
+            Column::Time => a.timestamp.cmp(&b.timestamp),
+            _ => self.cell(a).cmp(&self.cell(b)),
+        }
+    }
+}
+
+/// Length of the response body, or the bytes received for a tunnel.
+fn length(req: &Req) -> u64 {
+    match &req.tunnel {
+        Some(t) => t.bytes_received,
+        None => req.res_body_len(),
+    }
+}
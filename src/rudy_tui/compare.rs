@@ -0,0 +1,635 @@
+use super::{history::History, theme::Theme, Req};
+use crate::settings::CompareSettings;
+use hudsucker::hyper::Version;
+use std::{
+    collections::HashMap,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+use tui::{
+    style::{Modifier, Style},
+    text::{Span, Spans},
+};
+
+/// Number of inserted and deleted tokens after which the diff gives up and shows the differing
+/// parts as a single change.
+const MAX_EDITS: usize = 2_000;
+
+/// Number of differing tokens above which the texts are not diffed, and the differing parts are
+/// shown as a single change.
+const MAX_TOKENS: usize = 100_000;
+
+/// Part of the compared exchanges which is diffed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Part {
+    Request,
+    Response,
+}
+
+impl Part {
+    pub const ALL: [Part; 2] = [Part::Request, Part::Response];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Part::Request => "Request",
+            Part::Response => "Response",
+        }
+    }
+}
+
+/// Unit the compared texts are split into before diffing them.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Granularity {
+    Words,
+    Bytes,
+}
+
+impl Granularity {
+    pub fn title(&self) -> &'static str {
+        match self {
+            Granularity::Words => "words",
+            Granularity::Bytes => "bytes",
+        }
+    }
+}
+
+/// State of the Compare tab: the two History entries being compared and how they are diffed.
+pub struct Compare {
+    /// Indices in the history of the compared entries. The first one is set when an entry is
+    /// marked and the second one when another entry is marked after it.
+    pub first: Option<usize>,
+    pub second: Option<usize>,
+    pub part: Part,
+    pub granularity: Granularity,
+    pub scroll_y: u16,
+    /// Lower case names of the headers left out of the diff.
+    pub ignore_headers: Vec<String>,
+    /// The diff of the current entries, part and granularity, computed in the background when
+    /// first shown.
+    diff: Option<(DiffKey, Diff)>,
+    pending: Option<(DiffKey, Receiver<Diff>)>,
+}
+
+/// What a diff was computed from: the entries with the state of their responses, which changes
+/// when a pending response arrives, the part and the granularity.
+#[derive(Clone, PartialEq)]
+struct DiffKey {
+    entries: [(usize, Option<u16>, u64, bool); 2],
+    part: Part,
+    granularity: Granularity,
+}
+
+/// Diff of two texts, rendered with the removed and added parts inline.
+pub struct Diff {
+    pub lines: Vec<Spans<'static>>,
+    /// Line of each change, in order.
+    pub changes: Vec<usize>,
+    /// Whether the texts differ too much to find the single changes.
+    pub too_large: bool,
+}
+
+impl Compare {
+    pub fn new(settings: &CompareSettings) -> Self {
+        Compare {
+            first: None,
+            second: None,
+            part: Part::Response,
+            granularity: Granularity::Words,
+            scroll_y: 0,
+            ignore_headers: settings
+                .ignore_headers
+                .iter()
+                .map(|h| h.to_lowercase())
+                .collect(),
+            diff: None,
+            pending: None,
+        }
+    }
+
+    /// Marks a history entry for comparison. Returns true if it was the second entry, so both
+    /// entries are set.
+    pub fn mark(&mut self, i: usize) -> bool {
+        if self.second.is_none() && self.first.is_some() && self.first != Some(i) {
+            self.second = Some(i);
+            self.reset();
+            return true;
+        }
+        self.first = Some(i);
+        self.second = None;
+        self.reset();
+        false
+    }
+
+    pub fn next_part(&mut self) {
+        self.part = match self.part {
+            Part::Request => Part::Response,
+            Part::Response => Part::Request,
+        };
+        self.reset();
+    }
+
+    pub fn toggle_granularity(&mut self) {
+        self.granularity = match self.granularity {
+            Granularity::Words => Granularity::Bytes,
+            Granularity::Bytes => Granularity::Words,
+        };
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.diff = None;
+        self.scroll_y = 0;
+    }
+
+    pub fn scroll_down(&mut self, lines: u16) {
+        self.scroll_y = self.scroll_y.saturating_add(lines);
+    }
+
+    pub fn scroll_up(&mut self, lines: u16) {
+        self.scroll_y = self.scroll_y.saturating_sub(lines);
+    }
+
+    /// Scrolls to the next change after the current position, wrapping around to the top.
+    pub fn next_change(&mut self) {
+        let changes = match &self.diff {
+            Some((_, d)) => &d.changes,
+            None => return,
+        };
+        let current = self.scroll_y as usize;
+        let next = changes
+            .iter()
+            .find(|&&line| line > current)
+            .or_else(|| changes.first());
+        if let Some(&line) = next {
+            self.scroll_y = line as u16;
+        }
+    }
+
+    /// Returns the diff of the compared entries, or None if two entries are not marked yet or the
+    /// diff is still being computed.
+    pub fn diff(&mut self, history: &History, theme: &Theme) -> Option<&Diff> {
+        let (first, second) = (self.first?, self.second?);
+        let state = |i: usize| {
+            let (status, body_len, error) = history.response_state(i);
+            (i, status, body_len, error)
+        };
+        let key = DiffKey {
+            entries: [state(first), state(second)],
+            part: self.part,
+            granularity: self.granularity,
+        };
+
+        if self.diff.as_ref().is_some_and(|(k, _)| *k == key) {
+            return self.diff.as_ref().map(|(_, d)| d);
+        }
+        if !self.pending.as_ref().is_some_and(|(k, _)| *k == key) {
+            let a = self.content(&history.full(first));
+            let b = self.content(&history.full(second));
+            let styles = Styles {
+                removed: Style::default()
+                    .fg(theme.highlighted_fg)
+                    .bg(theme.diff_removed)
+                    .add_modifier(Modifier::CROSSED_OUT),
+                added: Style::default()
+                    .fg(theme.highlighted_fg)
+                    .bg(theme.diff_added),
+            };
+            let granularity = self.granularity;
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || tx.send(diff(&a, &b, granularity, styles)));
+            self.pending = Some((key.clone(), rx));
+        }
+
+        let (_, rx) = self.pending.as_ref()?;
+        let diff = rx.try_recv().ok()?;
+        self.pending = None;
+        self.diff = Some((key, diff));
+        self.diff.as_ref().map(|(_, d)| d)
+    }
+
+    /// Returns the compared part of an exchange without the ignored headers.
+    fn content(&self, req: &Req) -> Vec<u8> {
+        let ignored = |name: &str| self.ignore_headers.contains(&name.to_lowercase());
+        let mut p = String::new();
+        let body = match self.part {
+            Part::Request => {
+                p += &format!("{} {} {:?}\n", req.method, req.url, req.client_version);
+                if !ignored("host") {
+                    p += &format!("Host: {}\n", req.host);
+                }
+                for (name, value) in req.headers.iter().filter(|(n, _)| !ignored(n)) {
+                    p += &format!("{}: {}\n", name, value);
+                }
+                &req.body
+            }
+            Part::Response => {
+                match req.status {
+                    Some(status) => {
+                        let version = req.upstream_version.unwrap_or(Version::HTTP_11);
+                        p += &format!("{:?} {}\n", version, status);
+                    }
+                    None => p += "No response\n",
+                }
+                for (name, value) in req.res_headers.iter().filter(|(n, _)| !ignored(n)) {
+                    p += &format!("{}: {}\n", name, value);
+                }
+                &req.res_body
+            }
+        };
+
+        let mut content = p.into_bytes();
+        if !body.is_empty() {
+            content.push(b'\n');
+            content.extend_from_slice(body);
+        }
+        content
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Styles of the removed and added parts of a diff.
+#[derive(Clone, Copy)]
+struct Styles {
+    removed: Style,
+    added: Style,
+}
+
+/// Diffs two texts by words or bytes.
+fn diff(a: &[u8], b: &[u8], granularity: Granularity, styles: Styles) -> Diff {
+    let a_tokens = tokens(a, granularity);
+    let b_tokens = tokens(b, granularity);
+
+    // Tokens are compared by id, which is much faster than comparing slices.
+    let mut ids: HashMap<&[u8], u32> = HashMap::new();
+    let [a_ids, b_ids] = [&a_tokens, &b_tokens].map(|tokens| {
+        tokens
+            .iter()
+            .map(|&t| {
+                let next = ids.len() as u32;
+                *ids.entry(t).or_insert(next)
+            })
+            .collect::<Vec<u32>>()
+    });
+
+    // Only the part between the common start and end is diffed.
+    let prefix = a_ids.iter().zip(&b_ids).take_while(|(x, y)| x == y).count();
+    let suffix = a_ids[prefix..]
+        .iter()
+        .rev()
+        .zip(b_ids[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let a_middle = &a_ids[prefix..a_ids.len() - suffix];
+    let b_middle = &b_ids[prefix..b_ids.len() - suffix];
+
+    let mut ops = vec![Op::Equal; prefix];
+    let middle = if a_middle.len() + b_middle.len() > MAX_TOKENS {
+        None
+    } else {
+        myers(a_middle, b_middle)
+    };
+    let too_large = middle.is_none();
+    match middle {
+        Some(middle) => ops.extend(middle),
+        None => {
+            ops.extend(std::iter::repeat_n(Op::Delete, a_middle.len()));
+            ops.extend(std::iter::repeat_n(Op::Insert, b_middle.len()));
+        }
+    }
+    ops.extend(std::iter::repeat_n(Op::Equal, suffix));
+
+    let mut renderer = Renderer {
+        granularity,
+        removed: styles.removed,
+        added: styles.added,
+        lines: vec![],
+        line: vec![],
+        changes: vec![],
+        previous: Op::Equal,
+    };
+    let (mut i, mut j) = (0, 0);
+    let mut run: Vec<u8> = vec![];
+    let mut run_op = Op::Equal;
+    for op in ops {
+        if op != run_op {
+            renderer.push(&run, run_op);
+            run.clear();
+            run_op = op;
+        }
+        match op {
+            Op::Equal | Op::Delete => {
+                run.extend_from_slice(a_tokens[i]);
+                i += 1;
+                if op == Op::Equal {
+                    j += 1;
+                }
+            }
+            Op::Insert => {
+                run.extend_from_slice(b_tokens[j]);
+                j += 1;
+            }
+        }
+    }
+    renderer.push(&run, run_op);
+    renderer.finish(too_large)
+}
+
+/// Splits a text into words, runs of spaces and single other characters, or into bytes.
+fn tokens(text: &[u8], granularity: Granularity) -> Vec<&[u8]> {
+    if granularity == Granularity::Bytes {
+        return text.chunks(1).collect();
+    }
+
+    // Bytes of multi-byte UTF-8 characters count as word characters, so characters are not
+    // split.
+    let class = |b: u8| match b {
+        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | 0x80.. => 0,
+        b' ' | b'\t' => 1,
+        _ => 2,
+    };
+    let mut tokens = vec![];
+    let mut start = 0;
+    while start < text.len() {
+        let c = class(text[start]);
+        let mut end = start + 1;
+        if c != 2 {
+            while end < text.len() && class(text[end]) == c {
+                end += 1;
+            }
+        }
+        tokens.push(&text[start..end]);
+        start = end;
+    }
+    tokens
+}
+
+/// Finds the shortest edit script turning `a` into `b` with Myers' algorithm. Returns None if it
+/// needs more than `MAX_EDITS` edits.
+fn myers(a: &[u32], b: &[u32]) -> Option<Vec<Op>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m).min(MAX_EDITS as isize);
+    let offset = max + 1;
+    let index = |k: isize| (k + offset) as usize;
+
+    // `v[k]` is the furthest x reached on diagonal k. Its state before each round is kept to
+    // find the path back.
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace = vec![];
+    for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+
+            if x >= n && y >= m {
+                return Some(backtrack(&trace, n, m, index));
+            }
+        }
+    }
+    None
+}
+
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize, index: impl Fn(isize) -> usize) -> Vec<Op> {
+    let mut ops = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[index(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            ops.push(Op::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            ops.push(if x == prev_x { Op::Insert } else { Op::Delete });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.reverse();
+    ops
+}
+
+/// Builds the lines of a diff from runs of equal, removed and added bytes.
+struct Renderer {
+    granularity: Granularity,
+    removed: Style,
+    added: Style,
+    lines: Vec<Spans<'static>>,
+    line: Vec<Span<'static>>,
+    changes: Vec<usize>,
+    /// Operation of the last run.
+    previous: Op,
+}
+
+impl Renderer {
+    fn push(&mut self, bytes: &[u8], op: Op) {
+        if bytes.is_empty() {
+            return;
+        }
+        let style = match op {
+            Op::Equal => Style::default(),
+            Op::Delete => self.removed,
+            Op::Insert => self.added,
+        };
+        // A removal followed by an addition is one change.
+        if op == Op::Delete || (op == Op::Insert && self.previous != Op::Delete) {
+            self.changes.push(self.lines.len());
+        }
+        self.previous = op;
+
+        let text = self.display(bytes);
+        let mut pieces = text.split('\n').peekable();
+        while let Some(piece) = pieces.next() {
+            let is_last = pieces.peek().is_none();
+            // Removed and added line breaks are shown, or they could not be told apart.
+            let piece = if op != Op::Equal && !is_last {
+                format!("{}↵", piece)
+            } else {
+                piece.to_string()
+            };
+            if !piece.is_empty() {
+                self.line.push(Span::styled(piece, style));
+            }
+            if !is_last {
+                self.lines.push(Spans::from(std::mem::take(&mut self.line)));
+            }
+        }
+    }
+
+    fn display(&self, bytes: &[u8]) -> String {
+        match self.granularity {
+            Granularity::Words => String::from_utf8_lossy(bytes).into_owned(),
+            Granularity::Bytes => bytes
+                .iter()
+                .map(|&b| match b {
+                    b'\n' | b'\t' | b' '..=b'~' => (b as char).to_string(),
+                    _ => format!("\\x{:02x}", b),
+                })
+                .collect(),
+        }
+    }
+
+    fn finish(mut self, too_large: bool) -> Diff {
+        if !self.line.is_empty() {
+            self.lines.push(Spans::from(self.line));
+        }
+        Diff {
+            lines: self.lines,
+            changes: self.changes,
+            too_large,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::StorageSettings;
+    use std::time::Duration;
+
+    /// Applies an edit script to `a`, checking that it turns `a` into `b`.
+    fn apply(ops: &[Op], a: &[u32], b: &[u32]) -> Vec<u32> {
+        let (mut i, mut j) = (0, 0);
+        let mut out = vec![];
+        for op in ops {
+            match op {
+                Op::Equal => {
+                    assert_eq!(a[i], b[j]);
+                    out.push(a[i]);
+                    i += 1;
+                    j += 1;
+                }
+                Op::Delete => i += 1,
+                Op::Insert => {
+                    out.push(b[j]);
+                    j += 1;
+                }
+            }
+        }
+        assert_eq!((i, j), (a.len(), b.len()));
+        out
+    }
+
+    fn edits(ops: &[Op]) -> usize {
+        ops.iter().filter(|&&op| op != Op::Equal).count()
+    }
+
+    fn styles() -> Styles {
+        Styles {
+            removed: Style::default(),
+            added: Style::default(),
+        }
+    }
+
+    #[test]
+    fn finds_shortest_edit_scripts() {
+        let cases: [(&[u32], &[u32], usize); 5] = [
+            (&[1, 2, 3, 1, 2, 2, 1], &[3, 2, 1, 2, 1, 3], 5),
+            (&[], &[1, 2], 2),
+            (&[1, 2], &[], 2),
+            (&[1, 2, 3], &[1, 2, 3], 0),
+            (&[1, 2, 3], &[4, 5, 6], 6),
+        ];
+        for (a, b, expected) in cases {
+            let ops = myers(a, b).unwrap();
+            assert_eq!(apply(&ops, a, b), b);
+            assert_eq!(edits(&ops), expected);
+        }
+    }
+
+    #[test]
+    fn gives_up_after_too_many_edits() {
+        let a: Vec<u32> = (0..MAX_EDITS as u32).collect();
+        let b: Vec<u32> = (MAX_EDITS as u32..2 * MAX_EDITS as u32).collect();
+        assert!(myers(&a, &b).is_none());
+        assert!(myers(&a[1..], &b[..1]).is_some());
+    }
+
+    #[test]
+    fn splits_words() {
+        let tokens = tokens("a  bc,d\né".as_bytes(), Granularity::Words);
+        let expected: [&[u8]; 7] = [b"a", b"  ", b"bc", b",", b"d", b"\n", "é".as_bytes()];
+        assert_eq!(tokens, expected);
+        assert_eq!(super::tokens(b"abc", Granularity::Bytes).len(), 3);
+    }
+
+    #[test]
+    fn counts_changes() {
+        let diff = diff(b"a b c\nd", b"a x c\nd e", Granularity::Words, styles());
+        assert_eq!(diff.changes, [0, 1]);
+        assert!(!diff.too_large);
+        assert_eq!(diff.lines.len(), 2);
+
+        let same = super::diff(b"a\nb", b"a\nb", Granularity::Bytes, styles());
+        assert!(same.changes.is_empty());
+    }
+
+    #[test]
+    fn shows_large_differences_as_one_change() {
+        let a = "a ".repeat(MAX_TOKENS);
+        let b = "b ".repeat(MAX_TOKENS);
+        let diff = diff(a.as_bytes(), b.as_bytes(), Granularity::Words, styles());
+        assert!(diff.too_large);
+        assert_eq!(diff.changes, [0]);
+    }
+
+    fn wait_for_diff(compare: &mut Compare, history: &History, theme: &Theme) -> usize {
+        for _ in 0..500 {
+            if let Some(diff) = compare.diff(history, theme) {
+                return diff.changes.len();
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("The diff was not computed");
+    }
+
+    #[test]
+    fn recomputes_the_diff_when_a_response_arrives() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = History::new(&StorageSettings {
+            dir: Some(dir.path().display().to_string()),
+            ..StorageSettings::default()
+        })
+        .unwrap();
+        for _ in 0..2 {
+            history.push(Req {
+                method: "GET".to_string(),
+                url: "http://a.test/".to_string(),
+                ..Req::default()
+            });
+        }
+        let theme = Theme::load("dark");
+        let mut compare = Compare::new(&Default::default());
+        compare.mark(0);
+        compare.mark(1);
+        assert_eq!(wait_for_diff(&mut compare, &history, &theme), 0);
+
+        let req = history.get_mut(1);
+        req.status = Some(200);
+        req.res_body = b"new".to_vec();
+        assert_ne!(wait_for_diff(&mut compare, &history, &theme), 0);
+    }
+}
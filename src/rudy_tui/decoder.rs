@@ -0,0 +1,624 @@
+use super::Req;
+use crate::jwt::Jwt;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use percent_encoding::{percent_decode, percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde_json::{Map, Value};
+use std::io::{Read, Write};
+
+/// Characters which are not percent-encoded: the unreserved characters of RFC 3986.
+const URL_UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Number of bytes shown per line of a hex dump.
+const HEX_DUMP_WIDTH: usize = 16;
+
+/// Size up to which gzip data is decompressed.
+const MAX_DECOMPRESSED: u64 = 32 * 1024 * 1024;
+
+/// An encoding the Decoder tab converts from and to.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Codec {
+    Url,
+    Base64,
+    Base64Url,
+    Hex,
+    Html,
+    Jwt,
+    Gzip,
+    Unicode,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 8] = [
+        Codec::Url,
+        Codec::Base64,
+        Codec::Base64Url,
+        Codec::Hex,
+        Codec::Html,
+        Codec::Jwt,
+        Codec::Gzip,
+        Codec::Unicode,
+    ];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Codec::Url => "URL",
+            Codec::Base64 => "Base64",
+            Codec::Base64Url => "Base64url",
+            Codec::Hex => "Hex",
+            Codec::Html => "HTML entities",
+            Codec::Jwt => "JWT",
+            Codec::Gzip => "Gzip",
+            Codec::Unicode => "Unicode escapes",
+        }
+    }
+
+    fn decode(&self, input: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Codec::Url => Ok(percent_decode(input).collect()),
+            Codec::Base64 => base64_decode(input, base64::STANDARD_NO_PAD),
+            Codec::Base64Url => base64_decode(input, base64::URL_SAFE_NO_PAD),
+            Codec::Hex => hex_decode(input),
+            Codec::Html => Ok(html_decode(text(input)?).into_bytes()),
+            Codec::Jwt => jwt_decode(text(input)?).map(String::into_bytes),
+            Codec::Gzip => {
+                let mut output = vec![];
+                GzDecoder::new(input)
+                    .take(MAX_DECOMPRESSED + 1)
+                    .read_to_end(&mut output)
+                    .map_err(|e| format!("Invalid gzip data. {}", e))?;
+                if output.len() as u64 > MAX_DECOMPRESSED {
+                    return Err(format!(
+                        "Decompressed data is larger than {} MiB",
+                        MAX_DECOMPRESSED / 1024 / 1024
+                    ));
+                }
+                Ok(output)
+            }
+            Codec::Unicode => unicode_decode(text(input)?).map(String::into_bytes),
+        }
+    }
+
+    fn encode(&self, input: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Codec::Url => Ok(percent_encode(input, URL_UNRESERVED)
+                .to_string()
+                .into_bytes()),
+            Codec::Base64 => Ok(base64::encode(input).into_bytes()),
+            Codec::Base64Url => {
+                Ok(base64::encode_config(input, base64::URL_SAFE_NO_PAD).into_bytes())
+            }
+            Codec::Hex => Ok(input
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+                .into_bytes()),
+            Codec::Html => Ok(html_encode(text(input)?).into_bytes()),
+            Codec::Jwt => jwt_encode(input).map(String::into_bytes),
+            Codec::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], Compression::default());
+                encoder
+                    .write_all(input)
+                    .and_then(|_| encoder.finish())
+                    .map_err(|e| format!("Unable to compress. {}", e))
+            }
+            Codec::Unicode => Ok(unicode_encode(text(input)?).into_bytes()),
+        }
+    }
+}
+
+/// A step of the Decoder chain.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Step {
+    pub codec: Codec,
+    pub decode: bool,
+}
+
+impl Step {
+    /// Every step, decoding steps first, for the menu of steps to add.
+    pub fn all() -> Vec<Step> {
+        let decode = Codec::ALL.iter().map(|&codec| Step {
+            codec,
+            decode: true,
+        });
+        let encode = Codec::ALL.iter().map(|&codec| Step {
+            codec,
+            decode: false,
+        });
+        decode.chain(encode).collect()
+    }
+
+    pub fn title(&self) -> String {
+        let direction = if self.decode { "decode" } else { "encode" };
+        format!("{} {}", self.codec.title(), direction)
+    }
+
+    fn apply(&self, input: &[u8]) -> Result<Vec<u8>, String> {
+        if self.decode {
+            self.codec.decode(input)
+        } else {
+            self.codec.encode(input)
+        }
+    }
+}
+
+/// State of the Decoder tab: the input and the chain of steps it is transformed through.
+#[derive(Default)]
+pub struct Decoder {
+    pub input: Vec<u8>,
+    /// Index in the history of the entry the input was sent from.
+    pub entry: Option<usize>,
+    pub steps: Vec<Step>,
+    /// Selected row of the chain. Row 0 is the input and row `i` the output of step `i - 1`.
+    pub selected: usize,
+    pub scroll_y: u16,
+    /// Input followed by the output of every step, computed when first shown. A failed step ends
+    /// the chain with its error.
+    outputs: Option<Vec<Result<Vec<u8>, String>>>,
+}
+
+impl Decoder {
+    pub fn set_input(&mut self, input: Vec<u8>) {
+        self.input = input;
+        self.selected = self.steps.len();
+        self.changed();
+    }
+
+    /// Adds a step at the end of the chain and selects its output.
+    pub fn add_step(&mut self, step: Step) {
+        self.steps.push(step);
+        self.selected = self.steps.len();
+        self.changed();
+    }
+
+    /// Removes the step whose output is selected.
+    pub fn remove_step(&mut self) {
+        if self.selected > 0 {
+            self.steps.remove(self.selected - 1);
+            self.selected -= 1;
+            self.changed();
+        }
+    }
+
+    /// Switches the step whose output is selected between decoding and encoding.
+    pub fn flip_step(&mut self) {
+        if let Some(step) = self.selected.checked_sub(1).map(|i| &mut self.steps[i]) {
+            step.decode = !step.decode;
+            self.changed();
+        }
+    }
+
+    pub fn next(&mut self) {
+        self.selected = (self.selected + 1).min(self.steps.len());
+        self.scroll_y = 0;
+    }
+
+    pub fn previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+        self.scroll_y = 0;
+    }
+
+    pub fn scroll_down(&mut self, lines: u16) {
+        self.scroll_y = self.scroll_y.saturating_add(lines);
+    }
+
+    pub fn scroll_up(&mut self, lines: u16) {
+        self.scroll_y = self.scroll_y.saturating_sub(lines);
+    }
+
+    fn changed(&mut self) {
+        self.outputs = None;
+        self.scroll_y = 0;
+    }
+
+    /// Returns the input and the output of every step which ran.
+    pub fn outputs(&mut self) -> &[Result<Vec<u8>, String>] {
+        let (input, steps) = (&self.input, &self.steps);
+        self.outputs.get_or_insert_with(|| {
+            let mut outputs = vec![Ok(input.clone())];
+            for step in steps {
+                let output = match outputs.last() {
+                    Some(Ok(previous)) => step.apply(previous),
+                    _ => break,
+                };
+                outputs.push(output);
+            }
+            outputs
+        })
+    }
+
+    /// Returns the output of the selected row, if it ran without an error.
+    pub fn selected_output(&mut self) -> Option<Vec<u8>> {
+        let selected = self.selected;
+        self.outputs().get(selected)?.as_ref().ok().cloned()
+    }
+
+    /// Returns a copy of the request with the input replaced by the selected output, such as a
+    /// re-encoded body or parameter, to add to the History and send again.
+    pub fn replace_in(&mut self, req: &Req) -> Result<Req, String> {
+        let output = self
+            .selected_output()
+            .ok_or_else(|| "The selected step has no output".to_string())?;
+        if !req.contains(&self.input) {
+            return Err(format!("The Decoder input is not in request {}", req.id));
+        }
+        Ok(Req {
+            comment: format!("Request {} with the Decoder output", req.id),
+            ..req.with_replaced(&self.input, &output)
+        })
+    }
+}
+
+/// Returns bytes as text if they are UTF-8, or else as a hex dump.
+pub fn display(bytes: &[u8]) -> String {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return text.to_string();
+    }
+
+    let mut p = format!("{} bytes of binary data\n\n", bytes.len());
+    for (i, chunk) in bytes.chunks(HEX_DUMP_WIDTH).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| match b {
+                b' '..=b'~' => b as char,
+                _ => '.',
+            })
+            .collect();
+        p += &format!(
+            "{:08x}  {:<width$}  {}\n",
+            i * HEX_DUMP_WIDTH,
+            hex.join(" "),
+            ascii,
+            width = HEX_DUMP_WIDTH * 3 - 1
+        );
+    }
+    p
+}
+
+fn text(input: &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(input).map_err(|_| "Input is not valid UTF-8 text".to_string())
+}
+
+/// Decodes Base64 with or without padding, ignoring whitespace such as line breaks.
+fn base64_decode(input: &[u8], config: base64::Config) -> Result<Vec<u8>, String> {
+    let input: Vec<u8> = input
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace() && *b != b'=')
+        .collect();
+    base64::decode_config(input, config).map_err(|e| format!("Invalid Base64. {}", e))
+}
+
+fn hex_decode(input: &[u8]) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = input
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err("Hex input has an odd number of digits".to_string());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .ok_or_else(|| format!("Invalid hex digits '{}'", String::from_utf8_lossy(pair)))
+        })
+        .collect()
+}
+
+fn html_decode(input: &str) -> String {
+    let mut p = String::new();
+    let mut rest = input;
+    while let Some(start) = rest.find('&') {
+        p += &rest[..start];
+        rest = &rest[start..];
+        let decoded = rest
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| html_entity(&rest[1..end]).map(|c| (c, end)));
+        match decoded {
+            Some((c, end)) => {
+                p.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                p.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    p + rest
+}
+
+/// Returns the character of an entity name such as `amp`, `#39` or `#x27`.
+fn html_entity(name: &str) -> Option<char> {
+    let code = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        _ => {
+            let number = name.strip_prefix('#')?;
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            return char::from_u32(code);
+        }
+    };
+    Some(code)
+}
+
+fn html_encode(input: &str) -> String {
+    input
+        .chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#39;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Decodes a JWT into its header and claims as pretty JSON, followed by the signature.
+fn jwt_decode(input: &str) -> Result<String, String> {
+    let jwt = Jwt::parse(input.trim()).ok_or(
+        "A JWT has three parts separated by dots, of which the first two are Base64url encoded \
+         JSON objects",
+    )?;
+    let pretty = |map: &Map<String, Value>| {
+        serde_json::to_string_pretty(map).expect("Failed to serialize JSON")
+    };
+    Ok(format!(
+        "{}\n\n{}\n\n{}\n",
+        pretty(&jwt.header),
+        pretty(&jwt.claims),
+        jwt.signature
+    ))
+}
+
+/// Encodes a header and claims written as two JSON documents, as shown by decoding, into a JWT.
+/// A signature after them is kept.
+fn jwt_encode(input: &[u8]) -> Result<String, String> {
+    let mut documents = serde_json::Deserializer::from_slice(input).into_iter::<Value>();
+    let mut next = || -> Result<Value, String> {
+        match documents.next() {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(e)) => Err(format!("Invalid JSON. {}", e)),
+            None => Err("Write the header and the claims as two JSON documents".to_string()),
+        }
+    };
+    let header = next()?;
+    let claims = next()?;
+    let signature = text(&input[documents.byte_offset()..])?.trim();
+
+    let encode = |value: &Value| {
+        let json = serde_json::to_vec(value).expect("Failed to serialize JSON");
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    };
+    Ok(format!(
+        "{}.{}.{}",
+        encode(&header),
+        encode(&claims),
+        signature
+    ))
+}
+
+/// Decodes `\uXXXX` escapes, including surrogate pairs, and `\u{X}` escapes.
+fn unicode_decode(input: &str) -> Result<String, String> {
+    let mut p = String::new();
+    let mut rest = input;
+    while let Some(start) = rest.find("\\u") {
+        p += &rest[..start];
+        rest = &rest[start + 2..];
+
+        if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced.find('}').ok_or("Unterminated \\u{ escape")?;
+            let c = u32::from_str_radix(&braced[..end], 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("Invalid escape '\\u{{{}}}'", &braced[..end]))?;
+            p.push(c);
+            rest = &braced[end + 1..];
+            continue;
+        }
+
+        let mut units = vec![hex4(rest)?];
+        rest = &rest[4..];
+        // A high surrogate is followed by the escape of the low surrogate.
+        if (0xd800..0xdc00).contains(&units[0]) {
+            if let Some(low) = rest.strip_prefix("\\u") {
+                units.push(hex4(low)?);
+                rest = &low[4..];
+            }
+        }
+        for c in char::decode_utf16(units) {
+            p.push(c.map_err(|e| format!("Invalid escape. {}", e))?);
+        }
+    }
+    Ok(p + rest)
+}
+
+fn hex4(s: &str) -> Result<u16, String> {
+    s.get(..4)
+        .and_then(|digits| u16::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| {
+            format!(
+                "Invalid escape '\\u{}'",
+                s.chars().take(4).collect::<String>()
+            )
+        })
+}
+
+/// Escapes every character outside of ASCII as `\uXXXX`, with surrogate pairs for characters
+/// outside the Basic Multilingual Plane.
+fn unicode_encode(input: &str) -> String {
+    let mut p = String::new();
+    for c in input.chars() {
+        if c.is_ascii() {
+            p.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                p += &format!("\\u{:04x}", unit);
+            }
+        }
+    }
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(codec: Codec, input: &[u8]) {
+        let encoded = codec.encode(input).unwrap();
+        assert_eq!(codec.decode(&encoded).unwrap(), input, "{}", codec.title());
+    }
+
+    #[test]
+    fn codecs_round_trip() {
+        for codec in Codec::ALL {
+            if codec != Codec::Jwt {
+                round_trip(codec, "a <b> & \"c\" = d/é 😀".as_bytes());
+            }
+        }
+        round_trip(Codec::Url, &[0, 0xff, b'%']);
+        round_trip(Codec::Base64, &[0, 0xff, 0xfe]);
+        round_trip(Codec::Hex, &[0, 0xff, 0x10]);
+        round_trip(Codec::Gzip, &[0, 0xff, 0x10]);
+    }
+
+    #[test]
+    fn hex_decode_errors() {
+        assert_eq!(hex_decode(b"0a ff\n10").unwrap(), [0x0a, 0xff, 0x10]);
+        assert_eq!(
+            hex_decode(b"abc").unwrap_err(),
+            "Hex input has an odd number of digits"
+        );
+        assert_eq!(hex_decode(b"zz").unwrap_err(), "Invalid hex digits 'zz'");
+    }
+
+    #[test]
+    fn html_entities() {
+        assert_eq!(
+            html_decode("&lt;a&gt; &#39;&#x27; &amp;amp; &unknown; &"),
+            "<a> '' &amp; &unknown; &"
+        );
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        assert_eq!(
+            unicode_decode("\\u00e9 \\ud83d\\ude00 \\u{1f600}").unwrap(),
+            "é 😀 😀"
+        );
+        assert_eq!(unicode_encode("é😀"), "\\u00e9\\ud83d\\ude00");
+        assert!(unicode_decode("\\u12").is_err());
+        assert!(unicode_decode("\\u{110000}").is_err());
+    }
+
+    #[test]
+    fn gzip_decode_is_capped() {
+        let large = vec![0; MAX_DECOMPRESSED as usize + 1];
+        let compressed = Codec::Gzip.encode(&large).unwrap();
+        assert!(Codec::Gzip.decode(&compressed).is_err());
+        assert!(Codec::Gzip.decode(b"not gzip").is_err());
+    }
+
+    #[test]
+    fn jwt_decodes_and_encodes() {
+        let token = "eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJhIn0.c2ln";
+        let decoded = Codec::Jwt.decode(token.as_bytes()).unwrap();
+        assert_eq!(
+            String::from_utf8(decoded.clone()).unwrap(),
+            "{\n  \"alg\": \"HS256\"\n}\n\n{\n  \"sub\": \"a\"\n}\n\nc2ln\n"
+        );
+        assert_eq!(Codec::Jwt.encode(&decoded).unwrap(), token.as_bytes());
+        assert!(Codec::Jwt.decode(b"a.b").is_err());
+        assert!(Codec::Jwt.encode(b"{}").is_err());
+    }
+
+    #[test]
+    fn display_shows_binary_as_hex_dump() {
+        assert_eq!(display(b"text"), "text");
+        assert_eq!(
+            display(&[0xff, b'a']),
+            format!("2 bytes of binary data\n\n00000000  ff 61{:44}.a\n", "")
+        );
+    }
+
+    #[test]
+    fn steps_chain_until_an_error() {
+        let mut decoder = Decoder::default();
+        decoder.set_input(b"aGk=".to_vec());
+        decoder.add_step(Step {
+            codec: Codec::Base64,
+            decode: true,
+        });
+        decoder.add_step(Step {
+            codec: Codec::Hex,
+            decode: true,
+        });
+        let outputs = decoder.outputs();
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[1].as_deref(), Ok(&b"hi"[..]));
+        assert!(outputs[2].is_err());
+    }
+
+    #[test]
+    fn replaces_the_input_in_the_request() {
+        let req = Req {
+            id: 3,
+            method: "POST".to_string(),
+            url: "http://a.test/?q=YQ==".to_string(),
+            headers: vec![
+                ("Content-Length".to_string(), "6".to_string()),
+                ("X-A".to_string(), "YQ==".to_string()),
+            ],
+            body: b"d=YQ==".to_vec(),
+            ..Req::default()
+        };
+        let mut decoder = Decoder::default();
+        decoder.set_input(b"YQ==".to_vec());
+        decoder.add_step(Step {
+            codec: Codec::Base64,
+            decode: true,
+        });
+        decoder.add_step(Step {
+            codec: Codec::Hex,
+            decode: false,
+        });
+
+        let edited = decoder.replace_in(&req).unwrap();
+        assert_eq!(edited.url, "http://a.test/?q=61");
+        assert_eq!(
+            edited.headers,
+            [
+                ("Content-Length".to_string(), "4".to_string()),
+                ("X-A".to_string(), "61".to_string())
+            ]
+        );
+        assert_eq!(edited.body, b"d=61");
+        assert_eq!(edited.comment, "Request 3 with the Decoder output");
+
+        decoder.set_input(b"nope".to_vec());
+        assert_eq!(
+            decoder.replace_in(&req).err().unwrap(),
+            "The Decoder input is not in request 3"
+        );
+        decoder.set_input(Vec::new());
+        assert!(decoder.replace_in(&req).is_err());
+    }
+}
@@ -0,0 +1,520 @@
+use super::{theme::Theme, Req};
+use crate::jwt;
+use hudsucker::hyper::{Uri, Version};
+use regex::{Regex, RegexBuilder};
+use tui::{
+    layout::Rect,
+    style::Style,
+    text::{Span, Spans, Text},
+};
+use unicode_width::UnicodeWidthChar;
+
+/// Sub-tab of the detail viewer below the History table.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DetailTab {
+    Request,
+    Response,
+    Headers,
+    Cookies,
+    Params,
+    Timing,
+    Jwt,
+}
+
+impl DetailTab {
+    pub const ALL: [DetailTab; 7] = [
+        DetailTab::Request,
+        DetailTab::Response,
+        DetailTab::Headers,
+        DetailTab::Cookies,
+        DetailTab::Params,
+        DetailTab::Timing,
+        DetailTab::Jwt,
+    ];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            DetailTab::Request => "Request",
+            DetailTab::Response => "Response",
+            DetailTab::Headers => "Headers",
+            DetailTab::Cookies => "Cookies",
+            DetailTab::Params => "Params",
+            DetailTab::Timing => "Timing",
+            DetailTab::Jwt => "JWT",
+        }
+    }
+
+    pub fn content(&self, req: &Req) -> String {
+        match self {
+            DetailTab::Request => request(req),
+            DetailTab::Response => response(req),
+            DetailTab::Headers => headers(req),
+            DetailTab::Cookies => cookies(req),
+            DetailTab::Params => params(req),
+            DetailTab::Timing => timing(req),
+            DetailTab::Jwt => jwts(req),
+        }
+    }
+
+    fn index(&self) -> usize {
+        DetailTab::ALL
+            .iter()
+            .position(|t| t == self)
+            .unwrap_or_default()
+    }
+}
+
+/// State of the detail viewer: the selected sub-tab, scroll position, wrapping and search.
+pub struct Detail {
+    pub tab: DetailTab,
+    pub scroll_y: u16,
+    pub scroll_x: u16,
+    pub wrap: bool,
+    pub search_text: String,
+    search: Option<Regex>,
+    match_row: Option<usize>,
+    width: u16,
+    height: u16,
+    rows: usize,
+}
+
+impl Default for Detail {
+    fn default() -> Self {
+        Detail {
+            tab: DetailTab::Request,
+            scroll_y: 0,
+            scroll_x: 0,
+            wrap: false,
+            search_text: String::new(),
+            search: None,
+            match_row: None,
+            width: 0,
+            height: 0,
+            rows: 0,
+        }
+    }
+}
+
+impl Detail {
+    pub fn tab_index(&self) -> usize {
+        self.tab.index()
+    }
+
+    pub fn next_tab(&mut self) {
+        self.tab = DetailTab::ALL[(self.tab.index() + 1) % DetailTab::ALL.len()];
+        self.reset_scroll();
+    }
+
+    pub fn previous_tab(&mut self) {
+        let len = DetailTab::ALL.len();
+        self.tab = DetailTab::ALL[(self.tab.index() + len - 1) % len];
+        self.reset_scroll();
+    }
+
+    pub fn reset_scroll(&mut self) {
+        self.scroll_y = 0;
+        self.scroll_x = 0;
+        self.match_row = None;
+    }
+
+    pub fn scroll_down(&mut self, lines: u16) {
+        self.scroll_y = self.scroll_y.saturating_add(lines).min(self.max_scroll());
+        self.match_row = None;
+    }
+
+    pub fn scroll_up(&mut self, lines: u16) {
+        self.scroll_y = self.scroll_y.saturating_sub(lines);
+        self.match_row = None;
+    }
+
+    pub fn scroll_right(&mut self, columns: u16) {
+        if !self.wrap {
+            self.scroll_x = self.scroll_x.saturating_add(columns);
+        }
+    }
+
+    pub fn scroll_left(&mut self, columns: u16) {
+        self.scroll_x = self.scroll_x.saturating_sub(columns);
+    }
+
+    pub fn toggle_wrap(&mut self) {
+        self.wrap = !self.wrap;
+        self.scroll_x = 0;
+        self.match_row = None;
+    }
+
+    /// Scroll position which shows the last rows of the content at the bottom of the viewer.
+    fn max_scroll(&self) -> u16 {
+        let max = self.rows.saturating_sub(self.height as usize);
+        max.min(u16::MAX as usize) as u16
+    }
+
+    /// Number of rows a line takes up in the viewer.
+    fn line_rows(&self, line: &str) -> usize {
+        if !self.wrap || self.width == 0 {
+            return 1;
+        }
+        let width: usize = line.chars().map(|c| c.width().unwrap_or(0)).sum();
+        width.max(1).div_ceil(self.width as usize)
+    }
+
+    /// Sets the text searched for in the detail viewer, a case-insensitive regex. The previous
+    /// search is kept if the text is not a valid regex.
+    pub fn set_search(&mut self, text: &str) -> Result<(), regex::Error> {
+        self.search = if text.is_empty() {
+            None
+        } else {
+            Some(RegexBuilder::new(text).case_insensitive(true).build()?)
+        };
+        self.search_text = text.to_string();
+        self.match_row = None;
+        Ok(())
+    }
+
+    /// Scrolls to the next line which matches the search, starting after the previous match or
+    /// at the current position, and wrapping around to the top.
+    pub fn next_match(&mut self, req: &Req) {
+        let search = match &self.search {
+            Some(s) => s,
+            None => return,
+        };
+
+        let content = self.tab.content(req);
+        let mut offsets = vec![];
+        let mut row = 0;
+        for line in content.lines() {
+            offsets.push((row, search.is_match(line)));
+            row += self.line_rows(line);
+        }
+        self.rows = row;
+
+        let from = self.match_row.map_or(self.scroll_y as usize, |r| r + 1);
+        let found = offsets
+            .iter()
+            .find(|&&(row, matched)| matched && row >= from)
+            .or_else(|| offsets.iter().find(|&&(_, matched)| matched));
+        if let Some(&(row, _)) = found {
+            self.match_row = Some(row);
+            self.scroll_y = row.min(self.max_scroll() as usize) as u16;
+        }
+    }
+
+    /// Returns the text of the selected sub-tab with search matches highlighted, wrapped to the
+    /// viewer `area` if wrapping is on, and keeps the scroll position within the text.
+    pub fn text(&mut self, req: &Req, theme: &Theme, area: Rect) -> Text<'static> {
+        self.width = area.width;
+        self.height = area.height;
+
+        let content = self.tab.content(req);
+        let style = Style::default().fg(theme.match_fg).bg(theme.match_bg);
+        let mut lines = vec![];
+        for line in content.lines() {
+            let spans = match &self.search {
+                Some(search) => highlight_matches(search, line, style),
+                None => Spans::from(line.to_string()),
+            };
+            if self.wrap && self.width > 0 {
+                lines.extend(wrap_spans(spans, self.width as usize));
+            } else {
+                lines.push(spans);
+            }
+        }
+
+        self.rows = lines.len();
+        self.scroll_y = self.scroll_y.min(self.max_scroll());
+        Text::from(lines)
+    }
+}
+
+/// Breaks a line into rows of at most `width` columns, keeping the styles of its spans.
+fn wrap_spans(spans: Spans<'static>, width: usize) -> Vec<Spans<'static>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut row_width = 0;
+    for span in spans.0 {
+        let mut text = String::new();
+        for c in span.content.chars() {
+            let w = c.width().unwrap_or(0);
+            if row_width + w > width && row_width > 0 {
+                row.push(Span::styled(std::mem::take(&mut text), span.style));
+                rows.push(Spans::from(std::mem::take(&mut row)));
+                row_width = 0;
+            }
+            text.push(c);
+            row_width += w;
+        }
+        row.push(Span::styled(text, span.style));
+    }
+    rows.push(Spans::from(row));
+    rows
+}
+
+fn highlight_matches(search: &Regex, line: &str, style: Style) -> Spans<'static> {
+    let mut spans = vec![];
+    let mut end = 0;
+    for m in search.find_iter(line).filter(|m| !m.as_str().is_empty()) {
+        spans.push(Span::raw(line[end..m.start()].to_string()));
+        spans.push(Span::styled(m.as_str().to_string(), style));
+        end = m.end();
+    }
+    spans.push(Span::raw(line[end..].to_string()));
+    Spans::from(spans)
+}
+
+fn request(req: &Req) -> String {
+    let mut p = String::new();
+    if !req.comment.is_empty() {
+        p += &format!("Comment: {}\n", req.comment);
+    }
+    if !req.tags.is_empty() {
+        p += &format!("Tags: {}\n", req.tags.join(", "));
+    }
+    if !p.is_empty() {
+        p += "\n";
+    }
+    if let Some(error) = &req.error {
+        p += &format!("Error: {}\n\n", error);
+    }
+    if req.client_version == Version::HTTP_2 {
+        for (name, value) in req.pseudo_headers() {
+            p += &format!("{}: {}\n", name, value)
+        }
+    } else {
+        p += &format!("{} {} {:?}\n", req.method, req.url, req.client_version);
+        p += &format!("Host: {}\n", req.host);
+    }
+    for (name, value) in &req.headers {
+        p += &format!("{}: {}\n", name, value)
+    }
+    if !req.body.is_empty() {
+        p += &format!("\n{}\n", String::from_utf8_lossy(&req.body));
+    }
+    p
+}
+
+fn response(req: &Req) -> String {
+    let mut p = String::new();
+    if let Some(tunnel) = &req.tunnel {
+        p += "Tunnel (not decrypted)\n";
+        p += &format!("  Bytes sent: {}\n", tunnel.bytes_sent);
+        p += &format!("  Bytes received: {}\n", tunnel.bytes_received);
+    }
+    if let (Some(status), Some(version)) = (req.status, req.upstream_version) {
+        if version == Version::HTTP_2 {
+            p += &format!(":status: {}\n", status);
+        } else {
+            p += &format!("{:?} {}\n", version, status);
+        }
+        for (name, value) in &req.res_headers {
+            p += &format!("{}: {}\n", name, value)
+        }
+        if !req.res_body.is_empty() {
+            p += &format!("\n{}\n", String::from_utf8_lossy(&req.res_body));
+        }
+        if req.res_body_truncated {
+            p += &format!("\n[Body truncated after {} bytes]\n", req.res_body.len());
+        }
+    }
+    p
+}
+
+fn headers(req: &Req) -> String {
+    let mut p = "Request headers\n".to_string();
+    for (name, value) in &req.headers {
+        p += &format!("  {}: {}\n", name, value)
+    }
+    p += "\nResponse headers\n";
+    for (name, value) in &req.res_headers {
+        p += &format!("  {}: {}\n", name, value)
+    }
+    p
+}
+
+fn cookies(req: &Req) -> String {
+    let mut p = "Request cookies\n".to_string();
+    let sent = req
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("cookie"))
+        .flat_map(|(_, value)| value.split(';'));
+    for cookie in sent.map(|c| c.trim()).filter(|c| !c.is_empty()) {
+        let (name, value) = cookie.split_once('=').unwrap_or((cookie, ""));
+        p += &format!("  {} = {}\n", name, value);
+    }
+
+    p += "\nResponse cookies\n";
+    let set = req
+        .res_headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("set-cookie"));
+    for (_, value) in set {
+        let mut parts = value.split(';').map(|a| a.trim());
+        let cookie = parts.next().unwrap_or_default();
+        let (name, value) = cookie.split_once('=').unwrap_or((cookie, ""));
+        p += &format!("  {} = {}\n", name, value);
+        for attribute in parts.filter(|a| !a.is_empty()) {
+            p += &format!("      {}\n", attribute);
+        }
+    }
+    p
+}
+
+fn params(req: &Req) -> String {
+    let mut p = "Query parameters\n".to_string();
+    let uri = req.url.parse::<Uri>().unwrap_or_default();
+    let query = uri.query().unwrap_or_default().as_bytes();
+    for (name, value) in form_urlencoded::parse(query) {
+        p += &format!("  {} = {}\n", name, value);
+    }
+
+    let is_form = req.headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("content-type")
+            && value.starts_with("application/x-www-form-urlencoded")
+    });
+    if is_form {
+        p += "\nBody parameters\n";
+        for (name, value) in form_urlencoded::parse(&req.body) {
+            p += &format!("  {} = {}\n", name, value);
+        }
+    }
+    p
+}
+
+fn timing(req: &Req) -> String {
+    let mut p = String::new();
+    if let Some(timestamp) = req.timestamp {
+        p += &format!("Started: {}\n", timestamp);
+    }
+    if let Some(duration) = req.duration {
+        p += &format!("Duration: {} ms\n", duration.as_millis());
+    }
+    if !req.client.is_empty() {
+        p += &format!("Client: {}\n", req.client);
+    }
+    if !req.listener.is_empty() {
+        p += &format!("Listener: {}\n", req.listener);
+    }
+    if req.tunnel.is_none() && req.error.is_none() {
+        p += "\nProtocol\n";
+        p += &format!("  Client: {:?}\n", req.client_version);
+        if let Some(version) = req.upstream_version {
+            p += &format!("  Upstream: {:?}\n", version);
+        }
+        if let Some(alt_svc) = req.http3_alt_svc() {
+            p += &format!("  HTTP/3 advertised: {}\n", alt_svc);
+        }
+    }
+    if let Some(tls) = &req.tls {
+        p += "\nConnection\n";
+        p += &format!("  TLS version: {}\n", tls.version);
+        p += &format!("  Cipher suite: {}\n", tls.cipher_suite);
+        p += &format!("  ALPN: {}\n", tls.alpn.as_deref().unwrap_or("none"));
+        p += &format!("  SNI: {}\n", tls.sni);
+        for (i, cert) in tls.certificates.iter().enumerate() {
+            p += &format!("  Certificate {}\n", i);
+            p += &format!("    Subject: {}\n", cert.subject);
+            p += &format!("    Issuer: {}\n", cert.issuer);
+            p += &format!("    Valid: {} to {}\n", cert.not_before, cert.not_after);
+            p += &format!("    SANs: {}\n", cert.sans.join(", "));
+            p += &format!("    SHA-256: {}\n", cert.fingerprint);
+        }
+    }
+    p
+}
+
+fn jwts(req: &Req) -> String {
+    let found = jwt::find(req);
+    if found.is_empty() {
+        return "No JWTs found\n".to_string();
+    }
+
+    let mut p = String::new();
+    for f in found {
+        p += &format!("{} ({})\n{}\n\n", f.location, f.jwt.alg(), f.jwt.token);
+        p += &f.jwt.describe();
+        p += "\n";
+    }
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req() -> Req {
+        Req {
+            method: "GET".to_string(),
+            url: "http://a.test/".to_string(),
+            host: "a.test".to_string(),
+            body: format!("{}\nneedle\n", "a".repeat(30)).into_bytes(),
+            ..Req::default()
+        }
+    }
+
+    fn rows(spans: &[Spans]) -> Vec<String> {
+        spans
+            .iter()
+            .map(|s| s.0.iter().map(|s| s.content.as_ref()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn wraps_spans_by_width() {
+        let spans = Spans::from(vec![Span::raw("abcd"), Span::raw("efg")]);
+        assert_eq!(rows(&wrap_spans(spans, 3)), ["abc", "def", "g"]);
+        assert_eq!(rows(&wrap_spans(Spans::from("日本語"), 4)), ["日本", "語"]);
+        assert_eq!(rows(&wrap_spans(Spans::from(""), 4)), [""]);
+    }
+
+    #[test]
+    fn scrolls_to_matches_on_wrapped_rows() {
+        let mut detail = Detail {
+            wrap: true,
+            ..Detail::default()
+        };
+        detail.set_search("needle").unwrap();
+        let text = detail.text(&req(), &Theme::load("dark"), Rect::new(0, 0, 10, 4));
+        assert_eq!(text.lines.len(), 11);
+
+        // The match is on row 9, but the last 4 rows are shown from row 7.
+        detail.next_match(&req());
+        assert_eq!(detail.scroll_y, 7);
+        detail.next_match(&req());
+        assert_eq!(detail.scroll_y, 7);
+    }
+
+    #[test]
+    fn scrolls_to_matching_lines_without_wrapping() {
+        let mut detail = Detail::default();
+        detail.set_search("a").unwrap();
+        detail.text(&req(), &Theme::load("dark"), Rect::new(0, 0, 10, 2));
+        detail.next_match(&req());
+        assert_eq!(detail.scroll_y, 0);
+        detail.next_match(&req());
+        assert_eq!(detail.scroll_y, 1);
+        detail.next_match(&req());
+        assert_eq!(detail.scroll_y, 3);
+        detail.next_match(&req());
+        assert_eq!(detail.scroll_y, 0);
+    }
+
+    #[test]
+    fn keeps_the_search_if_the_regex_is_invalid() {
+        let mut detail = Detail::default();
+        detail.set_search("a+").unwrap();
+        assert!(detail.set_search("(a").is_err());
+        assert_eq!(detail.search_text, "a+");
+        assert!(detail.search.as_ref().unwrap().is_match("AA"));
+
+        detail.set_search("").unwrap();
+        assert!(detail.search.is_none());
+    }
+
+    #[test]
+    fn clamps_scrolling_to_the_content() {
+        let mut detail = Detail::default();
+        detail.text(&req(), &Theme::load("dark"), Rect::new(0, 0, 80, 3));
+        detail.scroll_down(20);
+        assert_eq!(detail.scroll_y, 3);
+    }
+}
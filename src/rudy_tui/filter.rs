@@ -0,0 +1,447 @@
+use super::{history::History, Req};
+use crate::settings::host_matches;
+use regex::{Regex, RegexBuilder};
+use std::{cell::RefCell, collections::HashMap, time::Instant};
+
+/// Filters applied to the History table.
+///
+/// The filter bar takes space separated terms which must all match:
+///
+/// - `method:GET,POST` matches any of the listed methods
+/// - `status:2xx,404` matches a status class or an exact status code
+/// - `mime:json` matches response MIME types containing the text
+/// - `host:*.example.com` matches a host pattern
+/// - `scope` only matches in-scope hosts
+/// - `params` only matches requests with query or body parameters
+/// - `url:https://example.com/api` matches URLs below the given one
+/// - `param:id` matches requests with any of the listed query parameters
+/// - `tag:a,b` matches entries with any of the listed tags
+/// - `highlight` matches highlighted entries, `highlight:red,green` only the listed colors
+/// - `comment` only matches entries with a comment
+///
+/// Values are separated by commas. A value containing commas, such as a URL, can be quoted:
+/// `url:"https://example.com/a,b"`.
+#[derive(Default)]
+pub struct Filter {
+    pub query: String,
+    pub search_text: String,
+    methods: Vec<String>,
+    statuses: Vec<String>,
+    mime_types: Vec<String>,
+    hosts: Vec<String>,
+    in_scope: bool,
+    has_params: bool,
+    urls: Vec<String>,
+    params: Vec<String>,
+    tags: Vec<String>,
+    highlighted: bool,
+    highlights: Vec<String>,
+    has_comment: bool,
+    search: Option<Regex>,
+    /// Search results for the entries kept on disk, by History index, so that each entry is only
+    /// read back once per search.
+    stored_matches: RefCell<HashMap<usize, bool>>,
+}
+
+impl Filter {
+    /// Replaces the filter bar terms with the ones parsed from `query`.
+    pub fn set_query(&mut self, query: &str) -> Result<(), String> {
+        let mut filter = Filter::default();
+
+        for term in query.split_whitespace() {
+            let (key, values) = match term.split_once(':') {
+                Some((key, values)) => (key, split_values(values)),
+                None => (term, vec![]),
+            };
+
+            match key {
+                "method" => filter.methods = values.iter().map(|v| v.to_uppercase()).collect(),
+                "status" => filter.statuses = values.iter().map(|v| v.to_lowercase()).collect(),
+                "mime" => filter.mime_types = values.iter().map(|v| v.to_lowercase()).collect(),
+                "host" => filter.hosts = values,
+                "scope" => filter.in_scope = true,
+                "params" => filter.has_params = true,
+                "url" => filter.urls = values,
+                "param" => filter.params = values,
+                "tag" => filter.tags = values.iter().map(|v| v.to_lowercase()).collect(),
+                "highlight" => {
+                    filter.highlighted = true;
+                    filter.highlights = values.iter().map(|v| v.to_lowercase()).collect();
+                }
+                "comment" => filter.has_comment = true,
+                _ => return Err(format!("Unknown filter '{}'", term)),
+            }
+        }
+
+        filter.query = query.trim().to_string();
+        filter.search_text = std::mem::take(&mut self.search_text);
+        filter.search = self.search.take();
+        filter.stored_matches = std::mem::take(&mut self.stored_matches);
+        *self = filter;
+        Ok(())
+    }
+
+    /// Sets the search text, which is used as a case-insensitive regex.
+    pub fn set_search(&mut self, text: &str) -> Result<(), String> {
+        self.search = if text.is_empty() {
+            None
+        } else {
+            let regex = RegexBuilder::new(text)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| regex_error(&e))?;
+            Some(regex)
+        };
+        self.search_text = text.to_string();
+        self.stored_matches.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// Checks an entry of the History, reading it back from disk if the search needs it.
+    ///
+    /// Returns `None` if the entry would have to be read after the deadline, so that searching
+    /// many entries on disk is spread over several redraws instead of freezing the UI.
+    pub fn matches_entry(
+        &self,
+        history: &History,
+        i: usize,
+        scope: &[String],
+        deadline: Instant,
+    ) -> Option<bool> {
+        let req = history.get(i);
+        let search = match &self.search {
+            Some(s) if req.stored.is_some() => s,
+            _ => return Some(self.matches(&req, scope)),
+        };
+
+        if !self.matches_terms(&req, scope) {
+            return Some(false);
+        }
+        if matches_annotations(search, &req) {
+            return Some(true);
+        }
+        if let Some(&found) = self.stored_matches.borrow().get(&i) {
+            return Some(found);
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        let found = matches_content(search, &history.full(i));
+        self.stored_matches.borrow_mut().insert(i, found);
+        Some(found)
+    }
+
+    fn matches(&self, req: &Req, scope: &[String]) -> bool {
+        if !self.matches_terms(req, scope) {
+            return false;
+        }
+
+        match &self.search {
+            Some(search) => matches_content(search, req) || matches_annotations(search, req),
+            None => true,
+        }
+    }
+
+    /// Checks the filter bar terms, which only need the summary of an entry.
+    fn matches_terms(&self, req: &Req, scope: &[String]) -> bool {
+        if !self.methods.is_empty() && !self.methods.contains(&req.method) {
+            return false;
+        }
+
+        if !self.statuses.is_empty() {
+            let status = match req.status {
+                Some(s) => s.to_string(),
+                None => return false,
+            };
+            let class = format!("{}xx", &status[..1]);
+            if !self.statuses.iter().any(|s| *s == status || *s == class) {
+                return false;
+            }
+        }
+
+        if !self.mime_types.is_empty() {
+            let mime = req.mime_type().unwrap_or_default().to_lowercase();
+            if !self.mime_types.iter().any(|m| mime.contains(m.as_str())) {
+                return false;
+            }
+        }
+
+        if !self.hosts.is_empty() && !self.hosts.iter().any(|p| host_matches(p, &req.host)) {
+            return false;
+        }
+
+        if self.in_scope && !scope.is_empty() && !scope.iter().any(|p| host_matches(p, &req.host)) {
+            return false;
+        }
+
+        if self.has_params && !req.has_params() {
+            return false;
+        }
+
+        if !self.urls.is_empty() && !self.urls.iter().any(|u| url_below(u, &req.url)) {
+            return false;
+        }
+
+        if !self.params.is_empty() {
+            let query = req.url.split_once('?').map(|(_, q)| q).unwrap_or_default();
+            let mut names = query.split('&').filter_map(|p| p.split('=').next());
+            if !names.any(|n| self.params.iter().any(|p| p == n)) {
+                return false;
+            }
+        }
+
+        if !self.tags.is_empty()
+            && !req
+                .tags
+                .iter()
+                .any(|t| self.tags.contains(&t.to_lowercase()))
+        {
+            return false;
+        }
+
+        if self.highlighted {
+            let highlight = match req.highlight {
+                Some(h) => h,
+                None => return false,
+            };
+            if !self.highlights.is_empty() && !self.highlights.iter().any(|h| h == highlight.name())
+            {
+                return false;
+            }
+        }
+
+        !(self.has_comment && req.comment.is_empty())
+    }
+}
+
+/// Splits filter values on the commas outside of double quotes, removing the quotes.
+fn split_values(values: &str) -> Vec<String> {
+    let mut split = vec![String::new()];
+    let mut quoted = false;
+    for c in values.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => split.push(String::new()),
+            c => split.last_mut().unwrap().push(c),
+        }
+    }
+    split
+}
+
+/// Describes an invalid search regex in one line for the filter bar.
+pub fn regex_error(e: &regex::Error) -> String {
+    // Syntax errors span several lines, with the reason on the last one.
+    let e = e.to_string();
+    let reason = e.lines().last().unwrap_or_default();
+    format!("Invalid regex: {}", reason.trim_start_matches("error: "))
+}
+
+/// Quotes a filter value if it contains commas.
+pub fn quote_value(value: &str) -> String {
+    if value.contains(',') {
+        format!("\"{}\"", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Checks the host, URL, headers and bodies of an entry against the search regex.
+fn matches_content(search: &Regex, req: &Req) -> bool {
+    search.is_match(&req.host)
+        || search.is_match(&req.url)
+        || req
+            .headers
+            .iter()
+            .chain(&req.res_headers)
+            .any(|(name, value)| search.is_match(&format!("{}: {}", name, value)))
+        || search.is_match(&String::from_utf8_lossy(&req.body))
+        || search.is_match(&String::from_utf8_lossy(&req.res_body))
+}
+
+/// Checks the annotations of an entry, which may change after it is stored, against the search
+/// regex.
+fn matches_annotations(search: &Regex, req: &Req) -> bool {
+    search.is_match(&req.comment) || req.tags.iter().any(|t| search.is_match(t))
+}
+
+/// Checks if a URL is `prefix` or below it. The prefix only matches whole path segments.
+fn url_below(prefix: &str, url: &str) -> bool {
+    match url.strip_prefix(prefix) {
+        Some(rest) => {
+            prefix.ends_with('/')
+                || rest.is_empty()
+                || rest.starts_with('/')
+                || rest.starts_with('?')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn later() -> Instant {
+        Instant::now() + std::time::Duration::from_secs(60)
+    }
+
+    fn req(method: &str, url: &str, status: Option<u16>) -> Req {
+        Req {
+            method: method.to_string(),
+            url: url.to_string(),
+            host: url.split('/').nth(2).unwrap_or_default().to_string(),
+            status,
+            ..Req::default()
+        }
+    }
+
+    #[test]
+    fn splits_values_outside_quotes() {
+        assert_eq!(split_values("GET,POST"), ["GET", "POST"]);
+        assert_eq!(split_values("\"a,b\",c"), ["a,b", "c"]);
+        assert_eq!(split_values(""), [""]);
+    }
+
+    #[test]
+    fn quotes_values_with_commas() {
+        assert_eq!(quote_value("https://a.test/x"), "https://a.test/x");
+        assert_eq!(quote_value("https://a.test/a,b"), "\"https://a.test/a,b\"");
+        assert_eq!(
+            split_values(&quote_value("https://a.test/a,b")),
+            ["https://a.test/a,b"]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_filters() {
+        let mut filter = Filter::default();
+        assert_eq!(
+            filter.set_query("nope:1"),
+            Err("Unknown filter 'nope:1'".to_string())
+        );
+        assert!(filter.set_query("method:get status:2xx").is_ok());
+        assert_eq!(filter.query, "method:get status:2xx");
+    }
+
+    #[test]
+    fn matches_methods_and_statuses() {
+        let mut filter = Filter::default();
+        filter.set_query("method:get,post status:2xx,404").unwrap();
+        assert!(filter.matches(&req("GET", "http://a.test/", Some(204)), &[]));
+        assert!(filter.matches(&req("POST", "http://a.test/", Some(404)), &[]));
+        assert!(!filter.matches(&req("PUT", "http://a.test/", Some(200)), &[]));
+        assert!(!filter.matches(&req("GET", "http://a.test/", Some(500)), &[]));
+        assert!(!filter.matches(&req("GET", "http://a.test/", None), &[]));
+    }
+
+    #[test]
+    fn matches_urls_below_whole_segments() {
+        let mut filter = Filter::default();
+        filter.set_query("url:\"http://a.test/a,b\"").unwrap();
+        assert!(filter.matches(&req("GET", "http://a.test/a,b", None), &[]));
+        assert!(filter.matches(&req("GET", "http://a.test/a,b/c?d=1", None), &[]));
+        assert!(!filter.matches(&req("GET", "http://a.test/a,bc", None), &[]));
+        assert!(!filter.matches(&req("GET", "http://a.test/a", None), &[]));
+    }
+
+    #[test]
+    fn matches_params_and_scope() {
+        let mut filter = Filter::default();
+        filter.set_query("param:id scope").unwrap();
+        let scope = ["*.a.test".to_string()];
+        assert!(filter.matches(&req("GET", "http://x.a.test/?q=1&id=2", None), &scope));
+        assert!(!filter.matches(&req("GET", "http://x.a.test/?ids=2", None), &scope));
+        assert!(!filter.matches(&req("GET", "http://b.test/?id=2", None), &scope));
+    }
+
+    #[test]
+    fn reports_invalid_search_regexes() {
+        let mut filter = Filter::default();
+        assert!(filter
+            .set_search("(ab")
+            .unwrap_err()
+            .starts_with("Invalid regex:"));
+        filter.set_search("A.C").unwrap();
+        assert!(filter.matches(&req("GET", "http://a.test/abc", None), &[]));
+        assert!(!filter.matches(&req("GET", "http://a.test/ab", None), &[]));
+    }
+
+    #[test]
+    fn searches_response_bodies() {
+        let mut filter = Filter::default();
+        filter.set_search("welcome").unwrap();
+        let mut entry = req("GET", "http://a.test/", Some(200));
+        assert!(!filter.matches(&entry, &[]));
+        entry.res_body = b"<h1>Welcome</h1>".to_vec();
+        assert!(filter.matches(&entry, &[]));
+    }
+
+    #[test]
+    fn searches_entries_stored_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = History::new(&crate::settings::StorageSettings {
+            memory_budget_mb: 0,
+            spill_body_kb: 1,
+            dir: Some(dir.path().display().to_string()),
+        })
+        .unwrap();
+        let mut stored = req("POST", "http://a.test/", None);
+        stored.body = b"needle".to_vec();
+        history.push(stored);
+        let mut stored = req("GET", "http://a.test/", Some(200));
+        stored.res_body = b"haystack".to_vec();
+        history.push(stored);
+        history.push(req("GET", "http://a.test/", None));
+        assert!(history.get(0).stored.is_some());
+
+        let mut filter = Filter::default();
+        filter.set_search("needle").unwrap();
+        assert_eq!(filter.matches_entry(&history, 0, &[], later()), Some(true));
+        assert_eq!(filter.matches_entry(&history, 1, &[], later()), Some(false));
+        assert_eq!(filter.matches_entry(&history, 2, &[], later()), Some(false));
+
+        // Annotations are checked on the entry in memory.
+        history.get_mut(2).comment = "a needle".to_string();
+        assert_eq!(filter.matches_entry(&history, 2, &[], later()), Some(true));
+
+        filter.set_search("haystack").unwrap();
+        assert!(history.get(1).stored.is_some());
+        assert_eq!(filter.matches_entry(&history, 1, &[], later()), Some(true));
+        filter.set_search("needle").unwrap();
+
+        filter.set_query("method:get").unwrap();
+        assert_eq!(filter.matches_entry(&history, 0, &[], later()), Some(false));
+        filter.set_query("").unwrap();
+        filter.set_search("nothing").unwrap();
+        assert_eq!(filter.matches_entry(&history, 0, &[], later()), Some(false));
+    }
+
+    #[test]
+    fn reads_stored_entries_until_the_deadline() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = History::new(&crate::settings::StorageSettings {
+            memory_budget_mb: 0,
+            spill_body_kb: 1,
+            dir: Some(dir.path().display().to_string()),
+        })
+        .unwrap();
+        let mut stored = req("POST", "http://a.test/", None);
+        stored.body = b"needle".to_vec();
+        history.push(stored);
+        history.push(req("GET", "http://a.test/needle", None));
+
+        let mut filter = Filter::default();
+        filter.set_search("needle").unwrap();
+        let passed = Instant::now();
+        assert_eq!(filter.matches_entry(&history, 0, &[], passed), None);
+        assert_eq!(filter.matches_entry(&history, 1, &[], passed), Some(true));
+
+        // Terms which rule the entry out don't need it to be read.
+        filter.set_query("method:get").unwrap();
+        assert_eq!(filter.matches_entry(&history, 0, &[], passed), Some(false));
+        filter.set_query("").unwrap();
+
+        assert_eq!(filter.matches_entry(&history, 0, &[], later()), Some(true));
+        assert_eq!(filter.matches_entry(&history, 0, &[], passed), Some(true));
+    }
+}
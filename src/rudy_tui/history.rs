@@ -0,0 +1,498 @@
+use super::Req;
+use crate::{scanner::Issue, settings::StorageSettings};
+use serde::de::DeserializeOwned;
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    mem::size_of,
+    path::PathBuf,
+    rc::Rc,
+};
+use tempfile::NamedTempFile;
+use tracing::warn;
+
+/// Number of entries read back from disk which are kept in memory for the detail viewer.
+const LOADED_CACHE_SIZE: usize = 8;
+
+/// Number of summaries written to the storage file together once the summaries of evicted
+/// entries exceed the memory budget.
+const PAGE_SIZE: usize = 256;
+
+/// Number of pages of summaries read back from disk which are kept in memory.
+const LOADED_PAGES: usize = 16;
+
+/// Location of an entry which was written to the storage file.
+#[derive(Clone, Copy)]
+pub struct Stored {
+    offset: u64,
+    len: u64,
+    pub body_len: u64,
+    pub res_body_len: u64,
+}
+
+/// An entry of the History.
+enum Slot {
+    /// The entry, or only its summary once it has been evicted.
+    Loaded(Box<Req>),
+    /// The summary of an evicted entry, which was written to a page of the storage file. The
+    /// location of the whole entry is not saved with the summary.
+    Paged {
+        page: usize,
+        pos: usize,
+        stored: Stored,
+    },
+}
+
+/// Captured traffic, kept within a memory budget.
+///
+/// Entries with large bodies have the bodies spilled to a storage file on disk. Once the budget
+/// is exceeded, the oldest entries are evicted: they are written to the storage file and only a
+/// summary is kept in memory for the History table. [`History::full`] reads the whole entry back.
+/// If the summaries exceed the budget as well, the oldest ones are written to the storage file
+/// in pages, which are read back when the table needs them. Only a small slot per entry is kept.
+pub struct History {
+    entries: Vec<Slot>,
+    /// Storage file, which is removed when the History is dropped.
+    file: NamedTempFile,
+    file_len: u64,
+    budget: usize,
+    spill_size: usize,
+    /// Approximate memory used by the entries.
+    used: usize,
+    /// Index of the oldest entry which has not been evicted.
+    next_evict: usize,
+    /// Index of the oldest summary which has not been paged out.
+    next_page: usize,
+    /// Offset and length of each page of summaries in the storage file.
+    pages: Vec<(u64, u64)>,
+    loaded: RefCell<Vec<(usize, Req)>>,
+    loaded_pages: RefCell<Vec<(usize, Rc<Vec<Req>>)>>,
+}
+
+impl History {
+    pub fn new(settings: &StorageSettings) -> Result<Self, String> {
+        let dir = match &settings.dir {
+            Some(d) => PathBuf::from(d),
+            None => std::env::temp_dir().join(format!("rudy-{}", std::process::id())),
+        };
+        // The directory may be shared by several instances, which each use their own file.
+        let file = fs::create_dir_all(&dir).and_then(|_| {
+            tempfile::Builder::new()
+                .prefix("history-")
+                .suffix(".data")
+                .tempfile_in(&dir)
+        });
+        let file = file.map_err(|e| {
+            format!(
+                "Unable to create storage file in '{}'. {}",
+                dir.display(),
+                e
+            )
+        })?;
+
+        Ok(History {
+            entries: vec![],
+            file,
+            file_len: 0,
+            budget: settings.memory_budget_mb as usize * 1024 * 1024,
+            spill_size: settings.spill_body_kb as usize * 1024,
+            used: 0,
+            next_evict: 0,
+            next_page: 0,
+            pages: vec![],
+            loaded: RefCell::new(vec![]),
+            loaded_pages: RefCell::new(vec![]),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns an entry as kept in memory, which may not include its bodies and headers. The
+    /// summary is read back from disk if it was paged out.
+    pub fn get(&self, i: usize) -> Cow<'_, Req> {
+        let (page, pos, stored) = match &self.entries[i] {
+            Slot::Loaded(req) => return Cow::Borrowed(req),
+            Slot::Paged { page, pos, stored } => (*page, *pos, *stored),
+        };
+
+        match self.read_page(page) {
+            Ok(summaries) => Cow::Owned(Req {
+                stored: Some(stored),
+                ..summaries[pos].clone()
+            }),
+            Err(e) => {
+                warn!("Unable to read entry from disk. {}", e);
+                Cow::Owned(Req {
+                    id: i + 1,
+                    error: Some(format!("Unable to read entry from disk. {}", e)),
+                    stored: Some(stored),
+                    ..Req::default()
+                })
+            }
+        }
+    }
+
+    /// Returns an entry to annotate. A summary which was paged out is loaded into memory again
+    /// and stays there.
+    pub fn get_mut(&mut self, i: usize) -> &mut Req {
+        if let Slot::Paged { .. } = self.entries[i] {
+            let req = self.get(i).into_owned();
+            self.used += memory_size(&req);
+            self.entries[i] = Slot::Loaded(Box::new(req));
+        }
+        match &mut self.entries[i] {
+            Slot::Loaded(req) => req,
+            Slot::Paged { .. } => unreachable!("Entry was loaded above"),
+        }
+    }
+
+    /// Returns the status, response body length and whether there is an error of an entry, which
+    /// change when a pending response arrives.
+    pub fn response_state(&self, i: usize) -> (Option<u16>, u64, bool) {
+        let req = self.get(i);
+        let body_len = req
+            .stored
+            .map_or(req.res_body.len() as u64, |s| s.res_body_len);
+        (req.status, body_len, req.error.is_some())
+    }
+
+    pub fn push(&mut self, mut req: Req) {
+        if req.body.len() > self.spill_size || req.res_body.len() > self.spill_size {
+            match self.store(&req) {
+                Ok(stored) => {
+                    req.stored = Some(stored);
+                    req.body = vec![];
+                    req.res_body = vec![];
+                }
+                Err(e) => warn!("Unable to spill bodies to disk. {}", e),
+            }
+        }
+
+        self.used += size_of::<Slot>() + memory_size(&req);
+        self.entries.push(Slot::Loaded(Box::new(req)));
+
+        // The newest entry always stays in memory.
+        while self.used > self.budget && self.next_evict + 1 < self.entries.len() {
+            if let Err(e) = self.evict(self.next_evict) {
+                warn!("Unable to evict entry from memory. {}", e);
+                break;
+            }
+            self.next_evict += 1;
+        }
+
+        while self.used > self.budget && self.next_page + PAGE_SIZE <= self.next_evict {
+            if let Err(e) = self.page_out() {
+                warn!("Unable to move summaries to disk. {}", e);
+                break;
+            }
+        }
+    }
+
+    /// Returns the whole entry, reading it from disk if it has been spilled or evicted.
+    pub fn full(&self, i: usize) -> Cow<'_, Req> {
+        let req = self.get(i);
+        let stored = match req.stored {
+            Some(s) => s,
+            None => return req,
+        };
+
+        match self.read(&stored) {
+            Ok(full) => Cow::Owned(with_annotations(full, &req)),
+            Err(e) => {
+                warn!("Unable to read entry from disk. {}", e);
+                req
+            }
+        }
+    }
+
+    /// Like [`History::full`], but keeps the last few entries read from disk in memory. Used for
+    /// entries which are shown on every redraw.
+    pub fn full_cached(&self, i: usize) -> Cow<'_, Req> {
+        let req = self.get(i);
+        if req.stored.is_none() {
+            return req;
+        }
+
+        let mut loaded = self.loaded.borrow_mut();
+        if let Some(pos) = loaded.iter().position(|(j, _)| *j == i) {
+            let entry = loaded.remove(pos);
+            let full = entry.1.clone();
+            loaded.push(entry);
+            return Cow::Owned(with_annotations(full, &req));
+        }
+
+        let full = self.full(i);
+        if let Cow::Owned(full) = &full {
+            if loaded.len() >= LOADED_CACHE_SIZE {
+                loaded.remove(0);
+            }
+            loaded.push((i, full.clone()));
+        }
+        full
+    }
+
+    /// Writes an entry to the end of the storage file.
+    fn store(&mut self, req: &Req) -> io::Result<Stored> {
+        let (offset, len) = self.write_record(serde_json::to_vec(req)?)?;
+        Ok(Stored {
+            offset,
+            len,
+            body_len: req.body.len() as u64,
+            res_body_len: req.res_body.len() as u64,
+        })
+    }
+
+    fn read(&self, stored: &Stored) -> io::Result<Req> {
+        self.read_record(stored.offset, stored.len)
+    }
+
+    /// Writes serialized JSON as a line to the end of the storage file. Returns its offset and
+    /// length.
+    fn write_record(&mut self, mut bytes: Vec<u8>) -> io::Result<(u64, u64)> {
+        bytes.push(b'\n');
+
+        let file = self.file.as_file_mut();
+        file.seek(SeekFrom::Start(self.file_len))?;
+        file.write_all(&bytes)?;
+
+        let offset = self.file_len;
+        self.file_len += bytes.len() as u64;
+        Ok((offset, bytes.len() as u64))
+    }
+
+    fn read_record<T: DeserializeOwned>(&self, offset: u64, len: u64) -> io::Result<T> {
+        let mut file = File::open(self.file.path())?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut bytes = vec![0; len as usize];
+        file.read_exact(&mut bytes)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Returns a page of summaries, keeping the last few pages read from disk in memory.
+    fn read_page(&self, page: usize) -> io::Result<Rc<Vec<Req>>> {
+        let mut loaded = self.loaded_pages.borrow_mut();
+        if let Some(pos) = loaded.iter().position(|(p, _)| *p == page) {
+            let entry = loaded.remove(pos);
+            let summaries = Rc::clone(&entry.1);
+            loaded.push(entry);
+            return Ok(summaries);
+        }
+
+        let (offset, len) = self.pages[page];
+        let summaries = Rc::new(self.read_record::<Vec<Req>>(offset, len)?);
+        if loaded.len() >= LOADED_PAGES {
+            loaded.remove(0);
+        }
+        loaded.push((page, Rc::clone(&summaries)));
+        Ok(summaries)
+    }
+
+    /// Writes the oldest summaries which are still in memory to a page of the storage file.
+    fn page_out(&mut self) -> io::Result<()> {
+        let range = self.next_page..self.next_page + PAGE_SIZE;
+        let summaries: Vec<&Req> = self.entries[range.clone()]
+            .iter()
+            .filter_map(|slot| match slot {
+                Slot::Loaded(req) => Some(req.as_ref()),
+                Slot::Paged { .. } => None,
+            })
+            .collect();
+        let freed: usize = summaries.iter().map(|req| memory_size(req)).sum();
+        let stored: Vec<Option<Stored>> = summaries.iter().map(|req| req.stored).collect();
+        let bytes = serde_json::to_vec(&summaries)?;
+        let location = self.write_record(bytes)?;
+
+        let page = self.pages.len();
+        self.pages.push(location);
+        for ((pos, i), stored) in range.enumerate().zip(stored) {
+            // Evicted entries always have been stored.
+            if let Some(stored) = stored {
+                self.entries[i] = Slot::Paged { page, pos, stored };
+            }
+        }
+        self.used -= freed;
+        self.next_page += PAGE_SIZE;
+        Ok(())
+    }
+
+    /// Reduces an entry to what the History table, sorting and filtering need.
+    fn evict(&mut self, i: usize) -> io::Result<()> {
+        let mut req = match &mut self.entries[i] {
+            Slot::Loaded(req) => std::mem::take(req),
+            Slot::Paged { .. } => return Ok(()),
+        };
+        if req.stored.is_none() {
+            match self.store(&req) {
+                Ok(stored) => req.stored = Some(stored),
+                Err(e) => {
+                    self.entries[i] = Slot::Loaded(req);
+                    return Err(e);
+                }
+            }
+        }
+
+        self.used -= memory_size(&req);
+
+        let content_type = |(name, _): &(String, String)| name.eq_ignore_ascii_case("content-type");
+        req.headers.retain(content_type);
+        req.res_headers.retain(content_type);
+        req.body = vec![];
+        req.res_body = vec![];
+        req.tls = None;
+
+        self.used += memory_size(&req);
+        self.entries[i] = Slot::Loaded(req);
+        Ok(())
+    }
+}
+
+/// Copies the annotations from the in-memory entry, which may have changed after it was stored.
+fn with_annotations(mut full: Req, req: &Req) -> Req {
+    full.id = req.id;
+    full.highlight = req.highlight;
+    full.comment = req.comment.clone();
+    full.tags = req.tags.clone();
+    full.stored = req.stored;
+    full
+}
+
+/// Approximate number of bytes an entry takes up in memory.
+fn memory_size(req: &Req) -> usize {
+    let headers: usize = req
+        .headers
+        .iter()
+        .chain(&req.res_headers)
+        .map(|(name, value)| name.len() + value.len() + size_of::<(String, String)>())
+        .sum();
+    let tls: usize = req.tls.as_ref().map_or(0, |tls| {
+        tls.certificates
+            .iter()
+            .map(|c| c.subject.len() + c.issuer.len() + c.sans.concat().len() + 256)
+            .sum()
+    });
+    let issues: usize = req
+        .issues
+        .iter()
+        .map(|i| i.title.len() + i.evidence.len() + size_of::<Issue>())
+        .sum();
+
+    size_of::<Req>()
+        + req.host.len()
+        + req.method.len()
+        + req.url.len()
+        + headers
+        + req.body.len()
+        + req.res_body.len()
+        + req.error.as_ref().map_or(0, |e| e.len())
+        + tls
+        + req.listener.len()
+        + issues
+        + req.comment.len()
+        + req.tags.concat().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(dir: &std::path::Path) -> StorageSettings {
+        StorageSettings {
+            memory_budget_mb: 0,
+            spill_body_kb: 1,
+            dir: Some(dir.display().to_string()),
+        }
+    }
+
+    fn req(body: &str) -> Req {
+        Req {
+            url: "http://a.test/".to_string(),
+            headers: vec![("X-Test".to_string(), "1".to_string())],
+            body: body.as_bytes().to_vec(),
+            ..Req::default()
+        }
+    }
+
+    #[test]
+    fn evicts_old_entries_and_reads_them_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = History::new(&settings(dir.path())).unwrap();
+        history.push(req("first"));
+        history.push(req("second"));
+
+        assert!(history.get(0).stored.is_some());
+        assert!(history.get(0).body.is_empty());
+        assert!(history.get(0).headers.is_empty());
+        assert!(history.get(1).stored.is_none());
+
+        history.get_mut(0).comment = "note".to_string();
+        let full = history.full(0);
+        assert_eq!(full.body, b"first");
+        assert_eq!(full.headers.len(), 1);
+        assert_eq!(full.comment, "note");
+        assert_eq!(history.full_cached(0).body, b"first");
+    }
+
+    #[test]
+    fn moves_summaries_to_disk_in_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = History::new(&settings(dir.path())).unwrap();
+        for i in 0..PAGE_SIZE + 2 {
+            let mut entry = req(&format!("body {}", i));
+            entry.id = i + 1;
+            history.push(entry);
+        }
+
+        assert!(matches!(
+            history.entries[0],
+            Slot::Paged {
+                page: 0,
+                pos: 0,
+                ..
+            }
+        ));
+        assert!(matches!(history.entries[PAGE_SIZE], Slot::Loaded(_)));
+        // Only the slots of paged out entries are left in memory.
+        let summary = memory_size(&history.get(PAGE_SIZE));
+        assert!(history.used < PAGE_SIZE * size_of::<Slot>() + 3 * summary + 1024);
+
+        assert_eq!(history.get(1).id, 2);
+        assert_eq!(history.get(1).url, "http://a.test/");
+        assert_eq!(history.full(1).body, b"body 1");
+        assert_eq!(history.response_state(1), (None, 0, false));
+
+        // Annotated entries are loaded into memory again.
+        history.get_mut(1).comment = "note".to_string();
+        assert!(matches!(history.entries[1], Slot::Loaded(_)));
+        assert_eq!(history.get(1).comment, "note");
+        assert_eq!(history.full(1).comment, "note");
+        assert_eq!(history.full(1).body, b"body 1");
+    }
+
+    #[test]
+    fn spills_large_bodies_right_away() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = History::new(&settings(dir.path())).unwrap();
+        history.push(req(&"a".repeat(2048)));
+
+        let stored = history.get(0).stored.unwrap();
+        assert_eq!(stored.body_len, 2048);
+        assert!(history.get(0).body.is_empty());
+        assert_eq!(history.full(0).body.len(), 2048);
+    }
+
+    #[test]
+    fn uses_a_storage_file_per_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = History::new(&settings(dir.path())).unwrap();
+        let second = History::new(&settings(dir.path())).unwrap();
+        assert_ne!(first.file.path(), second.file.path());
+
+        drop(first);
+        drop(second);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
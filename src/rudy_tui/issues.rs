@@ -0,0 +1,136 @@
+use super::history::History;
+use crate::scanner::Severity;
+
+/// An issue found on a host, with the exchanges it was found in.
+pub struct Group {
+    pub severity: Severity,
+    pub title: String,
+    pub host: String,
+    /// Indices in the history of the exchanges with the issue, and the evidence found in each.
+    pub found: Vec<(usize, String)>,
+}
+
+/// State of the Issues tab: the issues of the History grouped by host and title.
+#[derive(Default)]
+pub struct Issues {
+    pub groups: Vec<Group>,
+    pub selected: usize,
+    /// Index of the first visible group.
+    pub offset: usize,
+    /// Exchange of the selected group which is shown.
+    pub exchange: usize,
+    pub scroll_y: u16,
+    /// Number of history entries whose issues have been grouped.
+    counted: usize,
+}
+
+impl Issues {
+    /// Groups the issues of the entries added to the history since the last update, keeping the
+    /// selected group.
+    pub fn update(&mut self, history: &History) {
+        if self.counted == history.len() {
+            return;
+        }
+        let selected = self
+            .groups
+            .get(self.selected)
+            .map(|g| (g.host.clone(), g.title.clone()));
+
+        for i in self.counted..history.len() {
+            let req = history.get(i);
+            for issue in &req.issues {
+                let group = self
+                    .groups
+                    .iter_mut()
+                    .find(|g| g.title == issue.title && g.host == req.host);
+                match group {
+                    // The same issue found twice in an exchange, such as two reflected
+                    // parameters, is listed once with all the evidence.
+                    Some(g) => match g.found.last_mut() {
+                        Some((j, evidence)) if *j == i => {
+                            *evidence += &format!(", {}", issue.evidence)
+                        }
+                        _ => g.found.push((i, issue.evidence.clone())),
+                    },
+                    None => self.groups.push(Group {
+                        severity: issue.severity,
+                        title: issue.title.clone(),
+                        host: req.host.clone(),
+                        found: vec![(i, issue.evidence.clone())],
+                    }),
+                }
+            }
+        }
+        self.counted = history.len();
+
+        self.groups
+            .sort_by(|a, b| (a.severity, &a.host, &a.title).cmp(&(b.severity, &b.host, &b.title)));
+        if let Some((host, title)) = selected {
+            let i = self
+                .groups
+                .iter()
+                .position(|g| g.host == host && g.title == title);
+            self.selected = i.unwrap_or_default();
+        }
+    }
+
+    /// Counts the groups of each severity, from high to info.
+    pub fn counts(&self) -> [(Severity, usize); 4] {
+        [
+            Severity::High,
+            Severity::Medium,
+            Severity::Low,
+            Severity::Info,
+        ]
+        .map(|s| (s, self.groups.iter().filter(|g| g.severity == s).count()))
+    }
+
+    pub fn select(&mut self, i: usize) {
+        if i < self.groups.len() && i != self.selected {
+            self.selected = i;
+            self.exchange = 0;
+            self.scroll_y = 0;
+        }
+    }
+
+    pub fn next(&mut self) {
+        self.select(self.selected + 1);
+    }
+
+    pub fn previous(&mut self) {
+        if self.selected > 0 {
+            self.select(self.selected - 1);
+        }
+    }
+
+    /// Moves the offset so the selected group is visible in a list of the given height.
+    pub fn scroll_to_selected(&mut self, height: usize) {
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if height > 0 && self.selected >= self.offset + height {
+            self.offset = self.selected + 1 - height;
+        }
+    }
+
+    /// Shows the next exchange of the selected group, wrapping around to the first one.
+    pub fn next_exchange(&mut self) {
+        if let Some(group) = self.groups.get(self.selected) {
+            self.exchange = (self.exchange + 1) % group.found.len();
+            self.scroll_y = 0;
+        }
+    }
+
+    /// Returns the index in the history of the shown exchange.
+    pub fn selected_entry(&self) -> Option<usize> {
+        let group = self.groups.get(self.selected)?;
+        group.found.get(self.exchange).map(|(i, _)| *i)
+    }
+
+    pub fn scroll_down(&mut self, lines: u16) {
+        self.scroll_y = self.scroll_y.saturating_add(lines);
+    }
+
+    pub fn scroll_up(&mut self, lines: u16) {
+        self.scroll_y = self.scroll_y.saturating_sub(lines);
+    }
+}
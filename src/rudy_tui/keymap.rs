@@ -0,0 +1,600 @@
+use crate::settings::{Action, EditAction, KeymapPreset, KeymapSettings};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{
+    de::{value::Error, IntoDeserializer},
+    Deserialize,
+};
+use std::{fmt, process::exit};
+
+type Bindings<A> = &'static [(A, &'static [&'static str])];
+
+const DEFAULT: Bindings<Action> = &[
+    (Action::Quit, &["q"]),
+    (Action::Help, &["?", "f1"]),
+    (Action::Close, &["esc"]),
+    (Action::NextTab, &["tab"]),
+    (Action::PreviousTab, &["backtab"]),
+    (Action::InterceptTab, &["i"]),
+    (Action::HistoryTab, &["h"]),
+    (Action::MapTab, &["M"]),
+    (Action::SettingsTab, &["s"]),
+    (Action::CompareTab, &["C"]),
+    (Action::DecoderTab, &["D"]),
+    (Action::TokensTab, &["T"]),
+    (Action::IssuesTab, &["!"]),
+    (Action::Down, &["j"]),
+    (Action::Up, &["k"]),
+    (Action::First, &["home"]),
+    (Action::Last, &["end"]),
+    (Action::Toggle, &["space"]),
+    (Action::Select, &["enter"]),
+    (Action::Search, &["/"]),
+    (Action::Filter, &["f"]),
+    (Action::SortColumn, &["o"]),
+    (Action::ReverseSort, &["O"]),
+    (Action::Highlight, &["m"]),
+    (Action::Comment, &["n"]),
+    (Action::Tags, &["t"]),
+    (Action::Save, &["w"]),
+    (Action::Export, &["x"]),
+    (Action::NextDetailTab, &["]"]),
+    (Action::PreviousDetailTab, &["["]),
+    (Action::ScrollDown, &["J", "down"]),
+    (Action::ScrollUp, &["K", "up"]),
+    (Action::ScrollRight, &["L", "right"]),
+    (Action::ScrollLeft, &["H", "left"]),
+    (Action::PageDown, &["pagedown"]),
+    (Action::PageUp, &["pageup"]),
+    (Action::ToggleWrap, &["W"]),
+    (Action::FindInDetail, &["b"]),
+    (Action::NextMatch, &["N"]),
+    (Action::CopyAs, &["y"]),
+    (Action::Import, &["I"]),
+    (Action::Send, &["r"]),
+    (Action::Compare, &["d"]),
+    (Action::SendToDecoder, &["E"]),
+    (Action::Edit, &["e"]),
+    (Action::RemoveStep, &["backspace"]),
+    (Action::ActiveScan, &["A"]),
+    (Action::PauseScan, &["P"]),
+];
+
+/// Changes to the default bindings for the vim preset.
+const VIM: Bindings<Action> = &[
+    (Action::InterceptTab, &["1"]),
+    (Action::HistoryTab, &["2"]),
+    (Action::MapTab, &["3"]),
+    (Action::SettingsTab, &["4"]),
+    (Action::CompareTab, &["5"]),
+    (Action::DecoderTab, &["6"]),
+    (Action::TokensTab, &["7"]),
+    (Action::IssuesTab, &["8"]),
+    (Action::First, &["g", "home"]),
+    (Action::Last, &["G", "end"]),
+    (Action::Comment, &["a"]),
+    (Action::ScrollDown, &["J", "ctrl-e", "down"]),
+    (Action::ScrollUp, &["K", "ctrl-y", "up"]),
+    (Action::ScrollRight, &["l", "L", "right"]),
+    (Action::ScrollLeft, &["h", "H", "left"]),
+    (Action::PageDown, &["ctrl-d", "ctrl-f", "pagedown"]),
+    (Action::PageUp, &["ctrl-u", "ctrl-b", "pageup"]),
+    (Action::NextMatch, &["n", "N"]),
+];
+
+const EMACS: Bindings<Action> = &[
+    (Action::Quit, &["ctrl-q"]),
+    (Action::Help, &["f1"]),
+    (Action::Close, &["ctrl-g", "esc"]),
+    (Action::NextTab, &["tab"]),
+    (Action::PreviousTab, &["backtab"]),
+    (Action::InterceptTab, &["alt-1"]),
+    (Action::HistoryTab, &["alt-2"]),
+    (Action::MapTab, &["alt-3"]),
+    (Action::SettingsTab, &["alt-4"]),
+    (Action::CompareTab, &["alt-5"]),
+    (Action::DecoderTab, &["alt-6"]),
+    (Action::TokensTab, &["alt-7"]),
+    (Action::IssuesTab, &["alt-8"]),
+    (Action::Down, &["ctrl-n"]),
+    (Action::Up, &["ctrl-p"]),
+    (Action::First, &["alt-<", "home"]),
+    (Action::Last, &["alt->", "end"]),
+    (Action::Toggle, &["space"]),
+    (Action::Select, &["enter"]),
+    (Action::Search, &["ctrl-s"]),
+    (Action::Filter, &["alt-f"]),
+    (Action::SortColumn, &["alt-o"]),
+    (Action::ReverseSort, &["alt-O"]),
+    (Action::Highlight, &["alt-m"]),
+    (Action::Comment, &["alt-;"]),
+    (Action::Tags, &["alt-t"]),
+    (Action::Save, &["ctrl-o"]),
+    (Action::Export, &["alt-x"]),
+    (Action::NextDetailTab, &["alt-]"]),
+    (Action::PreviousDetailTab, &["alt-["]),
+    (Action::ScrollDown, &["alt-n", "down"]),
+    (Action::ScrollUp, &["alt-p", "up"]),
+    (Action::ScrollRight, &["ctrl-f", "right"]),
+    (Action::ScrollLeft, &["ctrl-b", "left"]),
+    (Action::PageDown, &["ctrl-v", "pagedown"]),
+    (Action::PageUp, &["alt-v", "pageup"]),
+    (Action::ToggleWrap, &["alt-q"]),
+    (Action::FindInDetail, &["alt-s"]),
+    (Action::NextMatch, &["alt-g"]),
+    (Action::CopyAs, &["alt-w"]),
+    (Action::Import, &["alt-i"]),
+    (Action::Send, &["alt-r"]),
+    (Action::Compare, &["alt-d"]),
+    (Action::SendToDecoder, &["alt-E"]),
+    (Action::Edit, &["alt-e"]),
+    (Action::RemoveStep, &["ctrl-d"]),
+    (Action::ActiveScan, &["alt-a"]),
+    (Action::PauseScan, &["alt-P"]),
+];
+
+const INPUT_DEFAULT: Bindings<EditAction> = &[
+    (EditAction::Submit, &["enter"]),
+    (EditAction::Cancel, &["esc"]),
+    (EditAction::DeleteChar, &["backspace"]),
+    (EditAction::DeleteWord, &["ctrl-w"]),
+    (EditAction::Clear, &["ctrl-u"]),
+];
+
+const INPUT_EMACS: Bindings<EditAction> = &[
+    (EditAction::Submit, &["enter"]),
+    (EditAction::Cancel, &["ctrl-g", "esc"]),
+    (EditAction::DeleteChar, &["backspace", "ctrl-h"]),
+    (EditAction::DeleteWord, &["alt-backspace", "ctrl-w"]),
+    (EditAction::Clear, &["ctrl-k", "ctrl-u"]),
+];
+
+/// A key and its modifiers. Shift is part of the character rather than a modifier.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    /// Parses a key such as `q`, `ctrl-c`, `alt-x`, `shift-tab` or `pagedown`.
+    fn parse(s: &str) -> Option<Key> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = s;
+        while rest.chars().count() > 1 {
+            if let Some(r) = rest.strip_prefix("ctrl-") {
+                modifiers |= KeyModifiers::CONTROL;
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix("alt-") {
+                modifiers |= KeyModifiers::ALT;
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix("shift-") {
+                modifiers |= KeyModifiers::SHIFT;
+                rest = r;
+            } else {
+                break;
+            }
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_lowercase().as_str() {
+                "enter" => KeyCode::Enter,
+                "esc" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                "space" => KeyCode::Char(' '),
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                f => KeyCode::F(f.strip_prefix('f')?.parse().ok()?),
+            },
+        };
+        Some(Key::new(code, modifiers))
+    }
+
+    /// Creates a key, moving shift into the character so keys compare the same however the
+    /// terminal reports them.
+    fn new(code: KeyCode, mut modifiers: KeyModifiers) -> Key {
+        let shift = modifiers.contains(KeyModifiers::SHIFT);
+        let code = match code {
+            KeyCode::Char(c) if shift => KeyCode::Char(c.to_ascii_uppercase()),
+            KeyCode::Tab if shift => KeyCode::BackTab,
+            code => code,
+        };
+        if matches!(code, KeyCode::Char(_) | KeyCode::BackTab) {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        Key { code, modifiers }
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(event: KeyEvent) -> Self {
+        Key::new(event.code, event.modifiers)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "shift-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            code => write!(f, "{}", format!("{:?}", code).to_lowercase()),
+        }
+    }
+}
+
+/// Keys bound to actions, for navigating and for typing into the filter bar.
+pub struct Keymap {
+    navigation: Vec<(Key, Action)>,
+    input: Vec<(Key, EditAction)>,
+}
+
+impl Keymap {
+    /// Builds the keymap from the preset and the configured bindings. Exits if a configured key
+    /// is invalid.
+    pub fn new(settings: &KeymapSettings) -> Self {
+        let mut keymap = Keymap {
+            navigation: vec![],
+            input: vec![],
+        };
+
+        let (navigation, input): (&[_], _) = match settings.preset {
+            KeymapPreset::Default => (&[DEFAULT], INPUT_DEFAULT),
+            KeymapPreset::Vim => (&[DEFAULT, VIM], INPUT_DEFAULT),
+            KeymapPreset::Emacs => (&[EMACS], INPUT_EMACS),
+        };
+        for (action, keys) in navigation.iter().copied().flatten() {
+            let keys = keys.iter().filter_map(|k| Key::parse(k)).collect();
+            bind(&mut keymap.navigation, *action, keys);
+        }
+        for (action, keys) in input {
+            let keys = keys.iter().filter_map(|k| Key::parse(k)).collect();
+            bind(&mut keymap.input, *action, keys);
+        }
+
+        for (name, keys) in &settings.navigation {
+            bind(&mut keymap.navigation, parse_action(name), parse_keys(keys));
+        }
+        for (name, keys) in &settings.input {
+            bind(&mut keymap.input, parse_action(name), parse_keys(keys));
+        }
+        keymap
+    }
+
+    /// Returns the action bound to a key while navigating.
+    pub fn action(&self, event: KeyEvent) -> Option<Action> {
+        lookup(&self.navigation, event)
+    }
+
+    /// Returns the action bound to a key while typing text.
+    pub fn edit_action(&self, event: KeyEvent) -> Option<EditAction> {
+        lookup(&self.input, event)
+    }
+
+    /// Returns the first key bound to an action, for hints.
+    pub fn key(&self, action: Action) -> String {
+        self.navigation
+            .iter()
+            .find(|(_, a)| *a == action)
+            .map_or("(unbound)".to_string(), |(key, _)| key.to_string())
+    }
+
+    /// Returns the keys of every navigation action and its description, for the help overlay.
+    pub fn navigation_help(&self) -> Vec<(String, &'static str)> {
+        Action::ALL
+            .iter()
+            .map(|&a| (keys(&self.navigation, a), a.description()))
+            .collect()
+    }
+
+    /// Returns the keys of every text editing action and its description.
+    pub fn input_help(&self) -> Vec<(String, &'static str)> {
+        EditAction::ALL
+            .iter()
+            .map(|&a| (keys(&self.input, a), a.description()))
+            .collect()
+    }
+}
+
+/// Binds keys to an action, replacing the keys the action had before and removing the keys from
+/// other actions.
+fn bind<A: Copy + PartialEq>(bindings: &mut Vec<(Key, A)>, action: A, keys: Vec<Key>) {
+    bindings.retain(|(key, a)| *a != action && !keys.contains(key));
+    bindings.extend(keys.into_iter().map(|key| (key, action)));
+}
+
+/// Parses the name of an action, such as `next_tab`.
+fn parse_action<'de, A: Deserialize<'de>>(name: &'de str) -> A {
+    match A::deserialize(IntoDeserializer::<Error>::into_deserializer(name)) {
+        Ok(action) => action,
+        Err(e) => {
+            eprintln!("Invalid action in keymap settings. {}", e);
+            exit(1)
+        }
+    }
+}
+
+fn parse_keys(keys: &[String]) -> Vec<Key> {
+    keys.iter()
+        .map(|k| match Key::parse(k) {
+            Some(key) => key,
+            None => {
+                eprintln!("Invalid key '{}' in keymap settings", k);
+                exit(1)
+            }
+        })
+        .collect()
+}
+
+fn lookup<A: Copy>(bindings: &[(Key, A)], event: KeyEvent) -> Option<A> {
+    let key = Key::from(event);
+    bindings.iter().find(|(k, _)| *k == key).map(|(_, a)| *a)
+}
+
+fn keys<A: PartialEq>(bindings: &[(Key, A)], action: A) -> String {
+    let keys: Vec<String> = bindings
+        .iter()
+        .filter(|(_, a)| *a == action)
+        .map(|(key, _)| key.to_string())
+        .collect();
+    keys.join(", ")
+}
+
+impl Action {
+    pub const ALL: [Action; 48] = [
+        Action::Quit,
+        Action::Help,
+        Action::Close,
+        Action::NextTab,
+        Action::PreviousTab,
+        Action::InterceptTab,
+        Action::HistoryTab,
+        Action::MapTab,
+        Action::SettingsTab,
+        Action::CompareTab,
+        Action::DecoderTab,
+        Action::TokensTab,
+        Action::IssuesTab,
+        Action::Down,
+        Action::Up,
+        Action::First,
+        Action::Last,
+        Action::Toggle,
+        Action::Select,
+        Action::Search,
+        Action::Filter,
+        Action::SortColumn,
+        Action::ReverseSort,
+        Action::Highlight,
+        Action::Comment,
+        Action::Tags,
+        Action::Save,
+        Action::Export,
+        Action::NextDetailTab,
+        Action::PreviousDetailTab,
+        Action::ScrollDown,
+        Action::ScrollUp,
+        Action::ScrollRight,
+        Action::ScrollLeft,
+        Action::PageDown,
+        Action::PageUp,
+        Action::ToggleWrap,
+        Action::FindInDetail,
+        Action::NextMatch,
+        Action::CopyAs,
+        Action::Import,
+        Action::Send,
+        Action::Compare,
+        Action::SendToDecoder,
+        Action::Edit,
+        Action::RemoveStep,
+        Action::ActiveScan,
+        Action::PauseScan,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Quit => "Quit",
+            Action::Help => "Show or hide this help",
+            Action::Close => "Close the help or context menu",
+            Action::NextTab => "Next tab",
+            Action::PreviousTab => "Previous tab",
+            Action::InterceptTab => "Intercept tab",
+            Action::HistoryTab => "History tab",
+            Action::MapTab => "Map tab",
+            Action::SettingsTab => "Settings tab",
+            Action::CompareTab => "Compare tab",
+            Action::DecoderTab => "Decoder tab",
+            Action::TokensTab => "Tokens tab",
+            Action::IssuesTab => "Issues tab",
+            Action::Down => "Select the next entry, step or issue, or scroll the comparison down",
+            Action::Up => "Select the previous entry, step or issue, or scroll the comparison up",
+            Action::First => "Select the first entry",
+            Action::Last => "Select the last entry",
+            Action::Toggle => {
+                "Expand or collapse a site map node, compare by words or bytes, or switch a step \
+                 between decoding and encoding"
+            }
+            Action::Select => {
+                "Open the context menu, show a node or issue in History, run a menu action, add a \
+                 step or sign a token"
+            }
+            Action::Search => "Search the History",
+            Action::Filter => "Filter the History",
+            Action::SortColumn => "Sort by the next column",
+            Action::ReverseSort => "Reverse the sort order",
+            Action::Highlight => "Cycle the highlight color",
+            Action::Comment => "Edit the comment",
+            Action::Tags => "Edit the tags",
+            Action::Save => "Save the project",
+            Action::Export => "Export the shown entries",
+            Action::NextDetailTab => "Next detail tab, or compare the other part",
+            Action::PreviousDetailTab => "Previous detail tab, or compare the other part",
+            Action::ScrollDown => "Scroll the detail viewer down",
+            Action::ScrollUp => "Scroll the detail viewer up",
+            Action::ScrollRight => "Scroll the detail viewer right",
+            Action::ScrollLeft => "Scroll the detail viewer left",
+            Action::PageDown => "Scroll the detail viewer down a page",
+            Action::PageUp => "Scroll the detail viewer up a page",
+            Action::ToggleWrap => "Toggle line wrapping in the detail viewer",
+            Action::FindInDetail => "Find in the detail viewer",
+            Action::NextMatch => {
+                "Jump to the next match in the detail viewer, the next change or the next exchange \
+                 with the issue"
+            }
+            Action::CopyAs => {
+                "Copy or save the request as code, or copy the Decoder output or token"
+            }
+            Action::Import => "Import a request from a curl command or a file",
+            Action::Send => {
+                "Send the request again with the upstream client, or add it with the Decoder \
+                 output to History"
+            }
+            Action::Compare => "Mark the entry to compare it with another one",
+            Action::SendToDecoder => "Send the body or detail text to the Decoder",
+            Action::Edit => "Edit the Decoder input or a claim of the token",
+            Action::RemoveStep => "Remove the selected Decoder step",
+            Action::ActiveScan => "Run the active checks on the entry",
+            Action::PauseScan => "Pause or resume the active scan",
+        }
+    }
+}
+
+impl EditAction {
+    pub const ALL: [EditAction; 5] = [
+        EditAction::Submit,
+        EditAction::Cancel,
+        EditAction::DeleteChar,
+        EditAction::DeleteWord,
+        EditAction::Clear,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            EditAction::Submit => "Apply the typed text",
+            EditAction::Cancel => "Stop typing",
+            EditAction::DeleteChar => "Delete the last character",
+            EditAction::DeleteWord => "Delete the last word",
+            EditAction::Clear => "Clear the typed text",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    fn keymap(preset: KeymapPreset, navigation: &[(&str, &[&str])]) -> Keymap {
+        Keymap::new(&KeymapSettings {
+            preset,
+            navigation: navigation
+                .iter()
+                .map(|(a, keys)| (a.to_string(), keys.iter().map(|k| k.to_string()).collect()))
+                .collect(),
+            input: Default::default(),
+        })
+    }
+
+    #[test]
+    fn parses_keys() {
+        let key = |s| Key::parse(s).map(|k| k.to_string());
+        assert_eq!(key("q").as_deref(), Some("q"));
+        assert_eq!(key("ctrl-alt-x").as_deref(), Some("ctrl-alt-x"));
+        assert_eq!(key("shift-a").as_deref(), Some("A"));
+        assert_eq!(key("shift-tab").as_deref(), Some("backtab"));
+        assert_eq!(key("PageDown").as_deref(), Some("pagedown"));
+        assert_eq!(key("space").as_deref(), Some("space"));
+        assert_eq!(key("f12").as_deref(), Some("f12"));
+        assert_eq!(key("-").as_deref(), Some("-"));
+        assert_eq!(key("alt-<").as_deref(), Some("alt-<"));
+        assert!(Key::parse("ctrl-").is_none());
+        assert!(Key::parse("hyper-x").is_none());
+        assert!(Key::parse("fx").is_none());
+    }
+
+    #[test]
+    fn compares_shifted_keys_however_they_are_reported() {
+        let shifted = Key::from(event(KeyCode::Char('a'), KeyModifiers::SHIFT));
+        let upper = Key::from(event(KeyCode::Char('A'), KeyModifiers::NONE));
+        assert!(shifted == upper);
+        assert!(Key::parse("A") == Some(upper));
+    }
+
+    #[test]
+    fn uses_the_preset_bindings() {
+        let default = keymap(KeymapPreset::Default, &[]);
+        let key = |c| event(KeyCode::Char(c), KeyModifiers::NONE);
+        assert!(default.action(key('q')) == Some(Action::Quit));
+        assert!(default.action(key('m')) == Some(Action::Highlight));
+        assert!(default.action(key('M')) == Some(Action::MapTab));
+        assert!(default.action(key('g')).is_none());
+
+        let vim = keymap(KeymapPreset::Vim, &[]);
+        assert!(vim.action(key('g')) == Some(Action::First));
+        assert!(vim.action(key('q')) == Some(Action::Quit));
+
+        let emacs = keymap(KeymapPreset::Emacs, &[]);
+        assert!(emacs.action(key('q')).is_none());
+        assert!(
+            emacs.action(event(KeyCode::Char('q'), KeyModifiers::CONTROL)) == Some(Action::Quit)
+        );
+        assert!(
+            emacs.edit_action(event(KeyCode::Char('g'), KeyModifiers::CONTROL))
+                == Some(EditAction::Cancel)
+        );
+    }
+
+    #[test]
+    fn configured_keys_replace_the_preset_keys() {
+        let keymap = keymap(
+            KeymapPreset::Default,
+            &[("quit", &["ctrl-c"]), ("search", &["q"])],
+        );
+        let key = |c, m| keymap.action(event(KeyCode::Char(c), m));
+        assert!(key('c', KeyModifiers::CONTROL) == Some(Action::Quit));
+        assert!(key('q', KeyModifiers::NONE) == Some(Action::Search));
+        assert!(key('/', KeyModifiers::NONE).is_none());
+        assert_eq!(keymap.key(Action::Quit), "ctrl-c");
+    }
+
+    #[test]
+    fn binds_every_action_in_the_presets() {
+        for preset in [
+            KeymapPreset::Default,
+            KeymapPreset::Vim,
+            KeymapPreset::Emacs,
+        ] {
+            let keymap = keymap(preset, &[]);
+            for (keys, description) in keymap.navigation_help() {
+                assert!(!keys.is_empty(), "{} is unbound", description);
+            }
+            for (keys, description) in keymap.input_help() {
+                assert!(!keys.is_empty(), "{} is unbound", description);
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use super::{decoder::Step, snippet::Snippet, tokens::SignAction};
+use tui::widgets::ListState;
+
+/// Action offered in the context menu of a History entry.
+#[derive(Clone, Copy)]
+pub enum MenuAction {
+    Highlight,
+    Comment,
+    Tags,
+    FilterHost,
+    Export,
+    Compare,
+    Send,
+    SendToDecoder,
+    ActiveScan,
+    /// Runs the action an extension added to the menu with this index.
+    Extension(usize),
+    /// Opens a menu of the snippet actions.
+    Snippets,
+    CopyAs(Snippet),
+    SaveAs(Snippet),
+    /// Adds a step to the Decoder chain.
+    AddStep(Step),
+    /// Signs the edited token in the Tokens tab.
+    Sign(SignAction),
+    /// Adds a copy of the request with the edited token to the History.
+    AddTokenRequest,
+    ResetToken,
+}
+
+impl MenuAction {
+    pub const ALL: [MenuAction; 10] = [
+        MenuAction::Highlight,
+        MenuAction::Comment,
+        MenuAction::Tags,
+        MenuAction::FilterHost,
+        MenuAction::Export,
+        MenuAction::Compare,
+        MenuAction::Send,
+        MenuAction::SendToDecoder,
+        MenuAction::ActiveScan,
+        MenuAction::Snippets,
+    ];
+
+    /// Actions which copy or save the request as a snippet.
+    pub fn snippets() -> Vec<MenuAction> {
+        let copy = Snippet::ALL.iter().map(|&s| MenuAction::CopyAs(s));
+        let save = Snippet::ALL.iter().map(|&s| MenuAction::SaveAs(s));
+        copy.chain(save).collect()
+    }
+
+    pub fn title(&self) -> String {
+        match self {
+            MenuAction::Highlight => "Cycle highlight".to_string(),
+            MenuAction::Comment => "Edit comment".to_string(),
+            MenuAction::Tags => "Edit tags".to_string(),
+            MenuAction::FilterHost => "Show only this host".to_string(),
+            MenuAction::Export => "Export shown entries".to_string(),
+            MenuAction::Compare => "Compare with another entry".to_string(),
+            MenuAction::Send => "Send again".to_string(),
+            MenuAction::SendToDecoder => "Send to Decoder".to_string(),
+            MenuAction::ActiveScan => "Run the active checks".to_string(),
+            // The titles of extension actions are looked up in the App.
+            MenuAction::Extension(_) => String::new(),
+            MenuAction::Snippets => "Copy or save as code ▸".to_string(),
+            MenuAction::CopyAs(s) => format!("Copy as {}", s.title()),
+            MenuAction::SaveAs(s) => format!("Save as {}", s.title()),
+            MenuAction::AddStep(step) => step.title(),
+            MenuAction::Sign(action) => action.title(),
+            MenuAction::AddTokenRequest => "Add the request with this token to History".to_string(),
+            MenuAction::ResetToken => "Discard the edits".to_string(),
+        }
+    }
+}
+
+/// Context menu opened with a right click, shown at the position of the click.
+pub struct ContextMenu {
+    pub column: u16,
+    pub row: u16,
+    pub actions: Vec<MenuAction>,
+    pub state: ListState,
+}
+
+impl ContextMenu {
+    pub fn new(column: u16, row: u16, actions: Vec<MenuAction>) -> Self {
+        let mut state = ListState::default();
+        state.select(Some(0));
+        ContextMenu {
+            column,
+            row,
+            actions,
+            state,
+        }
+    }
+
+    pub fn next(&mut self) {
+        let i = self.state.selected().unwrap_or_default();
+        self.state.select(Some((i + 1).min(self.actions.len() - 1)));
+    }
+
+    pub fn previous(&mut self) {
+        let i = self.state.selected().unwrap_or_default();
+        self.state.select(Some(i.saturating_sub(1)));
+    }
+
+    pub fn selected(&self) -> Option<MenuAction> {
+        self.state
+            .selected()
+            .and_then(|i| self.actions.get(i))
+            .copied()
+    }
+}
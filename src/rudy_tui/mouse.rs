@@ -0,0 +1,293 @@
+use super::{
+    compare::Part,
+    detail::DetailTab,
+    menu::{ContextMenu, MenuAction},
+    App,
+};
+use crossterm::event::{MouseButton, MouseEvent, MouseEventKind};
+use tui::layout::Rect;
+
+/// Lines scrolled by one step of the mouse wheel in the detail viewer.
+const WHEEL_LINES: u16 = 3;
+
+/// Smallest height of the History table and of the detail viewer when resizing the split.
+const MIN_PANE_HEIGHT: u16 = 3;
+
+/// Screen areas recorded while rendering, used to find what a mouse event points at.
+#[derive(Default)]
+pub struct Areas {
+    /// Inside of the border around the main tabs.
+    pub tabs: Rect,
+    /// History table including its header row.
+    pub table: Rect,
+    /// Detail viewer tabs. Their top border is the split which can be dragged.
+    pub detail_tabs: Rect,
+    pub detail: Rect,
+    /// Inside of the border around the site map.
+    pub site_map: Rect,
+    /// Tabs of the compared parts and the diff below them in the Compare tab.
+    pub compare_tabs: Rect,
+    pub compare: Rect,
+    /// Chain of steps and the output of the selected one in the Decoder tab.
+    pub decoder_steps: Rect,
+    pub decoder_output: Rect,
+    /// Found tokens and the edited token in the Tokens tab.
+    pub token_list: Rect,
+    pub token_detail: Rect,
+    /// Issues and the selected one in the Issues tab.
+    pub issue_list: Rect,
+    pub issue_detail: Rect,
+    /// Inside of the border around the context menu.
+    pub menu: Rect,
+}
+
+fn contains(area: Rect, column: u16, row: u16) -> bool {
+    column >= area.x && column < area.right() && row >= area.y && row < area.bottom()
+}
+
+/// Returns the index of the tab title at a column, following the layout of the `Tabs` widget:
+/// a space, the title, a space and a one column divider for each title.
+fn tab_at<'t>(area: Rect, titles: impl Iterator<Item = &'t str>, column: u16) -> Option<usize> {
+    let mut x = area.x;
+    for (i, title) in titles.enumerate() {
+        let end = x + title.chars().count() as u16 + 2;
+        if column >= x && column < end {
+            return Some(i);
+        }
+        x = end + 1;
+    }
+    None
+}
+
+/// Handles a mouse event.
+pub fn handle_mouse(app: &mut App, event: MouseEvent) {
+    let (column, row) = (event.column, event.row);
+    app.refresh_visible();
+
+    if app.show_help {
+        match event.kind {
+            MouseEventKind::ScrollDown => app.help_scroll = app.help_scroll.saturating_add(1),
+            MouseEventKind::ScrollUp => app.help_scroll = app.help_scroll.saturating_sub(1),
+            MouseEventKind::Down(_) => app.show_help = false,
+            _ => {}
+        }
+        return;
+    }
+
+    if app.menu.is_some() {
+        handle_menu(app, event);
+        return;
+    }
+
+    match event.kind {
+        MouseEventKind::Down(MouseButton::Left) => click(app, column, row),
+        MouseEventKind::Down(MouseButton::Right) if app.index == 1 => open_menu(app, column, row),
+        MouseEventKind::Drag(MouseButton::Left) if app.dragging_split => {
+            resize_split(app, row);
+        }
+        MouseEventKind::Up(_) => app.dragging_split = false,
+        MouseEventKind::ScrollDown => scroll(app, column, row, true),
+        MouseEventKind::ScrollUp => scroll(app, column, row, false),
+        _ => {}
+    }
+}
+
+fn click(app: &mut App, column: u16, row: u16) {
+    let areas = &app.areas;
+    if contains(areas.tabs, column, row) {
+        let titles = app.titles();
+        if let Some(i) = tab_at(areas.tabs, titles.iter().map(String::as_str), column) {
+            app.go_to_tab(i);
+        }
+        return;
+    }
+
+    match app.index {
+        1 if row == areas.detail_tabs.y && contains(areas.detail_tabs, column, row) => {
+            app.dragging_split = true;
+        }
+        1 if contains(areas.detail_tabs, column, row) => {
+            let mut tabs_row = areas.detail_tabs;
+            tabs_row.y += 1;
+            let titles = DetailTab::ALL.iter().map(|t| t.title());
+            if let Some(i) = tab_at(tabs_row, titles, column) {
+                app.detail.tab = DetailTab::ALL[i];
+                app.detail.reset_scroll();
+            }
+        }
+        1 => {
+            select_hist_row(app, column, row);
+        }
+        2 if contains(areas.site_map, column, row) => {
+            let i = app.site_map.offset + (row - areas.site_map.y) as usize;
+            let len = app.site_map.lines(&app.history).len();
+            if i < len {
+                // A second click on the selected node expands or collapses it.
+                if app.site_map.state.selected() == Some(i) {
+                    app.toggle_map_node();
+                } else {
+                    app.site_map.state.select(Some(i));
+                }
+            }
+        }
+        5 if contains(areas.decoder_steps, column, row) => {
+            let i = (row - areas.decoder_steps.y) as usize;
+            if i <= app.decoder.steps.len() {
+                app.decoder.selected = i;
+                app.decoder.scroll_y = 0;
+            }
+        }
+        6 if contains(areas.token_list, column, row) => {
+            let i = (row - areas.token_list.y) as usize;
+            if i < app.tokens.found.len() && i != app.tokens.selected {
+                app.tokens.selected = i;
+                app.tokens.reset();
+            }
+        }
+        7 if contains(areas.issue_list, column, row) => {
+            let i = app.issues.offset + (row - areas.issue_list.y) as usize;
+            // A second click on the selected issue shows its exchange in the History.
+            if i == app.issues.selected {
+                app.show_issue_in_history();
+            } else {
+                app.issues.select(i);
+            }
+        }
+        4 if contains(areas.compare_tabs, column, row) => {
+            let mut tabs_row = areas.compare_tabs;
+            tabs_row.y += 1;
+            let titles = Part::ALL.iter().map(|p| p.title());
+            if let Some(i) = tab_at(tabs_row, titles, column) {
+                if Part::ALL[i] != app.compare.part {
+                    app.compare.next_part();
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Opens the context menu of the History row at a position.
+fn open_menu(app: &mut App, column: u16, row: u16) {
+    if select_hist_row(app, column, row) {
+        app.menu = Some(ContextMenu::new(column, row, MenuAction::ALL.to_vec()));
+    }
+}
+
+/// Selects the History row at a position. Returns false if there is no row there.
+fn select_hist_row(app: &mut App, column: u16, row: u16) -> bool {
+    let table = app.areas.table;
+    // The first row of the table is the header.
+    if !contains(table, column, row) || row == table.y {
+        return false;
+    }
+
+    let i = app.history_offset + (row - table.y - 1) as usize;
+    if i >= app.visible.len() {
+        return false;
+    }
+    if app.history_state.selected() != Some(i) {
+        app.history_state.select(Some(i));
+        app.detail.reset_scroll();
+    }
+    true
+}
+
+fn scroll(app: &mut App, column: u16, row: u16, down: bool) {
+    let areas = &app.areas;
+    match app.index {
+        1 if contains(areas.detail, column, row) => {
+            if down {
+                app.detail.scroll_down(WHEEL_LINES);
+            } else {
+                app.detail.scroll_up(WHEEL_LINES);
+            }
+        }
+        1 if contains(areas.table, column, row) => {
+            if down {
+                app.next_hist_item();
+            } else {
+                app.prev_hist_item();
+            }
+        }
+        2 if contains(areas.site_map, column, row) => {
+            if down {
+                app.next_map_item();
+            } else {
+                app.site_map.previous();
+            }
+        }
+        5 if contains(areas.decoder_output, column, row) => {
+            if down {
+                app.decoder.scroll_down(WHEEL_LINES);
+            } else {
+                app.decoder.scroll_up(WHEEL_LINES);
+            }
+        }
+        6 if contains(areas.token_detail, column, row) => {
+            if down {
+                app.tokens.scroll_down(WHEEL_LINES);
+            } else {
+                app.tokens.scroll_up(WHEEL_LINES);
+            }
+        }
+        7 if contains(areas.issue_detail, column, row) => {
+            if down {
+                app.issues.scroll_down(WHEEL_LINES);
+            } else {
+                app.issues.scroll_up(WHEEL_LINES);
+            }
+        }
+        7 if contains(areas.issue_list, column, row) => {
+            if down {
+                app.issues.next();
+            } else {
+                app.issues.previous();
+            }
+        }
+        4 if contains(areas.compare, column, row) => {
+            if down {
+                app.compare.scroll_down(WHEEL_LINES);
+            } else {
+                app.compare.scroll_up(WHEEL_LINES);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Moves the split between the History table and the detail viewer to a row.
+fn resize_split(app: &mut App, row: u16) {
+    let table = app.areas.table;
+    let bottom = app.areas.detail.bottom();
+    let max = bottom.saturating_sub(table.y + MIN_PANE_HEIGHT + 2);
+    let height = row
+        .saturating_sub(table.y)
+        .clamp(MIN_PANE_HEIGHT, max.max(MIN_PANE_HEIGHT));
+    app.split = Some(height);
+}
+
+fn handle_menu(app: &mut App, event: MouseEvent) {
+    let area = app.areas.menu;
+    let (column, row) = (event.column, event.row);
+    match event.kind {
+        MouseEventKind::Down(MouseButton::Left) if contains(area, column, row) => {
+            if let Some(menu) = &mut app.menu {
+                menu.state.select(Some((row - area.y) as usize));
+            }
+            app.run_menu_action();
+        }
+        MouseEventKind::Down(_) => app.menu = None,
+        MouseEventKind::ScrollDown => {
+            if let Some(menu) = &mut app.menu {
+                menu.next();
+            }
+        }
+        MouseEventKind::ScrollUp => {
+            if let Some(menu) = &mut app.menu {
+                menu.previous();
+            }
+        }
+        _ => {}
+    }
+}
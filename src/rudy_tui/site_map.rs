@@ -0,0 +1,162 @@
+use super::filter::quote_value;
+use super::{History, Req};
+use hudsucker::hyper::Uri;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use tui::widgets::ListState;
+
+/// Tree of the captured traffic, grouped by scheme, host, path segments and query parameters.
+#[derive(Default)]
+pub struct SiteMap {
+    /// Keys of the expanded nodes.
+    expanded: HashSet<String>,
+    pub state: ListState,
+    /// Index of the first visible line.
+    pub offset: usize,
+    root: Node,
+    /// Number of history entries which have been added to the tree.
+    counted: usize,
+}
+
+/// A node of the site map as shown in the tree.
+pub struct Line {
+    pub depth: usize,
+    pub label: String,
+    pub count: usize,
+    pub statuses: Vec<u16>,
+    pub has_children: bool,
+    pub expanded: bool,
+    /// Filter which limits the History to the requests below the node.
+    pub query: String,
+    key: String,
+}
+
+#[derive(Default)]
+struct Node {
+    label: String,
+    query: String,
+    count: usize,
+    statuses: BTreeSet<u16>,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn child(&mut self, key: &str, label: &str, query: String) -> &mut Node {
+        self.children
+            .entry(key.to_string())
+            .or_insert_with(|| Node {
+                label: label.to_string(),
+                query,
+                ..Default::default()
+            })
+    }
+
+    fn add(&mut self, req: &Req) {
+        self.count += 1;
+        if let Some(status) = req.status {
+            self.statuses.insert(status);
+        }
+    }
+}
+
+impl SiteMap {
+    /// Returns the visible lines of the tree, after adding the entries added to the history since
+    /// the last time.
+    pub fn lines(&mut self, history: &History) -> Vec<Line> {
+        for i in self.counted..history.len() {
+            add_req(&mut self.root, &history.get(i));
+        }
+        self.counted = history.len();
+
+        let mut lines = vec![];
+        for (key, node) in &self.root.children {
+            self.flatten(key, node, 0, &mut lines);
+        }
+        lines
+    }
+
+    fn flatten(&self, key: &str, node: &Node, depth: usize, lines: &mut Vec<Line>) {
+        let expanded = self.expanded.contains(key);
+        lines.push(Line {
+            depth,
+            label: node.label.clone(),
+            count: node.count,
+            statuses: node.statuses.iter().copied().collect(),
+            has_children: !node.children.is_empty(),
+            expanded,
+            query: node.query.clone(),
+            key: key.to_string(),
+        });
+
+        if expanded {
+            for (child_key, child) in &node.children {
+                self.flatten(child_key, child, depth + 1, lines);
+            }
+        }
+    }
+
+    /// Expands or collapses a node.
+    pub fn toggle(&mut self, line: &Line) {
+        if !self.expanded.remove(&line.key) {
+            self.expanded.insert(line.key.clone());
+        }
+    }
+
+    pub fn next(&mut self, len: usize) {
+        let i = match self.state.selected() {
+            Some(i) if len > 0 && i >= len - 1 => i,
+            Some(i) => i + 1,
+            None => 0,
+        };
+        self.state.select(Some(i));
+    }
+
+    pub fn previous(&mut self) {
+        let i = self.state.selected().unwrap_or_default();
+        self.state.select(Some(i.saturating_sub(1)));
+    }
+}
+
+/// Adds a request to the nodes for its scheme, host, path segments and query parameters.
+/// Requests without an absolute URL, such as passed through tunnels, are left out.
+fn add_req(root: &mut Node, req: &Req) {
+    let uri = match req.url.parse::<Uri>() {
+        Ok(u) => u,
+        Err(_) => return,
+    };
+    let (scheme, authority) = match (uri.scheme_str(), uri.authority()) {
+        (Some(s), Some(a)) => (s, a.as_str()),
+        _ => return,
+    };
+
+    let mut url = format!("{}://", scheme);
+    let mut node = root.child(&url, &url, format!("url:{}", quote_value(&url)));
+    node.add(req);
+
+    url += authority;
+    node = node.child(&url, authority, format!("url:{}", quote_value(&url)));
+    node.add(req);
+
+    for segment in uri.path().split('/').filter(|s| !s.is_empty()) {
+        url += "/";
+        url += segment;
+        node = node.child(
+            &url,
+            &format!("/{}", segment),
+            format!("url:{}", quote_value(&url)),
+        );
+        node.add(req);
+    }
+
+    let params: BTreeSet<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|p| p.split('=').next())
+        .filter(|n| !n.is_empty())
+        .collect();
+    for name in params {
+        let key = format!("{}?{}", url, name);
+        let query = format!("url:{} param:{}", quote_value(&url), quote_value(name));
+        node.child(&key, &format!("?{}", name), query).add(req);
+    }
+}
@@ -0,0 +1,332 @@
+use super::Req;
+use hudsucker::hyper::Uri;
+
+/// Headers which the tools set themselves from the URL and body.
+const SKIPPED_HEADERS: [&str; 6] = [
+    "host",
+    "content-length",
+    "connection",
+    "proxy-connection",
+    "keep-alive",
+    "transfer-encoding",
+];
+
+/// Code which repeats a request with another tool.
+#[derive(Clone, Copy)]
+pub enum Snippet {
+    Curl,
+    Httpie,
+    Python,
+    Reqwest,
+}
+
+impl Snippet {
+    pub const ALL: [Snippet; 4] = [
+        Snippet::Curl,
+        Snippet::Httpie,
+        Snippet::Python,
+        Snippet::Reqwest,
+    ];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Snippet::Curl => "curl",
+            Snippet::Httpie => "HTTPie",
+            Snippet::Python => "Python requests",
+            Snippet::Reqwest => "Rust reqwest",
+        }
+    }
+
+    /// End of the names of files the snippet is saved to.
+    pub fn file_suffix(&self) -> &'static str {
+        match self {
+            Snippet::Curl => "curl.sh",
+            Snippet::Httpie => "httpie.sh",
+            Snippet::Python => "py",
+            Snippet::Reqwest => "rs",
+        }
+    }
+
+    pub fn render(&self, req: &Req) -> String {
+        match self {
+            Snippet::Curl => curl(req),
+            Snippet::Httpie => httpie(req),
+            Snippet::Python => python(req),
+            Snippet::Reqwest => reqwest(req),
+        }
+    }
+}
+
+/// Returns the absolute URL of a request. Requests to an origin server only have the path.
+fn url(req: &Req) -> String {
+    match req.url.parse::<Uri>() {
+        Ok(uri) if uri.scheme().is_some() => req.url.clone(),
+        _ => format!("http://{}{}", req.host, req.url),
+    }
+}
+
+fn headers(req: &Req) -> impl Iterator<Item = &(String, String)> {
+    req.headers.iter().filter(|(name, _)| {
+        !SKIPPED_HEADERS
+            .iter()
+            .any(|skipped| name.eq_ignore_ascii_case(skipped))
+    })
+}
+
+/// Quotes a string for POSIX shells.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Returns the shell command which writes a binary body to stdin, or None if the body is text.
+fn binary_body_pipe(req: &Req) -> Option<String> {
+    match std::str::from_utf8(&req.body) {
+        Ok(_) => None,
+        Err(_) => Some(format!(
+            "printf '%s' {} | base64 -d | ",
+            shell_quote(&base64::encode(&req.body))
+        )),
+    }
+}
+
+fn curl(req: &Req) -> String {
+    let pipe = binary_body_pipe(req);
+    let mut lines = vec![format!(
+        "{}curl {}",
+        pipe.as_deref().unwrap_or_default(),
+        shell_quote(&url(req))
+    )];
+    // Brackets and braces in the URL are not globbing patterns.
+    lines.push("--globoff".to_string());
+
+    // curl picks GET or POST depending on whether there is a body. `-X HEAD` would wait for a
+    // response body.
+    let implied = if req.body.is_empty() { "GET" } else { "POST" };
+    if req.method == "HEAD" && req.body.is_empty() {
+        lines.push("-I".to_string());
+    } else if req.method != implied {
+        lines.push(format!("-X {}", shell_quote(&req.method)));
+    }
+    for (name, value) in headers(req) {
+        if name.eq_ignore_ascii_case("cookie") {
+            lines.push(format!("-b {}", shell_quote(value)));
+        } else {
+            lines.push(format!(
+                "-H {}",
+                shell_quote(&format!("{}: {}", name, value))
+            ));
+        }
+    }
+    if pipe.is_some() {
+        lines.push("--data-binary @-".to_string());
+    } else if !req.body.is_empty() {
+        let body = String::from_utf8_lossy(&req.body);
+        lines.push(format!("--data-raw {}", shell_quote(&body)));
+    }
+    lines.join(" \\\n  ") + "\n"
+}
+
+fn httpie(req: &Req) -> String {
+    let pipe = binary_body_pipe(req);
+    let mut lines = vec![format!("{}http", pipe.as_deref().unwrap_or_default())];
+    if pipe.is_none() && !req.body.is_empty() {
+        let body = String::from_utf8_lossy(&req.body);
+        lines.push(format!("--raw {}", shell_quote(&body)));
+    }
+    lines.push(format!(
+        "{} {}",
+        shell_quote(&req.method),
+        shell_quote(&url(req))
+    ));
+    for (name, value) in headers(req) {
+        lines.push(shell_quote(&format!("{}:{}", name, value)));
+    }
+    lines.join(" \\\n  ") + "\n"
+}
+
+/// Returns a Python string literal. JSON strings are valid Python string literals.
+fn python_str(s: &str) -> String {
+    serde_json::to_string(s).expect("Failed to serialize string")
+}
+
+fn python_bytes(bytes: &[u8]) -> String {
+    let escaped: String = bytes
+        .iter()
+        .map(|&b| match b {
+            b'\\' => "\\\\".to_string(),
+            b'"' => "\\\"".to_string(),
+            b' '..=b'~' => (b as char).to_string(),
+            b'\n' => "\\n".to_string(),
+            b'\r' => "\\r".to_string(),
+            b'\t' => "\\t".to_string(),
+            _ => format!("\\x{:02x}", b),
+        })
+        .collect();
+    format!("b\"{}\"", escaped)
+}
+
+fn python(req: &Req) -> String {
+    let mut p = "import requests\n\n".to_string();
+
+    let cookies: Vec<(&str, &str)> = req
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("cookie"))
+        .flat_map(|(_, value)| value.split(';'))
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .map(|c| c.split_once('=').unwrap_or((c, "")))
+        .collect();
+
+    p += "headers = {\n";
+    for (name, value) in headers(req).filter(|(name, _)| !name.eq_ignore_ascii_case("cookie")) {
+        p += &format!("    {}: {},\n", python_str(name), python_str(value));
+    }
+    p += "}\n";
+    p += "cookies = {\n";
+    for (name, value) in cookies {
+        p += &format!("    {}: {},\n", python_str(name), python_str(value));
+    }
+    p += "}\n";
+
+    // A str would be sent encoded as Latin-1 by requests.
+    let has_data = !req.body.is_empty();
+    if has_data {
+        p += &format!("data = {}\n", python_bytes(&req.body));
+    }
+
+    p += &format!(
+        "\nresponse = requests.request(\n    {},\n    {},\n    headers=headers,\n    cookies=cookies,\n",
+        python_str(&req.method),
+        python_str(&url(req))
+    );
+    if has_data {
+        p += "    data=data,\n";
+    }
+    p += ")\nprint(response.status_code)\nprint(response.text)\n";
+    p
+}
+
+fn rust_bytes(bytes: &[u8]) -> String {
+    let escaped: String = bytes
+        .iter()
+        .flat_map(|&b| std::ascii::escape_default(b))
+        .map(char::from)
+        .collect();
+    format!("b\"{}\"", escaped)
+}
+
+fn reqwest(req: &Req) -> String {
+    let mut p =
+        "// Needs the reqwest crate and tokio with the macros and rt-multi-thread features.\n"
+            .to_string();
+    p += "#[tokio::main]\n";
+    p += "async fn main() -> Result<(), Box<dyn std::error::Error>> {\n";
+    p += "    let response = reqwest::Client::new()\n";
+    p += &format!(
+        "        .request(reqwest::Method::from_bytes({})?, {:?})\n",
+        rust_bytes(req.method.as_bytes()),
+        url(req)
+    );
+    for (name, value) in headers(req) {
+        p += &format!("        .header({:?}, {:?})\n", name, value);
+    }
+    match std::str::from_utf8(&req.body) {
+        _ if req.body.is_empty() => {}
+        Ok(body) => p += &format!("        .body({:?})\n", body),
+        Err(_) => p += &format!("        .body(&{}[..])\n", rust_bytes(&req.body)),
+    }
+    p += "        .send()\n        .await?;\n";
+    p += "    println!(\"{}\", response.status());\n";
+    p += "    println!(\"{}\", response.text().await?);\n";
+    p += "    Ok(())\n}\n";
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(method: &str, body: &[u8]) -> Req {
+        Req {
+            method: method.to_string(),
+            url: "/a?b[0]=1".to_string(),
+            host: "a.test".to_string(),
+            headers: vec![
+                ("Host".to_string(), "a.test".to_string()),
+                ("Content-Length".to_string(), body.len().to_string()),
+                ("X-Note".to_string(), "it's".to_string()),
+                ("Cookie".to_string(), "a=1; b=2".to_string()),
+            ],
+            body: body.to_vec(),
+            ..Req::default()
+        }
+    }
+
+    #[test]
+    fn quotes_for_shells() {
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn renders_curl() {
+        assert_eq!(
+            curl(&req("PUT", b"x=1")),
+            "curl 'http://a.test/a?b[0]=1' \\\n  --globoff \\\n  -X 'PUT' \\\n  \
+             -H 'X-Note: it'\\''s' \\\n  -b 'a=1; b=2' \\\n  --data-raw 'x=1'\n"
+        );
+        assert!(!curl(&req("GET", b"")).contains("-X"));
+        assert!(!curl(&req("POST", b"x")).contains("-X"));
+    }
+
+    #[test]
+    fn renders_curl_head_requests_with_head_option() {
+        let snippet = curl(&req("HEAD", b""));
+        assert!(snippet.contains("\n  -I \\\n"));
+        assert!(!snippet.contains("-X"));
+    }
+
+    #[test]
+    fn pipes_binary_bodies() {
+        let snippet = curl(&req("POST", &[0xff, 0]));
+        assert!(snippet.starts_with("printf '%s' '/wA=' | base64 -d | curl "));
+        assert!(snippet.ends_with("--data-binary @-\n"));
+
+        let snippet = httpie(&req("POST", &[0xff, 0]));
+        assert!(snippet.starts_with("printf '%s' '/wA=' | base64 -d | http"));
+        assert!(!snippet.contains("--raw"));
+    }
+
+    #[test]
+    fn renders_httpie() {
+        assert_eq!(
+            httpie(&req("POST", b"x=1")),
+            "http \\\n  --raw 'x=1' \\\n  'POST' 'http://a.test/a?b[0]=1' \\\n  \
+             'X-Note:it'\\''s' \\\n  'Cookie:a=1; b=2'\n"
+        );
+    }
+
+    #[test]
+    fn renders_python_bodies_as_bytes() {
+        assert_eq!(python_bytes("é\"\\\n".as_bytes()), r#"b"\xc3\xa9\"\\\n""#);
+
+        let snippet = python(&req("POST", "x=é".as_bytes()));
+        assert!(snippet.contains("data = b\"x=\\xc3\\xa9\"\n"));
+        assert!(snippet.contains("    \"X-Note\": \"it's\",\n"));
+        assert!(snippet.contains("cookies = {\n    \"a\": \"1\",\n    \"b\": \"2\",\n}\n"));
+        assert!(snippet.contains("    data=data,\n"));
+        assert!(!python(&req("GET", b"")).contains("data"));
+    }
+
+    #[test]
+    fn renders_reqwest() {
+        let snippet = reqwest(&req("POST", &[0xff]));
+        assert!(snippet.contains(
+            ".request(reqwest::Method::from_bytes(b\"POST\")?, \"http://a.test/a?b[0]=1\")"
+        ));
+        assert!(snippet.contains(".header(\"X-Note\", \"it's\")"));
+        assert!(snippet.contains(".body(&b\"\\xff\"[..])"));
+    }
+}
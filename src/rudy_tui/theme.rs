@@ -0,0 +1,354 @@
+use crate::scanner::Severity;
+use serde::Deserialize;
+use std::{collections::HashMap, process::exit};
+use tui::style::{Color, Modifier, Style};
+
+/// Colors of the terminal UI, named by what they are used for.
+pub struct Theme {
+    /// Text of the History table.
+    pub text: Color,
+    /// Hints and other secondary text.
+    pub dim: Color,
+    /// Table headers, prompts and counts.
+    pub accent: Color,
+    /// Result of the last action.
+    pub info: Color,
+    pub error: Color,
+    /// First letter of the main tab titles, which is the key switching to the tab.
+    pub tab_key: Color,
+    pub tab: Color,
+    pub tab_selected_bg: Color,
+    pub detail_tab: Color,
+    pub detail_tab_selected: Color,
+    /// Selected row of the History table, site map and menus.
+    pub selected_fg: Color,
+    pub selected_bg: Color,
+    /// Text of highlighted History entries, shown on the highlight color.
+    pub highlighted_fg: Color,
+    /// Search matches in the detail viewer.
+    pub match_fg: Color,
+    pub match_bg: Color,
+    pub cursor: Color,
+    /// Background of removed and added text in the Compare tab.
+    pub diff_removed: Color,
+    pub diff_added: Color,
+    /// Severities of the issues in the Issues tab.
+    pub severity_high: Color,
+    pub severity_medium: Color,
+    pub severity_low: Color,
+    pub severity_info: Color,
+    pub status_1xx: Color,
+    pub status_2xx: Color,
+    pub status_3xx: Color,
+    pub status_4xx: Color,
+    pub status_5xx: Color,
+    pub method_get: Color,
+    pub method_post: Color,
+    pub method_put: Color,
+    pub method_patch: Color,
+    pub method_delete: Color,
+    pub method_other: Color,
+}
+
+/// A theme file: a built-in theme to start from and the colors which differ from it.
+#[derive(Deserialize)]
+struct ThemeFile {
+    #[serde(default = "default_base")]
+    base: String,
+    #[serde(flatten)]
+    colors: HashMap<String, String>,
+}
+
+fn default_base() -> String {
+    "dark".to_string()
+}
+
+impl Theme {
+    /// Loads a built-in theme by name or a theme file by path. Exits if the theme is invalid.
+    pub fn load(theme: &str) -> Theme {
+        if let Some(t) = Theme::builtin(theme) {
+            return t;
+        }
+
+        let contents = match std::fs::read_to_string(theme) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Unable to load theme file at '{}'. {}", theme, e);
+                exit(1)
+            }
+        };
+        let file: ThemeFile = match toml::from_str(&contents) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("Invalid theme file at '{}'. {}", theme, e);
+                exit(1)
+            }
+        };
+
+        let mut t = match Theme::builtin(&file.base) {
+            Some(t) => t,
+            None => {
+                eprintln!("Unknown base theme '{}' in '{}'", file.base, theme);
+                exit(1)
+            }
+        };
+        for (name, value) in &file.colors {
+            let color = match parse_color(value) {
+                Some(c) => c,
+                None => {
+                    eprintln!("Invalid color '{}' for {} in '{}'", value, name, theme);
+                    exit(1)
+                }
+            };
+            match t.color_mut(name) {
+                Some(c) => *c = color,
+                None => {
+                    eprintln!("Unknown color '{}' in '{}'", name, theme);
+                    exit(1)
+                }
+            }
+        }
+        t
+    }
+
+    fn builtin(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Theme::dark()),
+            "light" => Some(Theme::light()),
+            "high-contrast" => Some(Theme::high_contrast()),
+            _ => None,
+        }
+    }
+
+    fn dark() -> Theme {
+        Theme {
+            text: Color::White,
+            dim: Color::DarkGray,
+            accent: Color::Yellow,
+            info: Color::Cyan,
+            error: Color::Red,
+            tab_key: Color::Red,
+            tab: Color::Blue,
+            tab_selected_bg: Color::Black,
+            detail_tab: Color::Blue,
+            detail_tab_selected: Color::Red,
+            selected_fg: Color::Green,
+            selected_bg: Color::Gray,
+            highlighted_fg: Color::Black,
+            match_fg: Color::Black,
+            match_bg: Color::Yellow,
+            cursor: Color::White,
+            diff_removed: Color::Red,
+            diff_added: Color::Green,
+            severity_high: Color::Red,
+            severity_medium: Color::Yellow,
+            severity_low: Color::Cyan,
+            severity_info: Color::Gray,
+            status_1xx: Color::Gray,
+            status_2xx: Color::Green,
+            status_3xx: Color::Cyan,
+            status_4xx: Color::Yellow,
+            status_5xx: Color::Red,
+            method_get: Color::Green,
+            method_post: Color::Yellow,
+            method_put: Color::Blue,
+            method_patch: Color::Magenta,
+            method_delete: Color::Red,
+            method_other: Color::White,
+        }
+    }
+
+    fn light() -> Theme {
+        let orange = Color::Rgb(175, 95, 0);
+        Theme {
+            text: Color::Black,
+            dim: Color::DarkGray,
+            accent: Color::Magenta,
+            info: Color::Blue,
+            error: Color::Red,
+            tab_key: Color::Red,
+            tab: Color::Blue,
+            tab_selected_bg: Color::Gray,
+            detail_tab: Color::Blue,
+            detail_tab_selected: Color::Red,
+            selected_fg: Color::White,
+            selected_bg: Color::Blue,
+            highlighted_fg: Color::Black,
+            match_fg: Color::Black,
+            match_bg: Color::Yellow,
+            cursor: Color::Black,
+            diff_removed: Color::LightRed,
+            diff_added: Color::LightGreen,
+            severity_high: Color::Red,
+            severity_medium: orange,
+            severity_low: Color::Blue,
+            severity_info: Color::DarkGray,
+            status_1xx: Color::DarkGray,
+            status_2xx: Color::Green,
+            status_3xx: Color::Blue,
+            status_4xx: orange,
+            status_5xx: Color::Red,
+            method_get: Color::Green,
+            method_post: orange,
+            method_put: Color::Blue,
+            method_patch: Color::Magenta,
+            method_delete: Color::Red,
+            method_other: Color::Black,
+        }
+    }
+
+    fn high_contrast() -> Theme {
+        Theme {
+            text: Color::White,
+            dim: Color::Gray,
+            accent: Color::LightYellow,
+            info: Color::LightCyan,
+            error: Color::LightRed,
+            tab_key: Color::LightYellow,
+            tab: Color::White,
+            tab_selected_bg: Color::Blue,
+            detail_tab: Color::White,
+            detail_tab_selected: Color::LightYellow,
+            selected_fg: Color::Black,
+            selected_bg: Color::White,
+            highlighted_fg: Color::Black,
+            match_fg: Color::Black,
+            match_bg: Color::LightCyan,
+            cursor: Color::White,
+            diff_removed: Color::LightRed,
+            diff_added: Color::LightGreen,
+            severity_high: Color::LightRed,
+            severity_medium: Color::LightYellow,
+            severity_low: Color::LightCyan,
+            severity_info: Color::White,
+            status_1xx: Color::White,
+            status_2xx: Color::LightGreen,
+            status_3xx: Color::LightCyan,
+            status_4xx: Color::LightYellow,
+            status_5xx: Color::LightRed,
+            method_get: Color::LightGreen,
+            method_post: Color::LightYellow,
+            method_put: Color::LightBlue,
+            method_patch: Color::LightMagenta,
+            method_delete: Color::LightRed,
+            method_other: Color::White,
+        }
+    }
+
+    fn color_mut(&mut self, name: &str) -> Option<&mut Color> {
+        Some(match name {
+            "text" => &mut self.text,
+            "dim" => &mut self.dim,
+            "accent" => &mut self.accent,
+            "info" => &mut self.info,
+            "error" => &mut self.error,
+            "tab_key" => &mut self.tab_key,
+            "tab" => &mut self.tab,
+            "tab_selected_bg" => &mut self.tab_selected_bg,
+            "detail_tab" => &mut self.detail_tab,
+            "detail_tab_selected" => &mut self.detail_tab_selected,
+            "selected_fg" => &mut self.selected_fg,
+            "selected_bg" => &mut self.selected_bg,
+            "highlighted_fg" => &mut self.highlighted_fg,
+            "match_fg" => &mut self.match_fg,
+            "match_bg" => &mut self.match_bg,
+            "cursor" => &mut self.cursor,
+            "diff_removed" => &mut self.diff_removed,
+            "diff_added" => &mut self.diff_added,
+            "severity_high" => &mut self.severity_high,
+            "severity_medium" => &mut self.severity_medium,
+            "severity_low" => &mut self.severity_low,
+            "severity_info" => &mut self.severity_info,
+            "status_1xx" => &mut self.status_1xx,
+            "status_2xx" => &mut self.status_2xx,
+            "status_3xx" => &mut self.status_3xx,
+            "status_4xx" => &mut self.status_4xx,
+            "status_5xx" => &mut self.status_5xx,
+            "method_get" => &mut self.method_get,
+            "method_post" => &mut self.method_post,
+            "method_put" => &mut self.method_put,
+            "method_patch" => &mut self.method_patch,
+            "method_delete" => &mut self.method_delete,
+            "method_other" => &mut self.method_other,
+            _ => return None,
+        })
+    }
+
+    /// Style of the selected row of tables and lists.
+    pub fn selected(&self) -> Style {
+        Style::default()
+            .add_modifier(Modifier::BOLD)
+            .fg(self.selected_fg)
+            .bg(self.selected_bg)
+    }
+
+    pub fn status(&self, status: u16) -> Color {
+        match status {
+            100..=199 => self.status_1xx,
+            200..=299 => self.status_2xx,
+            300..=399 => self.status_3xx,
+            400..=499 => self.status_4xx,
+            _ => self.status_5xx,
+        }
+    }
+
+    pub fn severity(&self, severity: Severity) -> Color {
+        match severity {
+            Severity::High => self.severity_high,
+            Severity::Medium => self.severity_medium,
+            Severity::Low => self.severity_low,
+            Severity::Info => self.severity_info,
+        }
+    }
+
+    pub fn method(&self, method: &str) -> Color {
+        match method {
+            "GET" => self.method_get,
+            "POST" => self.method_post,
+            "PUT" => self.method_put,
+            "PATCH" => self.method_patch,
+            "DELETE" => self.method_delete,
+            _ => self.method_other,
+        }
+    }
+}
+
+/// Parses a color name such as `red` or `light-blue`, a hex color such as `#ff8800` or an index
+/// into the terminal's 256 color palette.
+fn parse_color(s: &str) -> Option<Color> {
+    if let Some(hex) = s.strip_prefix('#') {
+        let value = u32::from_str_radix(hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 6)?;
+        return Some(Color::Rgb(
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ));
+    }
+    if let Ok(index) = s.parse() {
+        return Some(Color::Indexed(index));
+    }
+
+    let color = match s.to_lowercase().replace(['-', '_'], "").as_str() {
+        "reset" | "default" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return None,
+    };
+    Some(color)
+}
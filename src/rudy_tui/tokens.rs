@@ -0,0 +1,284 @@
+use super::Req;
+use crate::jwt::{self, Found, Signing};
+use serde_json::{Map, Value};
+
+/// How the edited token is signed, chosen from the menu of the Tokens tab.
+#[derive(Clone, Copy)]
+pub enum SignAction {
+    Original,
+    None(&'static str),
+    /// Asks for an HMAC secret.
+    Hmac,
+    /// Asks for the path of a private key.
+    PrivateKey,
+    /// Asks for the path of the server's public key.
+    KeyConfusion,
+}
+
+impl SignAction {
+    pub fn all() -> Vec<SignAction> {
+        let mut actions = vec![
+            SignAction::Original,
+            SignAction::Hmac,
+            SignAction::PrivateKey,
+        ];
+        actions.extend(jwt::NONE_VARIANTS.iter().map(|&alg| SignAction::None(alg)));
+        actions.push(SignAction::KeyConfusion);
+        actions
+    }
+
+    pub fn title(&self) -> String {
+        match self {
+            SignAction::Original => "Keep the original signature".to_string(),
+            SignAction::None(alg) => format!("Unsigned with alg {}", alg),
+            SignAction::Hmac => "Sign with an HMAC secret".to_string(),
+            SignAction::PrivateKey => "Sign with a private key file".to_string(),
+            SignAction::KeyConfusion => "Sign with HMAC and the public key file".to_string(),
+        }
+    }
+}
+
+/// Returns a copy of a request with a token replaced in the URL, the headers and the body.
+/// The Content-Length header is updated to the length of the new body.
+pub fn replace_token(req: &Req, original: &str, token: &str) -> Req {
+    Req {
+        comment: format!("Request {} with an edited JWT", req.id),
+        ..req.with_replaced(original.as_bytes(), token.as_bytes())
+    }
+}
+
+/// State of the Tokens tab: the JWTs of a History entry and the edited copy of the selected one.
+pub struct Tokens {
+    /// Index in the history of the entry whose tokens are shown.
+    pub entry: Option<usize>,
+    /// State of the response of the entry when its tokens were found.
+    response: (Option<u16>, u64, bool),
+    pub found: Vec<Found>,
+    pub selected: usize,
+    pub header: Map<String, Value>,
+    pub claims: Map<String, Value>,
+    pub signing: Signing,
+    /// The edited token, or the error signing it.
+    pub output: Result<String, String>,
+    pub scroll_y: u16,
+}
+
+impl Default for Tokens {
+    fn default() -> Self {
+        Tokens {
+            entry: None,
+            response: (None, 0, false),
+            found: vec![],
+            selected: 0,
+            header: Map::new(),
+            claims: Map::new(),
+            signing: Signing::Original(String::new()),
+            output: Ok(String::new()),
+            scroll_y: 0,
+        }
+    }
+}
+
+impl Tokens {
+    /// Returns whether the tokens of an entry, with its response in the given state, are shown.
+    pub fn is_loaded(&self, entry: usize, response: (Option<u16>, u64, bool)) -> bool {
+        self.entry == Some(entry) && self.response == response
+    }
+
+    /// Shows the tokens found in a History entry. When the tokens of the same entry are found
+    /// again after its response arrived, the selected token keeps its edits.
+    pub fn load(&mut self, entry: usize, response: (Option<u16>, u64, bool), found: Vec<Found>) {
+        let original = self.original().map(str::to_string);
+        let same_entry = self.entry == Some(entry);
+        self.entry = Some(entry);
+        self.response = response;
+        self.found = found;
+
+        let kept = original
+            .filter(|_| same_entry)
+            .and_then(|original| self.found.iter().position(|f| f.jwt.token == original));
+        match kept {
+            Some(i) => self.selected = i,
+            None => {
+                self.selected = 0;
+                self.reset();
+            }
+        }
+    }
+
+    pub fn next(&mut self) {
+        if self.selected + 1 < self.found.len() {
+            self.selected += 1;
+            self.reset();
+        }
+    }
+
+    pub fn previous(&mut self) {
+        if self.selected > 0 {
+            self.selected -= 1;
+            self.reset();
+        }
+    }
+
+    /// Discards the edits of the selected token.
+    pub fn reset(&mut self) {
+        self.scroll_y = 0;
+        match self.found.get(self.selected) {
+            Some(found) => {
+                self.header = found.jwt.header.clone();
+                self.claims = found.jwt.claims.clone();
+                self.signing = Signing::Original(found.jwt.signature.clone());
+            }
+            None => {
+                self.header.clear();
+                self.claims.clear();
+                self.signing = Signing::Original(String::new());
+            }
+        }
+        self.encode();
+    }
+
+    pub fn original(&self) -> Option<&str> {
+        self.found.get(self.selected).map(|f| f.jwt.token.as_str())
+    }
+
+    pub fn set_signing(&mut self, signing: Signing) {
+        self.signing = signing;
+        self.encode();
+    }
+
+    /// Applies an edit such as `role="admin"` or `header.kid=1`. Values are JSON, or else
+    /// strings. An edit like `-role` removes the claim.
+    pub fn edit(&mut self, edit: &str) -> Result<(), String> {
+        let (remove, edit) = match edit.trim().strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, edit.trim()),
+        };
+        let (name, value) = match edit.split_once('=') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None if remove => (edit, ""),
+            None => return Err("Write name=value, or -name to remove a claim".to_string()),
+        };
+        let (map, name) = match name.strip_prefix("header.") {
+            Some(name) => (&mut self.header, name),
+            None => (&mut self.claims, name),
+        };
+        if name.is_empty() {
+            return Err("Missing claim name".to_string());
+        }
+
+        if remove {
+            map.remove(name);
+        } else {
+            let value =
+                serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+            map.insert(name.to_string(), value);
+        }
+        self.encode();
+        Ok(())
+    }
+
+    /// Encodes the edited token. A token which was not edited is kept as it is, since encoding
+    /// its JSON again can change the spacing and the order of the keys.
+    fn encode(&mut self) {
+        self.output = match self.found.get(self.selected) {
+            None => Ok(String::new()),
+            Some(found)
+                if matches!(self.signing, Signing::Original(_))
+                    && found.jwt.header == self.header
+                    && found.jwt.claims == self.claims =>
+            {
+                Ok(found.jwt.token.clone())
+            }
+            Some(_) => jwt::encode(&self.header, &self.claims, &self.signing),
+        };
+    }
+
+    pub fn scroll_down(&mut self, lines: u16) {
+        self.scroll_y = self.scroll_y.saturating_add(lines);
+    }
+
+    pub fn scroll_up(&mut self, lines: u16) {
+        self.scroll_y = self.scroll_y.saturating_sub(lines);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJhIn0.c2ln";
+
+    fn found(token: &str) -> Found {
+        Found {
+            location: "URL".to_string(),
+            jwt: jwt::Jwt::parse(token).unwrap(),
+        }
+    }
+
+    #[test]
+    fn replace_token_updates_content_length() {
+        let req = Req {
+            id: 3,
+            method: "POST".to_string(),
+            url: format!("http://a.test/?t={}", TOKEN),
+            headers: vec![
+                ("Authorization".to_string(), format!("Bearer {}", TOKEN)),
+                ("Content-Length".to_string(), "45".to_string()),
+            ],
+            body: format!("t={}", TOKEN).into_bytes(),
+            ..Req::default()
+        };
+        let edited = replace_token(&req, TOKEN, "a.b.");
+        assert_eq!(edited.url, "http://a.test/?t=a.b.");
+        assert_eq!(edited.body, b"t=a.b.");
+        assert_eq!(
+            edited.headers,
+            [
+                ("Authorization".to_string(), "Bearer a.b.".to_string()),
+                ("Content-Length".to_string(), "6".to_string()),
+            ]
+        );
+        assert_eq!(edited.comment, "Request 3 with an edited JWT");
+    }
+
+    #[test]
+    fn edits_change_the_output() {
+        let mut tokens = Tokens::default();
+        tokens.load(0, (None, 0, false), vec![found(TOKEN)]);
+        assert_eq!(tokens.output.as_deref(), Ok(TOKEN));
+
+        tokens.edit("role=\"admin\"").unwrap();
+        tokens.edit("header.kid=1").unwrap();
+        tokens.edit("-sub").unwrap();
+        assert!(tokens.edit("role").is_err());
+        let output = tokens.output.clone().unwrap();
+        let edited = jwt::Jwt::parse(&output).unwrap();
+        assert_eq!(edited.claims.get("role").unwrap(), "admin");
+        assert_eq!(edited.header.get("kid").unwrap(), 1);
+        assert!(!edited.claims.contains_key("sub"));
+        assert_eq!(edited.signature, "c2ln");
+
+        tokens.reset();
+        assert_eq!(tokens.output.as_deref(), Ok(TOKEN));
+    }
+
+    #[test]
+    fn reloading_after_a_response_keeps_edits() {
+        let other = "eyJhbGciOiJub25lIn0.eyJzdWIiOiJiIn0.";
+        let mut tokens = Tokens::default();
+        tokens.load(0, (None, 0, false), vec![found(TOKEN)]);
+        tokens.edit("role=admin").unwrap();
+        assert!(tokens.is_loaded(0, (None, 0, false)));
+        assert!(!tokens.is_loaded(0, (Some(200), 10, false)));
+
+        tokens.load(0, (Some(200), 10, false), vec![found(other), found(TOKEN)]);
+        assert_eq!(tokens.found.len(), 2);
+        assert_eq!(tokens.selected, 1);
+        assert!(tokens.claims.contains_key("role"));
+
+        tokens.load(1, (None, 0, false), vec![found(TOKEN)]);
+        assert_eq!(tokens.selected, 0);
+        assert!(!tokens.claims.contains_key("role"));
+    }
+}
@@ -0,0 +1,509 @@
+use rhai::{Blob, CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST};
+use ring::{aead, digest, hmac};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::exit,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tracing::warn;
+
+/// Interval at which the scripts directory is checked for changed files.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of operations after which a hook is stopped, so that a script stuck in a loop does not
+/// hang the exchange.
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+/// Depth of nested function calls after which a hook is stopped.
+const MAX_CALL_LEVELS: usize = 64;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// A request or response as seen by the hooks of a script, bound to `this`.
+#[derive(Clone)]
+pub struct Message {
+    pub method: String,
+    pub uri: String,
+    /// Status of a response, or 0 for a request.
+    pub status: i64,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Message {
+    fn header(&mut self, name: &str) -> String {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default()
+    }
+
+    /// Replaces all headers of a name with a single one.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.remove_header(name);
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    fn add_header(&mut self, name: &str, value: &str) {
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn remove_header(&mut self, name: &str) {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+}
+
+struct Script {
+    path: PathBuf,
+    modified: SystemTime,
+    ast: AST,
+}
+
+impl Script {
+    fn has_hook(&self, hook: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == hook && f.params.is_empty())
+    }
+
+    fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// The Rhai scripts of the scripts directory, whose `on_request` and `on_response` hooks can
+/// change the exchanges passing through the proxy. Scripts are run in the order of their file
+/// names and reloaded when they change.
+pub struct Scripts {
+    dir: PathBuf,
+    engine: Engine,
+    scripts: RwLock<Vec<Script>>,
+}
+
+impl Scripts {
+    /// Compiles the scripts of a directory and starts watching it for changes.
+    pub fn load(dir: &str) -> Arc<Scripts> {
+        let engine = engine();
+        let mut scripts = vec![];
+        for (path, modified) in script_files(Path::new(dir)).unwrap_or_else(|e| {
+            eprintln!("Unable to read scripts directory '{}'. {}", dir, e);
+            exit(1)
+        }) {
+            match compile(&engine, &path, modified) {
+                Ok(s) => scripts.push(s),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1)
+                }
+            }
+        }
+
+        let scripts = Arc::new(Scripts {
+            dir: PathBuf::from(dir),
+            engine,
+            scripts: RwLock::new(scripts),
+        });
+        tokio::spawn(watch(Arc::clone(&scripts)));
+        scripts
+    }
+
+    /// Checks if any script defines a hook.
+    pub fn has_hook(&self, hook: &str) -> bool {
+        self.scripts
+            .read()
+            .unwrap()
+            .iter()
+            .any(|s| s.has_hook(hook))
+    }
+
+    /// Runs a hook of every script which defines it. Returns the errors of the scripts which
+    /// failed, whose changes are discarded.
+    pub fn run(&self, hook: &str, message: &mut Message) -> Vec<String> {
+        let scripts = self.scripts.read().unwrap();
+        let mut errors = vec![];
+        for script in scripts.iter().filter(|s| s.has_hook(hook)) {
+            let mut this = Dynamic::from(message.clone());
+            let options = CallFnOptions::new().bind_this_ptr(&mut this);
+            let result = self.engine.call_fn_with_options::<Dynamic>(
+                options,
+                &mut Scope::new(),
+                &script.ast,
+                hook,
+                (),
+            );
+            match result {
+                Ok(_) => match this.try_cast::<Message>() {
+                    Some(m) => *message = m,
+                    None => errors.push(format!("{} replaced this in {}", script.name(), hook)),
+                },
+                Err(e) => match exceeded_limit(&e) {
+                    Some(limit) => errors.push(format!(
+                        "{} was stopped in {} after {}",
+                        script.name(),
+                        hook,
+                        limit
+                    )),
+                    None => errors.push(format!("{} failed in {}. {}", script.name(), hook, e)),
+                },
+            }
+        }
+        errors
+    }
+
+    /// Recompiles the scripts which changed, keeping the previous version of those which no
+    /// longer compile.
+    fn reload(&self) {
+        let files = match script_files(&self.dir) {
+            Ok(f) => f,
+            Err(e) => {
+                warn!("Unable to read scripts directory. {}", e);
+                return;
+            }
+        };
+        let changed = {
+            let scripts = self.scripts.read().unwrap();
+            files.len() != scripts.len()
+                || files
+                    .iter()
+                    .zip(scripts.iter())
+                    .any(|((path, modified), s)| *path != s.path || *modified != s.modified)
+        };
+        if !changed {
+            return;
+        }
+
+        let mut scripts = self.scripts.write().unwrap();
+        let mut previous: HashMap<PathBuf, Script> =
+            scripts.drain(..).map(|s| (s.path.clone(), s)).collect();
+        for (path, modified) in files {
+            let old = previous.remove(&path);
+            match old {
+                Some(s) if s.modified == modified => scripts.push(s),
+                old => match compile(&self.engine, &path, modified) {
+                    Ok(s) => scripts.push(s),
+                    Err(e) => {
+                        warn!("{}", e);
+                        scripts.extend(old);
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Reloads the scripts on a blocking thread, as waiting for running hooks to release the
+/// scripts would hold up a worker thread of the proxy.
+async fn watch(scripts: Arc<Scripts>) {
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+    loop {
+        interval.tick().await;
+        let scripts = Arc::clone(&scripts);
+        tokio::task::spawn_blocking(move || scripts.reload())
+            .await
+            .ok();
+    }
+}
+
+/// Lists the `.rhai` files of a directory with their modification times, sorted by name.
+fn script_files(dir: &Path) -> std::io::Result<Vec<(PathBuf, SystemTime)>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "rhai") {
+            let modified = std::fs::metadata(&path)?.modified()?;
+            files.push((path, modified));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn compile(engine: &Engine, path: &Path, modified: SystemTime) -> Result<Script, String> {
+    let ast = engine
+        .compile_file(path.to_path_buf())
+        .map_err(|e| format!("Unable to compile script '{}'. {}", path.display(), e))?;
+    Ok(Script {
+        path: path.to_path_buf(),
+        modified,
+        ast,
+    })
+}
+
+/// Creates the script engine with the `Message` type and the helper functions.
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    // Printing would garble the terminal UI.
+    engine.on_print(|_| {});
+    engine.on_debug(|_, _, _| {});
+
+    engine
+        .register_type_with_name::<Message>("Message")
+        .register_get_set(
+            "method",
+            |m: &mut Message| m.method.clone(),
+            |m: &mut Message, v: String| m.method = v,
+        )
+        .register_get_set(
+            "uri",
+            |m: &mut Message| m.uri.clone(),
+            |m: &mut Message, v: String| m.uri = v,
+        )
+        .register_get_set(
+            "status",
+            |m: &mut Message| m.status,
+            |m: &mut Message, v: i64| m.status = v,
+        )
+        .register_get_set(
+            "body",
+            |m: &mut Message| String::from_utf8_lossy(&m.body).to_string(),
+            |m: &mut Message, v: String| m.body = v.into_bytes(),
+        )
+        .register_get_set(
+            "body_bytes",
+            |m: &mut Message| m.body.clone(),
+            |m: &mut Message, v: Blob| m.body = v,
+        )
+        .register_get("headers", |m: &mut Message| {
+            m.headers
+                .iter()
+                .map(|(name, value)| {
+                    let pair: rhai::Array = vec![name.clone().into(), value.clone().into()];
+                    Dynamic::from_array(pair)
+                })
+                .collect::<rhai::Array>()
+        })
+        .register_fn("header", Message::header)
+        .register_fn("set_header", Message::set_header)
+        .register_fn("add_header", Message::add_header)
+        .register_fn("remove_header", Message::remove_header);
+
+    engine
+        .register_fn("hmac_sha256", |k: Dynamic, d: Dynamic| {
+            sign(hmac::HMAC_SHA256, k, d)
+        })
+        .register_fn("hmac_sha384", |k: Dynamic, d: Dynamic| {
+            sign(hmac::HMAC_SHA384, k, d)
+        })
+        .register_fn("hmac_sha512", |k: Dynamic, d: Dynamic| {
+            sign(hmac::HMAC_SHA512, k, d)
+        })
+        .register_fn("sha1", |d: Dynamic| {
+            hash(&digest::SHA1_FOR_LEGACY_USE_ONLY, d)
+        })
+        .register_fn("sha256", |d: Dynamic| hash(&digest::SHA256, d))
+        .register_fn("sha384", |d: Dynamic| hash(&digest::SHA384, d))
+        .register_fn("sha512", |d: Dynamic| hash(&digest::SHA512, d))
+        .register_fn("aes_gcm_encrypt", aes_gcm_encrypt)
+        .register_fn("aes_gcm_decrypt", aes_gcm_decrypt)
+        .register_fn("base64_encode", |d: Dynamic| -> ScriptResult<String> {
+            Ok(base64::encode(bytes(d)?))
+        })
+        .register_fn("base64_decode", |s: &str| -> ScriptResult<Blob> {
+            base64::decode(s.trim()).map_err(|e| format!("Invalid Base64. {}", e).into())
+        })
+        .register_fn("hex_encode", |d: Dynamic| -> ScriptResult<String> {
+            Ok(hex(&bytes(d)?))
+        })
+        .register_fn("hex_decode", hex_decode)
+        .register_fn("utf8", |b: Blob| String::from_utf8_lossy(&b).to_string());
+
+    engine
+}
+
+/// Describes the limit a script exceeded, if the error is one of the engine's limits.
+fn exceeded_limit(error: &EvalAltResult) -> Option<String> {
+    match error {
+        EvalAltResult::ErrorTooManyOperations(_) => Some(format!("{} operations", MAX_OPERATIONS)),
+        EvalAltResult::ErrorStackOverflow(_) => Some(format!("{} nested calls", MAX_CALL_LEVELS)),
+        EvalAltResult::ErrorInFunctionCall(_, _, inner, _) => exceeded_limit(inner),
+        _ => None,
+    }
+}
+
+/// Takes the bytes of a string or a blob.
+fn bytes(value: Dynamic) -> ScriptResult<Vec<u8>> {
+    if value.is_blob() {
+        return Ok(value.cast::<Blob>());
+    }
+    match value.into_string() {
+        Ok(s) => Ok(s.into_bytes()),
+        Err(t) => Err(format!("Expected a string or a blob, got {}", t).into()),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(s: &str) -> ScriptResult<Blob> {
+    let s = s.trim();
+    if !s.len().is_multiple_of(2) {
+        return Err("Hex input has an odd number of digits".into());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| format!("Invalid hex digits in '{}'", s).into())
+        })
+        .collect()
+}
+
+/// Returns the hex encoded HMAC of the data.
+fn sign(algorithm: hmac::Algorithm, key: Dynamic, data: Dynamic) -> ScriptResult<String> {
+    let key = hmac::Key::new(algorithm, &bytes(key)?);
+    Ok(hex(hmac::sign(&key, &bytes(data)?).as_ref()))
+}
+
+/// Returns the hex encoded digest of the data.
+fn hash(algorithm: &'static digest::Algorithm, data: Dynamic) -> ScriptResult<String> {
+    Ok(hex(digest::digest(algorithm, &bytes(data)?).as_ref()))
+}
+
+/// Creates an AES-GCM key from 16 or 32 bytes, and a nonce from 12 bytes.
+fn aes_gcm(key: Dynamic, nonce: Dynamic) -> ScriptResult<(aead::LessSafeKey, aead::Nonce)> {
+    let key = bytes(key)?;
+    let algorithm = match key.len() {
+        16 => &aead::AES_128_GCM,
+        32 => &aead::AES_256_GCM,
+        n => return Err(format!("AES-GCM keys have 16 or 32 bytes, not {}", n).into()),
+    };
+    let key = aead::UnboundKey::new(algorithm, &key).map_err(|_| "Invalid AES-GCM key")?;
+    let nonce = aead::Nonce::try_assume_unique_for_key(&bytes(nonce)?)
+        .map_err(|_| "AES-GCM nonces have 12 bytes")?;
+    Ok((aead::LessSafeKey::new(key), nonce))
+}
+
+/// Encrypts the data, returning the ciphertext followed by the tag.
+fn aes_gcm_encrypt(key: Dynamic, nonce: Dynamic, data: Dynamic) -> ScriptResult<Blob> {
+    let (key, nonce) = aes_gcm(key, nonce)?;
+    let mut data = bytes(data)?;
+    key.seal_in_place_append_tag(nonce, aead::Aad::empty(), &mut data)
+        .map_err(|_| "Failed to encrypt")?;
+    Ok(data)
+}
+
+/// Decrypts a ciphertext followed by its tag.
+fn aes_gcm_decrypt(key: Dynamic, nonce: Dynamic, data: Dynamic) -> ScriptResult<Blob> {
+    let (key, nonce) = aes_gcm(key, nonce)?;
+    let mut data = bytes(data)?;
+    let len = key
+        .open_in_place(nonce, aead::Aad::empty(), &mut data)
+        .map_err(|_| "Failed to decrypt, the key, nonce or tag is wrong")?
+        .len();
+    data.truncate(len);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blob(value: &[u8]) -> Dynamic {
+        Dynamic::from_blob(value.to_vec())
+    }
+
+    /// Loads a script from a temporary directory, which is returned to keep it.
+    fn load(source: &str) -> (tempfile::TempDir, Arc<Scripts>) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.rhai"), source).unwrap();
+        let scripts = Scripts::load(dir.path().to_str().unwrap());
+        (dir, scripts)
+    }
+
+    fn message() -> Message {
+        Message {
+            method: "GET".to_string(),
+            uri: "http://a.test/".to_string(),
+            status: 0,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    #[test]
+    fn hex_decode_checks_digits() {
+        assert_eq!(hex_decode(" 00ff10 ").unwrap(), [0x00, 0xff, 0x10]);
+        assert!(hex_decode("abc").is_err());
+        assert!(hex_decode("zz").is_err());
+        assert_eq!(hex(&[0x00, 0xff]), "00ff");
+    }
+
+    #[test]
+    fn aes_gcm_round_trips() {
+        let key = blob(&[7; 16]);
+        let nonce = blob(&[1; 12]);
+        let sealed = aes_gcm_encrypt(key.clone(), nonce.clone(), Dynamic::from("secret")).unwrap();
+        assert_eq!(sealed.len(), 6 + 16);
+        let opened = aes_gcm_decrypt(key.clone(), nonce.clone(), blob(&sealed)).unwrap();
+        assert_eq!(opened, b"secret");
+
+        let mut tampered = sealed;
+        tampered[0] ^= 1;
+        assert!(aes_gcm_decrypt(key, nonce.clone(), blob(&tampered)).is_err());
+        assert!(aes_gcm_encrypt(blob(&[0; 10]), nonce, blob(b"")).is_err());
+        assert!(aes_gcm_encrypt(blob(&[0; 32]), blob(&[0; 8]), blob(b"")).is_err());
+    }
+
+    #[test]
+    fn sign_and_hash_are_hex() {
+        let hmac = sign(hmac::HMAC_SHA256, Dynamic::from("key"), Dynamic::from("a")).unwrap();
+        assert_eq!(hmac.len(), 64);
+        assert_eq!(
+            hash(&digest::SHA256, Dynamic::from("abc")).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(bytes(Dynamic::from(1_i64)).is_err());
+    }
+
+    #[tokio::test]
+    async fn hooks_change_the_message() {
+        let (_dir, scripts) = load(
+            r#"
+            fn on_request() {
+                this.set_header("X-Sig", sha256(this.body));
+                this.body = this.body + "!";
+            }
+            "#,
+        );
+        assert!(scripts.has_hook("on_request"));
+        assert!(!scripts.has_hook("on_response"));
+
+        let mut message = message();
+        message.body = b"a".to_vec();
+        assert!(scripts.run("on_request", &mut message).is_empty());
+        assert_eq!(message.body, b"a!");
+        assert_eq!(message.header("x-sig").len(), 64);
+    }
+
+    #[tokio::test]
+    async fn hooks_are_stopped_at_the_limits() {
+        let (_dir, scripts) = load(
+            r#"
+            fn on_request() { loop {} }
+            fn deep(n) { deep(n + 1) }
+            fn on_response() { deep(0) }
+            "#,
+        );
+        let mut message = message();
+        let errors = scripts.run("on_request", &mut message);
+        assert_eq!(
+            errors,
+            ["a.rhai was stopped in on_request after 10000000 operations"]
+        );
+        let errors = scripts.run("on_response", &mut message);
+        assert_eq!(
+            errors,
+            ["a.rhai was stopped in on_response after 64 nested calls"]
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use serde::Deserialize;
+use std::{collections::HashMap, num::NonZeroU64};
+
+/// Path of the settings file read on startup.
+pub const SETTINGS_PATH: &str = "rudy.toml";
+
+/// User configuration for the proxy.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Port the proxy listens on.
+    pub listen_port: u16,
+    /// Path to the CA certificate used to sign certificates for intercepted hosts.
+    pub ca_cert: String,
+    /// Path to the private key of the CA.
+    pub ca_key: String,
+    /// Host patterns which are in scope. All hosts pass the scope filter if this is empty, but
+    /// the active scanner only runs with a scope.
+    pub scope: Vec<String>,
+    /// Path of the project file the History is saved to. The History is not saved if this is not
+    /// set.
+    pub project: Option<String>,
+    /// Columns of the History table, in display order.
+    pub history_columns: Vec<ColumnSettings>,
+    /// Settings for keeping the History within a memory budget.
+    pub storage: StorageSettings,
+    /// Key bindings of the terminal UI.
+    pub keymap: KeymapSettings,
+    /// Name of a built-in theme (`dark`, `light` or `high-contrast`) or path to a theme file.
+    pub theme: String,
+    /// Settings of the Compare tab.
+    pub compare: CompareSettings,
+    /// Directory of Rhai scripts whose `on_request` and `on_response` hooks change the exchanges
+    /// passing through the proxy. No scripts are run if this is not set.
+    pub scripts_dir: Option<String>,
+    /// Settings of the active scanner.
+    pub active_scan: ActiveScanSettings,
+    /// Settings for the certificates issued to clients for intercepted hosts.
+    pub leaf_certs: LeafCertSettings,
+    /// Path to a PEM bundle of extra CA certificates trusted for upstream connections, in
+    /// addition to the system roots.
+    pub upstream_ca_bundle: Option<String>,
+    /// Per-host settings for upstream connections.
+    pub hosts: Vec<HostSettings>,
+    /// Host patterns whose CONNECT tunnels are passed through without decrypting them.
+    pub passthrough: Vec<String>,
+    /// Number of failed client TLS handshakes after which a host is added to the passthrough
+    /// list. Hosts are never added automatically if this is not set.
+    pub auto_passthrough: Option<u32>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            listen_port: 8080,
+            ca_cert: "cert/ca.crt".to_string(),
+            ca_key: "cert/ca.key".to_string(),
+            scope: vec![],
+            project: None,
+            history_columns: vec![
+                ColumnSettings::new(Column::Id),
+                ColumnSettings::new(Column::Host),
+                ColumnSettings::new(Column::Method),
+                ColumnSettings::new(Column::Url),
+                ColumnSettings::new(Column::Status),
+            ],
+            storage: StorageSettings::default(),
+            keymap: KeymapSettings::default(),
+            theme: "dark".to_string(),
+            compare: CompareSettings::default(),
+            scripts_dir: None,
+            active_scan: ActiveScanSettings::default(),
+            leaf_certs: LeafCertSettings::default(),
+            upstream_ca_bundle: None,
+            hosts: vec![],
+            passthrough: vec![],
+            auto_passthrough: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the settings from a TOML file. Defaults are used if the file does not exist.
+    pub fn load(path: &str) -> Result<Settings, String> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Settings::default()),
+            Err(e) => return Err(format!("Unable to load settings file at '{}'. {}", path, e)),
+        };
+
+        toml::from_str(&contents).map_err(|e| format!("Invalid settings file at '{}'. {}", path, e))
+    }
+}
+
+/// A column of the History table.
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Column {
+    Id,
+    Host,
+    Method,
+    Url,
+    Status,
+    Length,
+    Duration,
+    Time,
+    Mime,
+    Listener,
+    Comment,
+    Tags,
+}
+
+/// A column of the History table and its width.
+#[derive(Clone, Deserialize)]
+pub struct ColumnSettings {
+    pub column: Column,
+    /// Width of the column in characters. Each column has a default width if this is not set.
+    pub width: Option<u16>,
+}
+
+impl ColumnSettings {
+    fn new(column: Column) -> Self {
+        ColumnSettings {
+            column,
+            width: None,
+        }
+    }
+}
+
+/// Settings for keeping the History within a memory budget.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct StorageSettings {
+    /// Approximate amount of memory the History may use, in MiB. The oldest entries are moved to
+    /// disk once it is exceeded.
+    pub memory_budget_mb: u64,
+    /// Size in KiB above which request and response bodies are moved to disk right away.
+    pub spill_body_kb: u64,
+    /// Directory of the storage file. A directory in the system's temporary directory is used if
+    /// this is not set.
+    pub dir: Option<String>,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            memory_budget_mb: 512,
+            spill_body_kb: 256,
+            dir: None,
+        }
+    }
+}
+
+/// Key bindings of the terminal UI: a preset and bindings which replace the keys of single actions.
+/// Keys are written like `q`, `ctrl-c`, `alt-x`, `enter` or `pagedown`.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct KeymapSettings {
+    pub preset: KeymapPreset,
+    /// Keys of actions used while navigating, by the name of the [`Action`].
+    pub navigation: HashMap<String, Vec<String>>,
+    /// Keys of actions used while typing into the filter bar, by the name of the [`EditAction`].
+    pub input: HashMap<String, Vec<String>>,
+}
+
+/// Set of key bindings the configured bindings are applied to.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeymapPreset {
+    #[default]
+    Default,
+    Vim,
+    Emacs,
+}
+
+/// An action bound to a key while navigating.
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quit,
+    Help,
+    Close,
+    NextTab,
+    PreviousTab,
+    InterceptTab,
+    HistoryTab,
+    MapTab,
+    SettingsTab,
+    CompareTab,
+    DecoderTab,
+    TokensTab,
+    IssuesTab,
+    Down,
+    Up,
+    First,
+    Last,
+    Toggle,
+    Select,
+    Search,
+    Filter,
+    SortColumn,
+    ReverseSort,
+    Highlight,
+    Comment,
+    Tags,
+    Save,
+    Export,
+    NextDetailTab,
+    PreviousDetailTab,
+    ScrollDown,
+    ScrollUp,
+    ScrollRight,
+    ScrollLeft,
+    PageDown,
+    PageUp,
+    ToggleWrap,
+    FindInDetail,
+    NextMatch,
+    CopyAs,
+    Import,
+    Send,
+    Compare,
+    SendToDecoder,
+    Edit,
+    RemoveStep,
+    ActiveScan,
+    PauseScan,
+}
+
+/// An action bound to a key while typing text. Other keys are typed.
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EditAction {
+    Submit,
+    Cancel,
+    DeleteChar,
+    DeleteWord,
+    Clear,
+}
+
+/// Settings of the Compare tab.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct CompareSettings {
+    /// Headers which differ between otherwise equal exchanges, such as `Date`. They are left out
+    /// of the compared requests and responses.
+    pub ignore_headers: Vec<String>,
+}
+
+impl Default for CompareSettings {
+    fn default() -> Self {
+        CompareSettings {
+            ignore_headers: [
+                "date",
+                "expires",
+                "age",
+                "last-modified",
+                "etag",
+                "set-cookie",
+                "x-request-id",
+            ]
+            .iter()
+            .map(|h| h.to_string())
+            .collect(),
+        }
+    }
+}
+
+/// Settings of the active scanner, which sends probes based on selected requests.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ActiveScanSettings {
+    /// Maximum number of probes sent per second.
+    pub requests_per_second: u32,
+    /// Maximum number of probes waiting for a response at the same time.
+    pub concurrency: usize,
+    /// Time after which a probe is given up, in seconds.
+    pub timeout_secs: u64,
+    /// Delay the time based SQL injection probes ask the database for, in seconds.
+    pub sleep_secs: u64,
+    /// Address of the listener for server-side request forgery callbacks. It must be reachable
+    /// from the scanned servers.
+    pub callback_host: String,
+    /// Port of the callback listener. A free port is used if this is 0.
+    pub callback_port: u16,
+}
+
+impl Default for ActiveScanSettings {
+    fn default() -> Self {
+        ActiveScanSettings {
+            requests_per_second: 10,
+            concurrency: 4,
+            timeout_secs: 30,
+            sleep_secs: 5,
+            callback_host: "127.0.0.1".to_string(),
+            callback_port: 0,
+        }
+    }
+}
+
+/// Settings for the leaf certificates the proxy issues for intercepted hosts.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct LeafCertSettings {
+    /// Maximum number of certificates kept in memory.
+    pub cache_size: u64,
+    /// Directory in which issued certificates are persisted. Certificates are only kept in memory
+    /// if this is not set.
+    pub cache_dir: Option<String>,
+    /// Number of days issued certificates are valid for.
+    pub validity_days: NonZeroU64,
+    /// Type of key used for issued certificates.
+    pub key_type: KeyType,
+    /// Subject alternative names added to every issued certificate.
+    pub extra_sans: Vec<String>,
+}
+
+impl Default for LeafCertSettings {
+    fn default() -> Self {
+        LeafCertSettings {
+            cache_size: 1_000,
+            cache_dir: None,
+            validity_days: NonZeroU64::new(365).unwrap(),
+            key_type: KeyType::Ca,
+            extra_sans: vec![],
+        }
+    }
+}
+
+/// Key type of issued leaf certificates.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyType {
+    /// Use the CA's own key for every certificate.
+    Ca,
+    /// Generate a new P-256 ECDSA key for every certificate.
+    Ecdsa,
+    /// Generate a new 2048-bit RSA key for every certificate, for clients without ECDSA support.
+    Rsa,
+}
+
+/// Settings applied to upstream connections to a single host.
+#[derive(Deserialize)]
+pub struct HostSettings {
+    /// Host name the settings apply to.
+    pub host: String,
+    /// Client certificate presented to the host.
+    pub client_cert: Option<ClientCert>,
+    /// Whether the certificate of the host is verified. Only disable this for hosts with
+    /// self-signed certificates.
+    #[serde(default = "default_true")]
+    pub verify: bool,
+    /// Whether HTTP/2 may be used for the host. If disabled, connections to the host are
+    /// downgraded to HTTP/1.1 on both the client and the upstream side.
+    #[serde(default = "default_true")]
+    pub http2: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A client certificate and its private key.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ClientCert {
+    /// PEM encoded certificate chain and private key.
+    Pem { cert: String, key: String },
+    /// PKCS#12 archive containing the certificate chain and private key.
+    Pkcs12 {
+        pkcs12: String,
+        #[serde(default)]
+        password: String,
+    },
+}
+
+/// Checks if a host matches a pattern. Patterns are either a host name or a wildcard such as
+/// `*.example.com`, which matches all subdomains of `example.com`.
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let host = host.to_lowercase();
+
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{}", domain)),
+        None => pattern == host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaf_certificates_must_be_valid_for_a_day() {
+        let settings: Settings = toml::from_str("[leaf_certs]\nkey_type = \"rsa\"").unwrap();
+        assert_eq!(settings.leaf_certs.validity_days.get(), 365);
+        assert!(matches!(settings.leaf_certs.key_type, KeyType::Rsa));
+
+        let error = toml::from_str::<Settings>("[leaf_certs]\nvalidity_days = 0")
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("nonzero"), "{}", error);
+    }
+
+    #[test]
+    fn invalid_settings_files_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rudy.toml");
+        let path = path.to_str().unwrap();
+        assert!(Settings::load(path).is_ok());
+
+        std::fs::write(path, "listen_port = \"none\"").unwrap();
+        let error = Settings::load(path).err().unwrap();
+        assert!(error.starts_with("Invalid settings file at"), "{}", error);
+    }
+}
@@ -0,0 +1,404 @@
+use crate::{proxy, rudy_tui::Req, settings::Settings};
+use hudsucker::{
+    hyper::{
+        body::to_bytes,
+        client::{
+            connect::{Connected, Connection},
+            HttpConnector,
+        },
+        service::Service,
+        Body, Client, Request, Uri,
+    },
+    rustls::{
+        self,
+        client::{ServerCertVerified, ServerCertVerifier},
+        Certificate, ClientConfig, ClientConnection, OwnedTrustAnchor, RootCertStore, ServerName,
+    },
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder, MaybeHttpsStream};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    future::Future,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+use time::OffsetDateTime;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+    time::timeout,
+};
+use tracing::warn;
+use x509_parser::{extensions::GeneralName, parse_x509_certificate};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Connector used by the proxy to reach upstream servers.
+///
+/// Hosts which have their own TLS configuration in the settings get a dedicated connector. All
+/// other hosts share the default one.
+#[derive(Clone)]
+pub struct UpstreamConnector {
+    default: HttpsConnector<HttpConnector>,
+    hosts: Arc<HashMap<String, HttpsConnector<HttpConnector>>>,
+}
+
+impl UpstreamConnector {
+    pub fn new(settings: &Settings) -> Result<Self, String> {
+        let roots = root_store(settings)?;
+
+        let hosts = settings
+            .hosts
+            .iter()
+            .map(|h| {
+                let builder = ClientConfig::builder()
+                    .with_safe_defaults()
+                    .with_root_certificates(roots.clone());
+
+                let mut config = match &h.client_cert {
+                    Some(client_cert) => {
+                        let (certs, key) = proxy::load_client_cert(client_cert)?;
+                        builder.with_single_cert(certs, key).map_err(|e| {
+                            format!("Unable to use the client certificate for {}. {}", h.host, e)
+                        })?
+                    }
+                    None => builder.with_no_client_auth(),
+                };
+
+                if !h.verify {
+                    config
+                        .dangerous()
+                        .set_certificate_verifier(Arc::new(NoVerification));
+                }
+
+                Ok((h.host.to_lowercase(), https_connector(config, h.http2)))
+            })
+            .collect::<Result<_, String>>()?;
+
+        let default = https_connector(
+            ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+            true,
+        );
+
+        Ok(UpstreamConnector {
+            default,
+            hosts: Arc::new(hosts),
+        })
+    }
+
+    fn connector_for(&self, uri: &Uri) -> HttpsConnector<HttpConnector> {
+        uri.host()
+            .and_then(|host| self.hosts.get(&host.to_lowercase()))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
+
+impl Service<Uri> for UpstreamConnector {
+    type Response = UpstreamStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let connecting = self.connector_for(&dst).call(dst.clone());
+
+        Box::pin(async move {
+            let stream = connecting.await?;
+            let tls = match &stream {
+                MaybeHttpsStream::Https(s) => {
+                    Some(TlsInfo::new(dst.host().unwrap_or_default(), s.get_ref().1))
+                }
+                MaybeHttpsStream::Http(_) => None,
+            };
+            Ok(UpstreamStream { inner: stream, tls })
+        })
+    }
+}
+
+/// Upstream connection which carries the details of its TLS session.
+///
+/// The details are attached to every response received over the connection and can be read from
+/// its extensions as a [`TlsInfo`].
+pub struct UpstreamStream {
+    inner: MaybeHttpsStream<TcpStream>,
+    tls: Option<TlsInfo>,
+}
+
+impl Connection for UpstreamStream {
+    fn connected(&self) -> Connected {
+        let connected = self.inner.connected();
+        match &self.tls {
+            Some(tls) => connected.extra(tls.clone()),
+            None => connected,
+        }
+    }
+}
+
+impl AsyncRead for UpstreamStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UpstreamStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Details of the TLS session negotiated with an upstream server.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct TlsInfo {
+    pub version: String,
+    pub cipher_suite: String,
+    pub alpn: Option<String>,
+    pub sni: String,
+    pub certificates: Vec<CertInfo>,
+}
+
+impl TlsInfo {
+    fn new(sni: &str, conn: &ClientConnection) -> Self {
+        TlsInfo {
+            version: conn
+                .protocol_version()
+                .map(|v| format!("{:?}", v))
+                .unwrap_or_default(),
+            cipher_suite: conn
+                .negotiated_cipher_suite()
+                .map(|s| format!("{:?}", s.suite()))
+                .unwrap_or_default(),
+            alpn: conn
+                .alpn_protocol()
+                .map(|p| String::from_utf8_lossy(p).to_string()),
+            sni: sni.to_string(),
+            certificates: conn
+                .peer_certificates()
+                .unwrap_or_default()
+                .iter()
+                .map(CertInfo::new)
+                .collect(),
+        }
+    }
+}
+
+/// Summary of a certificate presented by an upstream server.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct CertInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: String,
+    pub not_after: String,
+    pub sans: Vec<String>,
+    pub fingerprint: String,
+}
+
+impl CertInfo {
+    fn new(cert: &Certificate) -> Self {
+        let fingerprint = ring::digest::digest(&ring::digest::SHA256, &cert.0)
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        let mut info = CertInfo {
+            subject: String::new(),
+            issuer: String::new(),
+            not_before: String::new(),
+            not_after: String::new(),
+            sans: vec![],
+            fingerprint,
+        };
+
+        let cert = match parse_x509_certificate(&cert.0) {
+            Ok((_, cert)) => cert,
+            Err(e) => {
+                info.subject = format!("Unable to parse certificate. {}", e);
+                return info;
+            }
+        };
+
+        info.subject = cert.subject().to_string();
+        info.issuer = cert.issuer().to_string();
+        info.not_before = cert.validity().not_before.to_rfc2822();
+        info.not_after = cert.validity().not_after.to_rfc2822();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            info.sans = san
+                .value
+                .general_names
+                .iter()
+                .map(|name| match name {
+                    GeneralName::DNSName(dns) => dns.to_string(),
+                    GeneralName::IPAddress(&[a, b, c, d]) => IpAddr::from([a, b, c, d]).to_string(),
+                    GeneralName::IPAddress(ip) => match <[u8; 16]>::try_from(*ip) {
+                        Ok(ip) => IpAddr::from(ip).to_string(),
+                        Err(_) => name.to_string(),
+                    },
+                    _ => name.to_string(),
+                })
+                .collect();
+        }
+
+        info
+    }
+}
+
+/// Creates the HTTP client used for upstream requests.
+pub fn client(settings: &Settings) -> Result<Client<UpstreamConnector>, String> {
+    Ok(Client::builder()
+        .http1_title_case_headers(true)
+        .http1_preserve_header_case(true)
+        .build(UpstreamConnector::new(settings)?))
+}
+
+/// Sends a request with the client, returning the exchange. The request is sent with its headers
+/// as they are.
+pub async fn send(client: &Client<UpstreamConnector>, req: &Req, time_limit: Duration) -> Req {
+    let mut entry = req.clone();
+    entry.timestamp = Some(OffsetDateTime::now_utc());
+    let started = Instant::now();
+
+    let request = async {
+        let mut builder = Request::builder()
+            .method(req.method.as_str())
+            .uri(req.url.as_str());
+        for (name, value) in &req.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        let res = client
+            .request(builder.body(Body::from(req.body.clone()))?)
+            .await?;
+        let (parts, body) = res.into_parts();
+        let body = to_bytes(body).await?;
+        Ok::<_, BoxError>((parts, body))
+    };
+
+    match timeout(time_limit, request).await {
+        Ok(Ok((parts, body))) => {
+            entry.status = Some(parts.status.as_u16());
+            entry.upstream_version = Some(parts.version);
+            entry.res_headers = proxy::header_list(&parts.headers);
+            entry.res_body = body.to_vec();
+        }
+        Ok(Err(e)) => entry.error = Some(format!("Failed to send request. {}", error_chain(&*e))),
+        Err(_) => entry.error = Some("Request timed out".to_string()),
+    }
+    entry.duration = Some(started.elapsed());
+    entry
+}
+
+fn https_connector(config: ClientConfig, http2: bool) -> HttpsConnector<HttpConnector> {
+    let builder = HttpsConnectorBuilder::new()
+        .with_tls_config(config)
+        .https_or_http()
+        .enable_http1();
+
+    if http2 {
+        builder.enable_http2().build()
+    } else {
+        builder.build()
+    }
+}
+
+/// Builds the trust roots for upstream connections from the system store and the optional CA
+/// bundle. The bundled Mozilla roots are used if the system store can't be read.
+fn root_store(settings: &Settings) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+
+    match rustls_native_certs::load_native_certs() {
+        Ok(certs) => {
+            let certs: Vec<Vec<u8>> = certs.into_iter().map(|c| c.0).collect();
+            roots.add_parsable_certificates(&certs);
+        }
+        Err(e) => warn!("Unable to load system root certificates. {}", e),
+    }
+
+    if roots.is_empty() {
+        roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+    }
+
+    if let Some(path) = &settings.upstream_ca_bundle {
+        for cert in proxy::load_ca_bundle(path)? {
+            roots.add(&cert).map_err(|e| {
+                format!("Invalid certificate in the CA bundle at '{}'. {}", path, e)
+            })?;
+        }
+    }
+
+    Ok(roots)
+}
+
+/// Joins an error and all of its sources into a single message.
+pub fn error_chain(e: &(dyn Error + 'static)) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(s) = source {
+        message += &format!(": {}", s);
+        source = s.source();
+    }
+    message
+}
+
+/// Certificate verifier which accepts any server certificate.
+pub struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}